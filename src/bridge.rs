@@ -2,22 +2,16 @@ use crate::GameBoard;
 use crate::Direction;
 
 pub struct Bridge {
-    if_bin_direction: bool, // 是否是双向通道，若是，则下方忽略
-    direction: Direction,
+    if_bin_direction: bool, // 是否是双向通道，若是，则两侧都能沿桥梁所在的轴发送，direction 只决定轴
+    direction: Direction, // 瓷砖过桥的方向：向右、向下时左侧（上方）棋盘送给右侧（下方），向左、向上时反之
     if_open: bool, // 当前通道的开启状态
-    left_index: usize, // 左侧棋盘的index
-    right_index: usize, // 右侧棋盘的index
+    left_index: usize, // 左侧（竖直桥梁为上方）棋盘上桥梁所在的行（竖直桥梁为列）
+    right_index: usize, // 右侧（竖直桥梁为下方）棋盘上桥梁所在的行（竖直桥梁为列）
     sending_limit: usize, // 该通道最多能发送的数量
 }
 
 impl Bridge {
     pub fn new(if_bin_direction: bool, direction: Direction, if_open: bool, left_index: usize, right_index: usize, sending_limit: usize) -> Self {
-        if if_bin_direction {
-            println!("Warning: if_bin_direction should be set to false, because only has one tunnel");
-        }
-        if direction == Direction::Left {
-            println!("Warning: direction should be set to right, because only has one tunnel");
-        }
         Self {
             if_bin_direction: if_bin_direction,
             direction: direction,
@@ -37,50 +31,65 @@ impl Bridge {
         self.sending_limit = sending_limit;
     }
 
-    pub fn send_through_bridge(&mut self, recei_board: &mut GameBoard, send_board: &mut GameBoard, direction: Direction, if_player2: bool) -> Option<Vec<u32>> {
-        if !self.if_legal(recei_board, send_board, direction, if_player2) {
+    pub fn is_open(&self) -> bool {
+        self.if_open
+    }
+
+    pub fn is_two_way(&self) -> bool {
+        self.if_bin_direction
+    }
+
+    // 竖直桥梁连接上下两块棋盘的列
+    pub fn is_vertical(&self) -> bool {
+        is_vertical(self.direction)
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    // 桥梁所在的行（竖直桥梁为列），两侧棋盘使用同一行时就是左侧棋盘的行
+    pub fn line(&self) -> usize {
+        self.left_index
+    }
+
+    // 还能送出的瓷砖数
+    pub fn sending_limit(&self) -> usize {
+        self.sending_limit
+    }
+
+    // 把发送方靠桥一端的瓷砖沿 direction 送给接收方，左右时经过两块棋盘的行，上下时经过列
+    // 返回过桥的瓷砖，按屏幕上从左到右（竖直桥梁为从上到下）的顺序排列，供动画使用
+    pub fn send_through_bridge(&mut self, recei_board: &mut GameBoard, send_board: &mut GameBoard, direction: Direction) -> Option<Vec<u32>> {
+        if !self.if_legal(send_board, direction) {
             return None;
         }
-    
-
-        let length_of_line = recei_board.get_tiles().len();
+        let (recei_index, send_index) = self.indices(direction);
         // 合法，那么开始处理移动
         // 发送方会将自己的方块也发送过去，直到对方该行已满
         // 如 0 2 0 0 ----- 2 2 2 0 会变成 0 2 2 2 ----- 2 0 0 0
-        
-        // 使用abstract functionm，这一块从右向左也能复用，上到下也能
-        // 提供动画数组
+        // 四个方向都先转成向左的情形，交给 send_abstract 处理后再写回
+        let mut receiver_line = read_line(recei_board, direction, recei_index);
+        let mut sender_line = read_line(send_board, direction, send_index);
+        let mut animated_vector = self.send_abstract(&mut receiver_line, &mut sender_line);
+        write_line(recei_board, direction, recei_index, receiver_line);
+        write_line(send_board, direction, send_index, sender_line);
+        // 向右、向下时先过桥的瓷砖在屏幕上排在后面
+        if matches!(direction, Direction::Right | Direction::Down) {
+            animated_vector.reverse();
+        }
+        Some(animated_vector)
+    }
+
+    // 沿 direction 发送时 (接收方, 发送方) 使用的行（列）：向左、向上时接收方在左侧（上方）
+    fn indices(&self, direction: Direction) -> (usize, usize) {
         match direction {
-            Direction::Left => {
-                // 向左不需要做任何特殊适配
-                return Some(self.send_abstract(&mut recei_board.get_tiles_mut()[self.left_index], &mut send_board.get_tiles_mut()[self.right_index]));
-            }
-            Direction::Right => {
-                // 向右实际上是反向向左
-                // 0200 => 0002 = 0000 0022     equals to    2000 <= 0020 = 2200  reverse direction to 0022 
-                // 反转对应节，最后两个答案也得反转重新写入
-                let mut line_receiver_reversed: Vec<u32> = recei_board.get_tiles()[self.left_index].iter().rev().cloned().collect();
-                let mut line_sender_reversed: Vec<u32> = send_board.get_tiles()[self.right_index].iter().rev().cloned().collect();
-                let mut animated_vector = self.send_abstract(&mut line_receiver_reversed, &mut line_sender_reversed);
-                // 重新写入
-                for i in 0..length_of_line {
-                    recei_board.get_tiles_mut()[self.left_index][i] = line_receiver_reversed[length_of_line - 1 - i];
-                }
-                for i in 0..length_of_line {
-                    send_board.get_tiles_mut()[self.right_index][i] = line_sender_reversed[length_of_line - 1 - i];
-                }
-                // 如果是player1，那么需要反转动画矩阵
-                if !if_player2 {
-                    animated_vector.reverse();
-                }
-                // 反转回来，并返回
-                return Some(animated_vector);
-            }
-            _ => {return None;}
+            Direction::Left | Direction::Up => (self.left_index, self.right_index),
+            _ => (self.right_index, self.left_index),
         }
-    
     }
 
+    // 向左发送：receiver_line 的末尾和 sender_line 的开头靠着桥
     fn send_abstract(&mut self, receiver_line: &mut Vec<u32>, sender_line: &mut Vec<u32>) -> Vec<u32>{
         // 先检查有几个空位
         let mut count = 0;
@@ -97,7 +106,8 @@ impl Bridge {
         // 将sender对应的数字拷走，注意需要检查是否为0
         let mut extra_num = 0;
         let mut i = 0;
-        while i < count && i + extra_num < length_of_line {
+        // 最多送出 sending_limit 块
+        while i < count && i < self.sending_limit && i + extra_num < length_of_line {
             assert!(receiver_line[length_of_line - count + i] == 0);
             receiver_line[length_of_line - count + i] = sender_line[i + extra_num];
             // 方便可以到时候直接move
@@ -119,52 +129,58 @@ impl Bridge {
     }
 
     // 查看当前向尝试的通道操作是否合法
-    fn if_legal(&mut self, board1: &mut GameBoard,  board2: &mut GameBoard, direction: Direction, if_player2: bool) -> bool {
-        // 先检查方向是否合法
-        if !self.if_bin_direction {
-            if !if_player2{
-                if direction!= self.direction {
-                    return false;
-                }
-            }else {
-                if direction!= Direction::opposite(& self.direction) {
-                    return false;
-                }
-            }
+    fn if_legal(&self, send_board: &GameBoard, direction: Direction) -> bool {
+        // 先检查方向是否合法：须与桥梁在同一条轴上，单向通道还只能顺着瓷砖过桥的方向发送
+        if matches!(direction, Direction::Quit | Direction::None) || is_vertical(direction) != self.is_vertical() {
+            return false;
         }
-        match direction {
-            Direction::Up => {
-                return false;
-            }
-            Direction::Down => {
-                return false;
-            }
-            _ => {
-            }
+        if !self.if_bin_direction && direction != self.direction {
+            return false;
         }
         // 再检查是否还有发送数量
-        if self.sending_limit <= 0 {
+        if self.sending_limit == 0 {
             return false;
         }
-
         // 再检查通道是否打开
         if !self.if_open {
             return false;
         }
-        // 最后检查选择的方向上原有棋盘是否有内容
-        if direction == Direction::Left {
-            let value: u32 = board2.get_tiles()[self.right_index].iter().sum();
-            if value == 0 {
-                return false;
-            } 
-        }
-        else if direction == Direction::Right {
-            let value: u32 = board2.get_tiles()[self.left_index].iter().sum();
-            if value == 0 {
-                return false;
-            } 
+        // 最后检查发送方桥梁所在的行（列）上是否有内容
+        let (_, send_index) = self.indices(direction);
+        read_line(send_board, direction, send_index).iter().sum::<u32>() > 0
+    }
+}
+
+fn is_vertical(direction: Direction) -> bool {
+    matches!(direction, Direction::Up | Direction::Down)
+}
+
+// 取出第 index 行（上下时为列），并排成向左的情形：向右、向下时反转
+fn read_line(board: &GameBoard, direction: Direction, index: usize) -> Vec<u32> {
+    let tiles = board.get_tiles();
+    let mut line: Vec<u32> = match is_vertical(direction) {
+        false => tiles[index].clone(),
+        true => tiles.iter().map(|row| row[index]).collect(),
+    };
+    if matches!(direction, Direction::Right | Direction::Down) {
+        line.reverse();
+    }
+    line
+}
+
+// read_line 的逆操作，把处理后的一行写回棋盘
+fn write_line(board: &mut GameBoard, direction: Direction, index: usize, mut line: Vec<u32>) {
+    if matches!(direction, Direction::Right | Direction::Down) {
+        line.reverse();
+    }
+    let tiles = board.get_tiles_mut();
+    match is_vertical(direction) {
+        false => tiles[index] = line,
+        true => {
+            for (row, tile) in tiles.iter_mut().zip(line) {
+                row[index] = tile;
+            }
         }
-        true
     }
 }
 
@@ -206,7 +222,7 @@ mod test_bridge {
 
         let mut bridge = Bridge::new(true, Direction::Left, true, 1,
              1, 200);
-        let animinated_vector = bridge.send_through_bridge(&mut game2, &mut game, Direction::Right, true);
+        let animinated_vector = bridge.send_through_bridge(&mut game2, &mut game, Direction::Right);
 
         let expected1 = vec![
             vec![2, 2, 4, 4],
@@ -233,5 +249,46 @@ mod test_bridge {
         }
 
     }
-    
+
+    #[test]
+    fn test_one_way_bridge_with_quota() {
+        let mut left = GameBoard::from_tiles(vec![vec![2, 4, 8, 16], vec![0; 4], vec![0; 4], vec![0; 4]]);
+        let mut right = GameBoard::from_tiles(vec![vec![0; 4]; 4]);
+        // 向右的桥梁不接受右侧玩家向左发送
+        let mut bridge = Bridge::new(false, Direction::Right, true, 0, 0, 3);
+        assert_eq!(bridge.send_through_bridge(&mut left, &mut right, Direction::Left), None);
+        // 额度只有3块，离桥最远的一块留在原地
+        let animated = bridge.send_through_bridge(&mut right, &mut left, Direction::Right);
+        assert_eq!(animated, Some(vec![4, 8, 16]));
+        assert_eq!(left.get_tiles()[0], vec![2, 0, 0, 0]);
+        assert_eq!(right.get_tiles()[0], vec![0, 4, 8, 16]);
+        assert_eq!(bridge.sending_limit(), 0);
+        assert_eq!(bridge.send_through_bridge(&mut right, &mut left, Direction::Right), None);
+    }
+
+    #[test]
+    fn test_vertical_bridge_both_ways() {
+        let mut top = GameBoard::from_tiles(vec![vec![0, 0, 2, 0], vec![0, 0, 4, 0], vec![0; 4], vec![0; 4]]);
+        let mut bottom = GameBoard::from_tiles(vec![vec![0; 4], vec![0; 4], vec![0; 4], vec![8, 0, 0, 0]]);
+        let mut bridge = Bridge::new(true, Direction::Down, true, 2, 2, 10);
+        assert!(bridge.is_vertical());
+        // 竖直桥梁不接受左右发送
+        assert_eq!(bridge.send_through_bridge(&mut bottom, &mut top, Direction::Right), None);
+
+        // 向下：上方第三列的瓷砖顺着列滑到下方棋盘的底部，动画从上到下排列
+        assert_eq!(bridge.send_through_bridge(&mut bottom, &mut top, Direction::Down), Some(vec![2, 4]));
+        let column = |board: &GameBoard| board.get_tiles().iter().map(|row| row[2]).collect::<Vec<_>>();
+        assert_eq!(column(&top), vec![0; 4]);
+        assert_eq!(column(&bottom), vec![0, 0, 2, 4]);
+
+        // 双向通道也能向上送回去
+        assert_eq!(bridge.send_through_bridge(&mut top, &mut bottom, Direction::Up), Some(vec![2, 4]));
+        assert_eq!(column(&top), vec![2, 4, 0, 0]);
+        assert_eq!(column(&bottom), vec![0; 4]);
+        assert_eq!(bridge.sending_limit(), 6);
+
+        // 单向通道只能顺着方向发送
+        let mut one_way = Bridge::new(false, Direction::Down, true, 2, 2, 10);
+        assert_eq!(one_way.send_through_bridge(&mut top, &mut bottom, Direction::Up), None);
+    }
 }
@@ -0,0 +1,106 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config;
+use crate::game_board::Direction;
+use crate::protocol::BridgePhase;
+
+/// 一座桥梁的时间表：开启和关闭交替，每段随机选择瓷砖过桥的方向或双向、可送出的瓷砖数和持续时间
+/// 每座桥梁各有一份时间表，方向总在桥梁所在的轴上：左右并排的棋盘之间选向左或向右，上下叠放的选向上或向下
+/// 第一次开启时留在创建时的行，之后每次开启时随机换到一行（竖直桥梁为列），关闭时留在原处
+// 由种子决定，同一种子和行总是生成同样的时间表，方便复现
+pub struct BridgeSchedule {
+    rng: StdRng,
+    line: u8,       // 桥梁当前所在的行（竖直桥梁为列）
+    lines: u8,      // 可选的行数，即棋盘边长
+    vertical: bool, // 是否是连接上下两块棋盘的竖直桥梁
+    open: bool,     // 上一段是否开启
+    opened: bool,   // 是否已经开启过
+}
+
+impl BridgeSchedule {
+    pub fn new(seed: u64, line: u8, lines: u8, vertical: bool) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed), line, lines, vertical, open: false, opened: false }
+    }
+
+    // 生成下一段，第一段总是开启
+    pub fn next_phase(&mut self) -> BridgePhase {
+        self.open = !self.open;
+        if self.open && self.opened {
+            self.line = self.rng.gen_range(0..self.lines);
+        }
+        self.opened |= self.open;
+        let (min_ms, max_ms) = match self.open {
+            true => (config::BRIDGE_OPEN_MS_MIN, config::BRIDGE_OPEN_MS_MAX),
+            false => (config::BRIDGE_CLOSED_MS_MIN, config::BRIDGE_CLOSED_MS_MAX),
+        };
+        let direction = match (self.vertical, self.rng.gen_bool(0.5)) {
+            (false, true) => Direction::Right,
+            (false, false) => Direction::Left,
+            (true, true) => Direction::Down,
+            (true, false) => Direction::Up,
+        };
+        BridgePhase {
+            open: self.open,
+            line: self.line,
+            direction,
+            two_way: self.rng.gen_bool(config::BRIDGE_TWO_WAY_PROBABILITY),
+            sending_limit: self.rng.gen_range(config::BRIDGE_SENDING_LIMIT_MIN..=config::BRIDGE_SENDING_LIMIT_MAX),
+            duration_ms: self.rng.gen_range(min_ms..=max_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_bridge_schedule {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_schedule() {
+        let phases = |seed| {
+            let mut schedule = BridgeSchedule::new(seed, 1, 4, false);
+            (0..20).map(|_| schedule.next_phase()).collect::<Vec<_>>()
+        };
+        assert_eq!(phases(42), phases(42));
+        assert_ne!(phases(42), phases(43));
+    }
+
+    #[test]
+    fn test_phases_alternate_within_limits() {
+        let mut schedule = BridgeSchedule::new(7, 2, 4, false);
+        let mut line = 2;
+        for i in 0..50 {
+            let phase = schedule.next_phase();
+            assert_eq!(phase.open, i % 2 == 0);
+            // 第一段留在创建时的行，关闭时不移动
+            if i == 0 || !phase.open {
+                assert_eq!(phase.line, line);
+            }
+            assert!(phase.line < 4);
+            line = phase.line;
+            assert!(matches!(phase.direction, Direction::Left | Direction::Right));
+            assert!((config::BRIDGE_SENDING_LIMIT_MIN..=config::BRIDGE_SENDING_LIMIT_MAX).contains(&phase.sending_limit));
+            let (min_ms, max_ms) = if phase.open {
+                (config::BRIDGE_OPEN_MS_MIN, config::BRIDGE_OPEN_MS_MAX)
+            } else {
+                (config::BRIDGE_CLOSED_MS_MIN, config::BRIDGE_CLOSED_MS_MAX)
+            };
+            assert!((min_ms..=max_ms).contains(&phase.duration_ms));
+        }
+    }
+
+    #[test]
+    fn test_bridge_moves_between_lines() {
+        let mut schedule = BridgeSchedule::new(7, 2, 4, false);
+        let lines: Vec<u8> = (0..20).map(|_| schedule.next_phase().line).collect();
+        assert!(lines.iter().any(|&line| line != 2));
+    }
+
+    #[test]
+    fn test_vertical_schedule_stays_on_column_axis() {
+        let mut schedule = BridgeSchedule::new(7, 3, 4, true);
+        let directions: Vec<Direction> = (0..50).map(|_| schedule.next_phase().direction).collect();
+        assert!(directions.iter().all(|direction| matches!(direction, Direction::Up | Direction::Down)));
+        assert!(directions.contains(&Direction::Up) && directions.contains(&Direction::Down));
+    }
+}
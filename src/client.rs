@@ -1,22 +1,20 @@
 use crossterm::{
     event::{self, Event, KeyCode},
-    terminal::{enable_raw_mode, EnterAlternateScreen},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use std::io;
-use std::process;
-use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::select;
 use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout_at, Duration, Instant};
 use tui::{
-    backend::CrosstermBackend,
-    layout::Alignment,
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Rect},
     style::{Color, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, Paragraph},
-    Terminal,
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame, Terminal,
 };
 
 mod bridge;
@@ -26,17 +24,327 @@ mod draw;
 mod game_board;
 mod game_controller;
 mod io_manager;
+mod lobby_screen;
+mod notation;
 mod protocol;
-use dc::draw_double_board;
+use dc::{draw_board_captions, draw_double_board, draw_multi_board, draw_stacked_board};
 use game_board::Direction;
+use lobby_screen::{run_lobby, LobbyOutcome};
 use protocol::{
-    receive_message, receive_message_with_buffer, serialize_message, Message, PlayerAction,
+    write_message, BridgeForecast, BridgeLink, BridgeStatus, ClockStatus, Codec, CoopControl, CoopStatus, FrameReader, GameState, Hello, Latency, MatchInfo,
+    MatchResult, Message, PlayerAction, SpawnerStatus, TilePlacement, Welcome, PROTOCOL_VERSION,
 };
 
 pub use crate::bridge::Bridge;
 pub use crate::game_board::GameBoard;
 pub use crate::game_controller::GameController;
-pub use crate::io_manager::IOManager;
+pub use crate::io_manager::{key_direction, IOManager};
+
+// 双人布局按 4x4 棋盘绘制，握手时拒绝其他边长的服务器
+const BOARD_SIZE: usize = 4;
+
+// 对局画面需要的状态：所有玩家的棋盘、桥梁及其时间表、延迟、淘汰赛中的出局情况、竞速进度、来袭的垃圾、合作玩法的移动权、回合制的棋钟和反派出块的角色，
+// our_identity 为 0 表示观战
+#[derive(Clone)]
+struct MatchView {
+    boards: Vec<Vec<Vec<u32>>>,
+    links: Vec<BridgeLink>,
+    bridges: Vec<BridgeStatus>, // 与 links 一一对应，相邻两块棋盘之间可有多座，每行至多一座
+    bridge_forecasts: Vec<(BridgeForecast, Instant)>, // 各桥梁的下一次变化和收到它的时刻，与 bridges 一一对应
+    latency: Latency,
+    our_identity: u8,
+    eliminated: Vec<Option<u8>>, // 已出局玩家的名次，重连后只知道出局、不知道名次时为 Some(0)
+    race: Option<(u32, Instant)>, // 竞速的目标瓷砖和本地推算出的时间到的时刻
+    incoming_garbage: Vec<u32>,   // 各玩家即将落下的垃圾块数
+    coop: Option<CoopStatus>,     // 合作玩法中所有人共用 boards 中唯一的棋盘
+    clocks: Option<(ClockStatus, Instant)>, // 回合制的棋钟和收到它的时刻，轮到的玩家的棋钟据此在本地倒计时
+    spawner: Option<(SpawnerStatus, Instant)>, // 反派出块的角色和收到它的时刻，出块时限据此在本地倒计时
+    spawn_cursor: (usize, usize),               // 自己出块时选中的格子，x为列、y为行
+}
+
+impl MatchView {
+    // 收到第一个 GameState 之前先显示两块空棋盘
+    fn new(our_identity: u8) -> Self {
+        Self {
+            boards: vec![GameBoard::new().get_tiles().clone(); 2],
+            links: BridgeLink::ring(2),
+            bridges: vec![],
+            bridge_forecasts: vec![],
+            latency: Latency::default(),
+            our_identity,
+            eliminated: vec![],
+            race: None,
+            incoming_garbage: vec![],
+            coop: None,
+            clocks: None,
+            spawner: None,
+            spawn_cursor: (0, 0),
+        }
+    }
+
+    // 换上新的棋盘，返回这一步经过各桥梁的瓷砖和桥梁下标
+    fn update(&mut self, state: GameState) -> Vec<(usize, Vec<u32>)> {
+        self.eliminated.resize(state.boards.len(), None);
+        for (place, out) in self.eliminated.iter_mut().zip(&state.eliminated) {
+            if *out && place.is_none() {
+                *place = Some(0);
+            }
+        }
+        self.boards = state.boards;
+        self.links = state.links;
+        self.bridges = state.bridges;
+        self.bridge_forecasts = state.bridge_forecasts.into_iter().map(|forecast| (forecast, Instant::now())).collect();
+        self.incoming_garbage = state.incoming_garbage;
+        self.coop = state.coop;
+        self.clocks = state.clocks.map(|clocks| (clocks, Instant::now()));
+        self.spawner = state.spawner.map(|spawner| (spawner, Instant::now()));
+        self.race = state.race.map(|race| (race.target, Instant::now() + Duration::from_millis(race.time_left_ms)));
+        state.animated
+    }
+
+    // 自己是否已在淘汰赛中出局
+    fn is_eliminated(&self) -> bool {
+        self.our_identity > 0 && matches!(self.eliminated.get(self.our_identity as usize - 1), Some(Some(_)))
+    }
+
+    // 自己是否正在为对手出块
+    fn is_spawning(&self) -> bool {
+        matches!(self.spawner, Some((spawner, _)) if spawner.spawner == self.our_identity && spawner.spawn_time_left_ms.is_some())
+    }
+
+    // 出块时方向键在滑动方的棋盘上移动选中的格子，2、空格或回车放置2，4放置4
+    fn choose_spawn(&mut self, key: KeyCode) -> Option<TilePlacement> {
+        let slider = self.spawner.map_or(0, |(spawner, _)| spawner.slider as usize - 1);
+        let last = self.boards.get(slider).map_or(0, |board| board.len().saturating_sub(1));
+        let (x, y) = &mut self.spawn_cursor;
+        match key_direction(key) {
+            Direction::Up => *y = y.saturating_sub(1),
+            Direction::Down => *y = (*y + 1).min(last),
+            Direction::Left => *x = x.saturating_sub(1),
+            Direction::Right => *x = (*x + 1).min(last),
+            _ => {}
+        }
+        let value = match key {
+            KeyCode::Char('2') | KeyCode::Char(' ') | KeyCode::Enter => 2,
+            KeyCode::Char('4') => 4,
+            _ => return None,
+        };
+        Some(TilePlacement { x: *x as u8, y: *y as u8, value })
+    }
+
+    // 每块棋盘上方的说明：玩家编号和网络延迟，出局的玩家显示名次，有垃圾来袭时显示块数
+    // 合作玩法只有一块棋盘，显示玩法和共同得分
+    fn captions(&self) -> Vec<String> {
+        if let Some(ref coop) = self.coop {
+            let score: u32 = self.boards.iter().flatten().flatten().sum();
+            return vec![format!("合作·{} 共同得分 {}", coop.control.describe(), score)];
+        }
+        (0..self.boards.len())
+            .map(|i| {
+                let you = if i + 1 == self.our_identity as usize { "（你）" } else { "" };
+                let caption = match (self.eliminated.get(i).copied().flatten(), self.latency.players_ms.get(i).copied().flatten()) {
+                    (Some(0), _) => format!("玩家{}{} 已出局", i + 1, you),
+                    (Some(place), _) => format!("玩家{}{} 出局 第{}名", i + 1, you, place),
+                    (None, Some(ms)) => format!("玩家{}{} 延迟 {}ms", i + 1, you, ms),
+                    (None, None) => format!("玩家{}{} 延迟 --", i + 1, you),
+                };
+                let caption = match self.incoming_garbage.get(i) {
+                    Some(&amount) if amount > 0 => format!("{} 垃圾来袭 {}", caption, amount),
+                    _ => caption,
+                };
+                let caption = match self.spawner {
+                    Some((spawner, _)) if spawner.slider as usize == i + 1 => format!("{} 滑动方", caption),
+                    Some((spawner, _)) if spawner.spawner as usize == i + 1 => format!("{} 出块方", caption),
+                    _ => caption,
+                };
+                match self.clocks {
+                    Some((ref clocks, received)) => {
+                        let turn = clocks.turn as usize == i + 1;
+                        let mut left = Duration::from_millis(clocks.remaining_ms.get(i).copied().unwrap_or(0));
+                        if turn {
+                            left = left.saturating_sub(received.elapsed());
+                        }
+                        let secs = left.as_secs();
+                        format!("{}{} 棋钟 {}:{:02}", if turn { "▶" } else { "" }, caption, secs / 60, secs % 60)
+                    }
+                    None => caption,
+                }
+            })
+            .collect()
+    }
+
+    // 两名玩家时左右并排、中间是桥梁，每座开启的桥梁各画一条管道；桥梁架在列上时上下叠放，管道竖着画在两块棋盘之间；
+    // 更多玩家时排成网格，每座开启的桥梁在左侧棋盘右边对应的行上画一个标记，这一步经过的每座桥梁在下方各画一行
+    fn render<B: Backend>(&self, f: &mut Frame<B>, pipes: &[(usize, Vec<u32>)]) {
+        let captions = self.captions();
+        if let [board1, board2] = self.boards.as_slice() {
+            let drawn: Vec<(usize, &[u32])> = self
+                .bridges
+                .iter()
+                .enumerate()
+                .filter_map(|(index, bridge)| {
+                    let data = pipes.iter().find(|(pipe, _)| *pipe == index).map_or(&[][..], |(_, data)| &data[..]);
+                    (bridge.open || !data.is_empty()).then_some((bridge.line as usize, data))
+                })
+                .collect();
+            let stacked = self.bridges.iter().any(|bridge| matches!(bridge.direction, Direction::Up | Direction::Down));
+            match stacked {
+                false => draw_double_board(f, board1, board2, &drawn),
+                true => draw_stacked_board(f, board1, board2, &drawn),
+            }
+            draw_board_captions(f, [&captions[0], &captions[1]], stacked);
+        } else {
+            let markers: Vec<(usize, usize, &str)> = self
+                .links
+                .iter()
+                .zip(&self.bridges)
+                .filter(|(_, bridge)| bridge.open)
+                .map(|(link, bridge)| {
+                    let marker = match (bridge.two_way, bridge.direction) {
+                        (true, _) => "⇄",
+                        (false, Direction::Left) => "←",
+                        (false, _) => "→",
+                    };
+                    (link.left, bridge.line as usize, marker)
+                })
+                .collect();
+            let label = match self.coop {
+                Some(ref coop) => self.coop_label(coop),
+                None => "桥梁：棋盘右侧的标记通向下一名玩家".to_string(),
+            };
+            let drawn: Vec<(String, &[u32])> = pipes
+                .iter()
+                .filter_map(|(index, data)| {
+                    let (link, bridge) = self.links.get(*index).zip(self.bridges.get(*index))?;
+                    let name = format!("玩家{}⇄玩家{} 第{}行", link.left + 1, link.right + 1, bridge.line + 1);
+                    Some((name, &data[..]))
+                })
+                .collect();
+            draw_multi_board(f, &self.boards, &captions, &markers, &label, &drawn);
+        }
+        self.render_race(f);
+        self.render_spawner(f);
+        self.render_bridges(f);
+    }
+
+    // 一对相邻棋盘之间各桥梁当前的状态和其中最先到来的下一次变化显示在边框底部，服务器没有为桥梁排时间表时不显示
+    // 显示的是自己所在的第一对棋盘，观战或已没有桥梁通向自己时显示第一对
+    fn render_bridges<B: Backend>(&self, f: &mut Frame<B>) {
+        let me = self.our_identity as usize;
+        let Some(shown_link) = self
+            .links
+            .iter()
+            .find(|link| me == 0 || link.left + 1 == me || link.right + 1 == me)
+            .or(self.links.first())
+            .copied()
+        else {
+            return;
+        };
+        let shown: Vec<usize> = (0..self.links.len()).filter(|&index| self.links[index] == shown_link).collect();
+        let soonest = shown
+            .iter()
+            .filter_map(|&index| self.bridge_forecasts.get(index))
+            .min_by_key(|(forecast, received)| Duration::from_millis(forecast.next_in_ms).saturating_sub(received.elapsed()));
+        let Some(&(forecast, received)) = soonest else {
+            return;
+        };
+        // 竖直桥梁架在列上，双向桥梁两个方向都能发送
+        let describe = |open: bool, line: u8, direction: Direction, two_way: bool, count: u32| {
+            let vertical = matches!(direction, Direction::Up | Direction::Down);
+            let arrow = match (two_way, direction) {
+                (true, _) if vertical => "⇅",
+                (true, _) => "⇄",
+                (false, Direction::Left) => "←",
+                (false, Direction::Up) => "↑",
+                (false, Direction::Down) => "↓",
+                (false, _) => "→",
+            };
+            let name = format!("第{}{}", line + 1, if vertical { "列" } else { "行" });
+            match open {
+                false => format!("{} 关闭", name),
+                true => format!("{} {} {}块", name, arrow, count),
+            }
+        };
+        let mut current: Vec<&BridgeStatus> = shown.iter().filter_map(|&index| self.bridges.get(index)).collect();
+        current.sort_by_key(|bridge| bridge.line);
+        let current: Vec<String> = current
+            .iter()
+            .map(|bridge| describe(bridge.open, bridge.line, bridge.direction, bridge.two_way, bridge.remaining))
+            .collect();
+        let next = forecast.next;
+        let left = Duration::from_millis(forecast.next_in_ms).saturating_sub(received.elapsed()).as_secs();
+        let text = format!(
+            " 桥梁 玩家{}⇄玩家{} {}  {}秒后 {} ",
+            shown_link.left + 1,
+            shown_link.right + 1,
+            current.join("  "),
+            left,
+            describe(next.open, next.line, next.direction, next.two_way, next.sending_limit)
+        );
+        let size = f.size();
+        let area = Rect::new(size.x + 2, size.y + size.height.saturating_sub(1), size.width.saturating_sub(4), 1);
+        f.render_widget(Paragraph::new(text), area);
+    }
+
+    // 合作玩法中谁能移动：轮到谁、本轮各人的投票，或自己负责的方向
+    fn coop_label(&self, coop: &CoopStatus) -> String {
+        let name = |player: u8| {
+            let you = if player == self.our_identity { "（你）" } else { "" };
+            format!("玩家{}{}", player, you)
+        };
+        match coop.control {
+            CoopControl::Alternate => coop.turn.map_or(String::new(), |turn| format!("轮到{}移动", name(turn))),
+            CoopControl::Vote => {
+                let votes: Vec<String> = coop
+                    .votes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, vote)| {
+                        let arrow = match vote {
+                            Some(Direction::Up) => "↑",
+                            Some(Direction::Down) => "↓",
+                            Some(Direction::Left) => "←",
+                            Some(Direction::Right) => "→",
+                            _ => "--",
+                        };
+                        format!("{} {}", name(i as u8 + 1), arrow)
+                    })
+                    .collect();
+                format!("投票：{}", votes.join("  "))
+            }
+            CoopControl::SplitAxis if self.our_identity == 0 => "单数号玩家左右移动，双数号玩家上下移动".to_string(),
+            CoopControl::SplitAxis if self.our_identity % 2 == 1 => "你负责左右移动".to_string(),
+            CoopControl::SplitAxis => "你负责上下移动".to_string(),
+        }
+    }
+
+    // 反派出块等待出块时，在边框右上角显示出块时限，自己出块时还显示选中的格子和按键
+    fn render_spawner<B: Backend>(&self, f: &mut Frame<B>) {
+        let Some((spawner, received)) = self.spawner else { return };
+        let Some(left_ms) = spawner.spawn_time_left_ms else { return };
+        let left = Duration::from_millis(left_ms).saturating_sub(received.elapsed()).as_secs();
+        let text = if self.is_spawning() {
+            let (x, y) = self.spawn_cursor;
+            format!(" 出块：第{}行第{}列  方向键选格，2或4放置  剩余{}秒 ", y + 1, x + 1, left)
+        } else {
+            format!(" 等待玩家{}出块  剩余{}秒 ", spawner.spawner, left)
+        };
+        let size = f.size();
+        let area = Rect::new(size.x + 2, size.y, size.width.saturating_sub(4), 1);
+        f.render_widget(Paragraph::new(text).alignment(Alignment::Right), area);
+    }
+
+    // 竞速的目标和剩余时间显示在边框右上角，每次重画时按本地时钟更新
+    fn render_race<B: Backend>(&self, f: &mut Frame<B>) {
+        if let Some((target, deadline)) = self.race {
+            let left = deadline.saturating_duration_since(Instant::now()).as_secs();
+            let text = format!(" 竞速 目标 {}  剩余 {}:{:02} ", target, left / 60, left % 60);
+            let size = f.size();
+            let area = Rect::new(size.x + 2, size.y, size.width.saturating_sub(4), 1);
+            f.render_widget(Paragraph::new(text).alignment(Alignment::Right), area);
+        }
+    }
+}
 
 async fn show_loading_screen(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
@@ -50,20 +358,8 @@ async fn show_loading_screen(
 
     while *running {
         if let Ok(message) = rx_main.try_recv() {
-            if message != "Connected successfully".to_string() {
-                terminal.draw(|f| {
-                    let size = f.size();
-                    let block = Block::default().title("错误").borders(Borders::ALL);
-                    let text = vec![Spans::from(Span::styled(
-                        "匹配失败，將返回主界面",
-                        Style::default().fg(Color::Red),
-                    ))];
-                    let paragraph = Paragraph::new(text)
-                        .block(block)
-                        .alignment(Alignment::Center);
-                    f.render_widget(paragraph, size);
-                })?;
-                let _ = sleep(Duration::from_secs(3));
+            if message != "Connected successfully" {
+                show_error(terminal, &message).await?;
             }
             break;
         }
@@ -83,7 +379,7 @@ async fn show_loading_screen(
         terminal.draw(|f| {
             let size = f.size();
             let block = Block::default()
-                .title("等待匹配中...")
+                .title("连接服务器中...")
                 .borders(Borders::ALL);
             let text = vec![
                 Spans::from(Span::styled(spinner, Style::default().fg(Color::Yellow))),
@@ -103,8 +399,259 @@ async fn show_loading_screen(
     Ok(())
 }
 
+// 显示错误原因，几秒后返回
+async fn show_error(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    reason: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    terminal.draw(|f| {
+        let size = f.size();
+        let block = Block::default().title("错误").borders(Borders::ALL);
+        // 第二行显示具体原因，例如服务器拒绝连接的说明
+        let text = vec![
+            Spans::from(Span::styled(
+                "匹配失败，將返回主界面",
+                Style::default().fg(Color::Red),
+            )),
+            Spans::from(Span::styled(reason, Style::default().fg(Color::White))),
+        ];
+        let paragraph = Paragraph::new(text)
+            .block(block)
+            .alignment(Alignment::Center);
+        f.render_widget(paragraph, size);
+    })?;
+    sleep(Duration::from_secs(3)).await;
+    Ok(())
+}
+
+// 握手：先用 JSON 发送 Hello，收到 Welcome 后双方改用协商出的编码
+// 外层错误为网络错误，可以重试；内层错误为服务器拒绝或协议不兼容，附带展示给玩家的原因
+async fn client_handshake(
+    stream: &mut TcpStream,
+    reader: &mut FrameReader,
+    preferred: Codec,
+    resume_token: Option<&str>,
+) -> io::Result<Result<Welcome, String>> {
+    let client_name = format!("rust2048-client {}", env!("CARGO_PKG_VERSION"));
+    let hello = match resume_token {
+        Some(token) => Hello::resume(&client_name, preferred, token),
+        None => Hello::new(&client_name, preferred),
+    };
+    write_message(stream, Codec::Json, &Message::Hello(hello)).await?;
+    Ok(match reader.read_message(stream, Codec::Json).await? {
+        Message::Welcome(welcome) if welcome.version != PROTOCOL_VERSION => Err(format!(
+            "协议版本不兼容：客户端为 {}，服务器为 {}",
+            PROTOCOL_VERSION, welcome.version
+        )),
+        // 没开启的玩法和布局由大厅界面隐藏，棋盘边长则没有退路
+        Message::Welcome(welcome) if welcome.board_size != BOARD_SIZE => Err(format!(
+            "服务器的棋盘边长为 {}，本客户端只能显示 {}x{} 的棋盘",
+            welcome.board_size, BOARD_SIZE, BOARD_SIZE
+        )),
+        Message::Welcome(welcome) => Ok(welcome),
+        Message::Rejected(reason) => Err(format!("服务器拒绝连接：{}", reason)),
+        message => Err(format!("握手失败：收到意外的消息 {:?}", message)),
+    })
+}
+
+// 断线后凭会话令牌重连，等待间隔从 CLIENT_MAX_RETRIES_PER_REQUEST 秒起每次翻倍，最多尝试 CLIENT_MAX_RETRIES 次
+// 重连期间在棋盘上显示提示，会话已过期或次数用完时返回 None
+async fn reconnect(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    address: &str,
+    preferred: Codec,
+    token: &str,
+    view: &MatchView,
+) -> Result<Option<(TcpStream, FrameReader, Codec)>, Box<dyn std::error::Error>> {
+    for attempt in 0..config::CLIENT_MAX_RETRIES {
+        let text = format!("连接已断开，重新连接中…（{}/{}）", attempt + 1, config::CLIENT_MAX_RETRIES);
+        draw_overlay(terminal, view, &text)?;
+        sleep(Duration::from_secs(config::CLIENT_MAX_RETRIES_PER_REQUEST << attempt)).await;
+
+        let mut stream = match TcpStream::connect(address).await {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let mut reader = FrameReader::new();
+        match client_handshake(&mut stream, &mut reader, preferred, Some(token)).await {
+            Ok(Ok(welcome)) => return Ok(Some((stream, reader, welcome.codec))),
+            Ok(Err(_)) => return Ok(None), // 会话已过期，重试也没有用
+            Err(_) => continue,
+        }
+    }
+    Ok(None)
+}
+
+// 绘制对局画面，每个棋盘上方显示该玩家的网络延迟，pipes 为这一步经过各桥梁的瓷砖和桥梁下标
+fn draw_game(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    view: &MatchView,
+    pipes: &[(usize, Vec<u32>)],
+) -> io::Result<()> {
+    terminal.draw(|f| view.render(f, pipes))?;
+    Ok(())
+}
+
+// 在所有棋盘上居中显示一行提示，例如重新连接中
+fn draw_overlay(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    view: &MatchView,
+    text: &str,
+) -> io::Result<()> {
+    terminal.draw(|f| {
+        view.render(f, &[]);
+        let size = f.size();
+        // 中文字符占两列
+        let width = (text.chars().count() as u16 * 2 + 4).min(size.width);
+        let area = Rect::new(size.x + (size.width - width) / 2, size.y + size.height.saturating_sub(3) / 2, width, 3.min(size.height));
+        let paragraph = Paragraph::new(Span::styled(text, Style::default().fg(Color::Yellow)))
+            .block(Block::default().borders(Borders::ALL))
+            .alignment(Alignment::Center);
+        f.render_widget(Clear, area);
+        f.render_widget(paragraph, area);
+    })?;
+    Ok(())
+}
+
+// 握手失败或收到意外消息时，在加载界面上显示原因，然后恢复终端退出
+async fn abort_with_error(
+    tx_to_async: &mpsc::Sender<String>,
+    loading_task: tokio::task::JoinHandle<()>,
+    reason: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let _ = tx_to_async.send(reason).await;
+    let _ = loading_task.await;
+    restore_terminal()
+}
+
+fn restore_terminal() -> Result<(), Box<dyn std::error::Error>> {
+    disable_raw_mode()?;
+    io::stdout().execute(LeaveAlternateScreen)?;
+    Ok(())
+}
+
+// 对局结束界面：显示各玩家最终棋盘、结果和分数，按任意键后返回主菜单
+// result 为 None 表示没有收到结果就与服务器断开了，此时显示最后收到的棋盘
+fn show_match_result(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    result: Option<&MatchResult>,
+    view: &MatchView,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut view = view.clone();
+    // 对局已结束，不再倒计时
+    view.race = None;
+    view.clocks = None;
+    view.spawner = None;
+    view.bridge_forecasts.clear();
+    if let Some(result) = result {
+        view.boards = result.boards.clone();
+    }
+    let our_identity = view.our_identity;
+    let (title, color) = match result.map(|result| result.winner) {
+        None => ("与服务器的连接已断开".to_string(), Color::Red),
+        Some(_) if view.coop.is_some() => ("合作结束".to_string(), Color::Yellow),
+        Some(Some(winner)) if our_identity == 0 => (format!("玩家{}获胜", winner), Color::Green),
+        Some(Some(winner)) if winner == our_identity => ("你赢了！".to_string(), Color::Green),
+        Some(Some(_)) => ("你输了".to_string(), Color::Red),
+        Some(None) => ("平局".to_string(), Color::Yellow),
+    };
+    let mut text = vec![Spans::from(Span::styled(title, Style::default().fg(color)))];
+    if let Some(result) = result {
+        let you = |player: u8| if player == our_identity { "（你）" } else { "" };
+        text.push(Spans::from(format!("结束原因：{}", result.reason.describe())));
+        let scores: Vec<String> = match view.coop {
+            Some(_) => result.scores.iter().map(|score| format!("共同得分 {}", score)).collect(),
+            None => result
+                .scores
+                .iter()
+                .enumerate()
+                .map(|(i, score)| format!("玩家{}{} 分数 {}", i + 1, you(i as u8 + 1), score))
+                .collect(),
+        };
+        // 人多时每行最多四人
+        for line in scores.chunks(4) {
+            text.push(Spans::from(line.join("    ")));
+        }
+        if result.standings.len() > 2 && view.coop.is_none() {
+            let standings: Vec<String> = result.standings.iter().map(|player| format!("玩家{}", player)).collect();
+            text.push(Spans::from(format!("排名：{}", standings.join(" > "))));
+        }
+    }
+    text.push(Spans::from(Span::styled("按任意键返回主菜单", Style::default().fg(Color::White))));
+
+    terminal.draw(|f| {
+        view.render(f, &[]);
+        // 结果显示在棋盘下方
+        let size = f.size();
+        let height = text.len() as u16 + 2;
+        let area = Rect::new(size.x + 1, size.height.saturating_sub(height + 1), size.width.saturating_sub(2), height);
+        let paragraph = Paragraph::new(text.clone())
+            .block(Block::default().title("对局结束").borders(Borders::ALL))
+            .alignment(Alignment::Center);
+        f.render_widget(paragraph, area);
+    })?;
+
+    loop {
+        if let Event::Key(key) = event::read()? {
+            if key.kind == event::KeyEventKind::Press {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// 观战：只读地显示服务器转发的棋盘和桥梁动画，直到对局结束或按 q 退出
+async fn spectate(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    stream: &mut TcpStream,
+    reader: &mut FrameReader,
+    codec: Codec,
+    info: &MatchInfo,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut view = MatchView::new(0);
+    terminal.clear()?;
+    // 有观战延迟时第一帧要过一会儿才到
+    draw_overlay(terminal, &view, &format!("观战：{}，等待画面…  按 q 退出", info.title))?;
+    loop {
+        if event::poll(Duration::from_millis(10))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == event::KeyEventKind::Press && key.code == KeyCode::Char('q') {
+                    return Ok(());
+                }
+            }
+        }
+        let message = match tokio::time::timeout(Duration::from_millis(50), reader.read_message(stream, codec)).await {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        match message {
+            Ok(Message::GameState(game_state)) => {
+                let pipes = view.update(*game_state);
+                draw_game(terminal, &view, &pipes)?;
+            }
+            Ok(Message::Latency(latency)) => {
+                view.latency = latency;
+                draw_game(terminal, &view, &[])?;
+            }
+            Ok(Message::Eliminated(elimination)) => {
+                if let Some(place) = view.eliminated.get_mut(elimination.player as usize - 1) {
+                    *place = Some(elimination.place);
+                }
+            }
+            Ok(Message::MatchOver(result)) => {
+                return show_match_result(terminal, Some(&result), &view);
+            }
+            Ok(_) => {}
+            Err(_) => return show_match_result(terminal, None, &view),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // 握手时优先使用的消息编码，最终使用哪种由服务器决定
+    let preferred_codec = Codec::from_args()?;
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     stdout.execute(EnterAlternateScreen)?;
@@ -122,28 +669,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let address = config::SERVER_IP.to_owned() + ":" + config::SERVER_PORT;
     let address: &str = address.as_str();
 
-    let mut game_board = GameBoard::new();
-    let mut other_board = GameBoard::new();
-    let data = vec![0,0,0];
-    let ref mut other_board_ref = other_board;
-
-    let mut our_identity = 0;
-    let mut a = 0;
+    // 所有玩家的棋盘和延迟，按玩家编号排列，玩家1的棋盘总在最前
+    let mut view = MatchView::new(0);
+    // 对局开始时服务器发来的会话令牌，断线后凭它重连
+    let mut session_token: Option<String> = None;
 
     let mut running = true;
 
     let (tx_to_async, rx_from_main) = mpsc::channel(1);
     let (tx_to_main, mut rx_from_async) = mpsc::channel(1);
 
-    let _ = tokio::spawn({
+    let loading_task = tokio::spawn({
         async move {
             let _ =
                 show_loading_screen(&mut terminal1, &mut running, rx_from_main, tx_to_main).await;
         }
     });
 
-    for _ in 0..config::CLIENT_MAX_RETRIES {
-        if let Ok(_) = rx_from_async.try_recv() {
+    for attempt in 1..=config::CLIENT_MAX_RETRIES {
+        if rx_from_async.try_recv().is_ok() {
             // println!("Received error message: {}", error_message);
             terminal.draw(|f| {
                 let size = f.size();
@@ -157,89 +701,153 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .alignment(Alignment::Center);
                 f.render_widget(paragraph, size);
             })?;
-            let _ = sleep(Duration::from_secs(3));
+            sleep(Duration::from_secs(3)).await;
             break;
         }
         match TcpStream::connect(address).await {
             Ok(mut stream) => {
-                match receive_message(&mut stream).await {
-                    Ok(message) => {
-                        match message {
-                            Message::PlayerIdentity(identity) => {
-                                our_identity = identity.player_number;
-                            }
-                            _ => {
-                                println!("Received invalid message: {:?}, Should receive Player Identity", message);
-                                panic!("Client Should receive Player Identity");
-                            }
-                        }
+                let mut reader = FrameReader::new();
+                let welcome = match client_handshake(&mut stream, &mut reader, preferred_codec, None).await {
+                    Ok(Ok(welcome)) => welcome,
+                    Ok(Err(reason)) => return abort_with_error(&tx_to_async, loading_task, reason).await,
+                    Err(e) => return abort_with_error(&tx_to_async, loading_task, format!("握手失败：{}", e)).await,
+                };
+                let mut codec = welcome.codec;
+
+                // 连接成功，停止加载动画，进入大厅选择或创建房间
+                let _ = tx_to_async.send("Connected successfully".to_string()).await;
+                let _ = loading_task.await;
+                match run_lobby(&mut terminal, &mut stream, &mut reader, codec, &welcome.features).await {
+                    Ok(Some(LobbyOutcome::Play(identity))) => view.our_identity = identity.player_number,
+                    Ok(Some(LobbyOutcome::Spectate(info))) => {
+                        spectate(&mut terminal, &mut stream, &mut reader, codec, &info).await?;
+                        return restore_terminal();
+                    }
+                    Ok(None) => return restore_terminal(),
+                    Err(e) => {
+                        show_error(&mut terminal, &format!("与服务器的连接已断开：{}", e)).await?;
+                        return restore_terminal();
                     }
-                    Err(e) => eprintln!("Failed to receive message: {}", e),
                 }
-                match receive_message(&mut stream).await {
+                // 身份之后是会话令牌（服务器开启重连时）和初始棋盘
+                let initial_state = loop {
+                    match reader.read_message(&mut stream, codec).await {
+                        Ok(Message::SessionToken(token)) => session_token = Some(token),
+                        other => break other,
+                    }
+                };
+                match initial_state {
                     Ok(message) => match message {
                         Message::GameState(game_state) => {
-                            game_board.set_tiles(game_state.board1);
-                            other_board_ref.set_tiles(game_state.board2);
-                            let _ = tx_to_async.send("Connected successfully".to_string()).await;
+                            view.update(*game_state);
                             terminal.clear()?;
-                            terminal.draw(|f| {
-                                draw_double_board(
-                                    f,
-                                    game_board.get_tiles(),
-                                    other_board_ref.get_tiles(),
-                                );
-                            })?;
+                            draw_game(&mut terminal, &view, &[])?;
                         }
                         _ => {
-                            println!(
-                                "Received invalid message: {:?}, Should receive GameState",
-                                message
-                            );
-                            panic!("Client Should receive GameState");
+                            show_error(&mut terminal, &format!("应收到棋盘状态，实际收到 {:?}", message)).await?;
+                            return restore_terminal();
                         }
                     },
                     Err(e) => eprintln!("Failed to receive message: {}", e),
                 }
 
-                let mut buffer = Vec::new();
+                // 服务器定时发送心跳，太久没有收到任何消息说明连接已经断开，不必等 TCP 发现
+                let dead_timeout = Duration::from_secs(config::DEAD_CONNECTION_TIMEOUT_SECS);
+                let mut last_heard = Instant::now();
                 loop {
                     select! {
-                        input_result = io_manager.read_input_async(our_identity) => {
-                            match input_result {
+                        key = io_manager.read_key_async() => {
+                            // 轮到自己出块时按键用来选格和放置，q 仍然是认输
+                            if let Some(key) = key.filter(|&key| view.is_spawning() && key_direction(key) != Direction::Quit) {
+                                if let Some(placement) = view.choose_spawn(key) {
+                                    let _ = write_message(&mut stream, codec, &Message::SpawnTile(placement)).await;
+                                }
+                                draw_game(&mut terminal, &view, &[])?;
+                                continue;
+                            }
+                            match key.map(key_direction) {
                                 Some(action) => match action {
                                     Direction::None => {},
+                                    Direction::Quit if view.is_eliminated() => {
+                                        // 已经出局，不再观看剩下的对局
+                                        return restore_terminal();
+                                    },
                                     Direction::Quit => {
-                                        terminal.clear()?;
-                                        process::exit(0); // 终止进程
+                                        // 认输，等服务器发来结果后显示结算界面
+                                        // 发送失败说明连接已断开，读取一侧会处理重连
+                                        let _ = write_message(&mut stream, codec, &Message::Forfeit).await;
                                     },
                                     _ => {
                                         let player_action = PlayerAction { direction: action };
                                         let message = Message::PlayerAction(player_action);
-                                        let serialized = serialize_message(&message).unwrap();
-                                        stream.write_all(serialized.as_bytes()).await.unwrap();
+                                        let _ = write_message(&mut stream, codec, &message).await;
                                     }
                                 },
                                 None => continue,
                             }
                         },
-                        message_result = receive_message_with_buffer(&mut stream, &mut buffer) => {
+                        message_result = timeout_at(last_heard + dead_timeout, reader.read_message(&mut stream, codec)) => {
+                            let message_result = message_result
+                                .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "连接超时")));
+                            last_heard = Instant::now();
                             match message_result {
                                 Ok(message) => match message {
                                     Message::GameState(game_state) => {
-                                        game_board.set_tiles(game_state.board1);
-                                        other_board_ref.set_tiles(game_state.board2);
-                                        terminal.draw(|f| {
-                                            draw_double_board(f, game_board.get_tiles(), other_board_ref.get_tiles());
-                                        })?;
+                                        let pipes = view.update(*game_state);
+                                        draw_game(&mut terminal, &view, &pipes)?;
+                                    },
+                                    Message::Ping(sequence) => {
+                                        let _ = write_message(&mut stream, codec, &Message::Pong(sequence)).await;
+                                    },
+                                    Message::Latency(latency) => {
+                                        view.latency = latency;
+                                        draw_game(&mut terminal, &view, &[])?;
+                                    },
+                                    Message::Eliminated(elimination) => {
+                                        // 随后服务器会发来桥梁改连后的棋盘
+                                        if let Some(place) = view.eliminated.get_mut(elimination.player as usize - 1) {
+                                            *place = Some(elimination.place);
+                                        }
+                                    },
+                                    Message::OpponentLeft(_) => {
+                                        // 有人离开，服务器随后会发送结果
+                                    },
+                                    Message::PlayerIdentity(identity) => {
+                                        // 重连后服务器会重新告知身份
+                                        view.our_identity = identity.player_number;
+                                    },
+                                    Message::SessionToken(token) => session_token = Some(token),
+                                    Message::OpponentDisconnected(player) => {
+                                        draw_overlay(&mut terminal, &view, &format!("玩家{}掉线，等待其重新连接…", player))?;
+                                    },
+                                    Message::OpponentReconnected(_) => {
+                                        draw_game(&mut terminal, &view, &[])?;
+                                    },
+                                    Message::MatchOver(result) => {
+                                        show_match_result(&mut terminal, Some(&result), &view)?;
+                                        return restore_terminal();
                                     },
                                     _ => {
-                                        // println!("Received invalid message: {:?}, Should receive GameState", message);
-                                        panic!("Client Should receive GameState");
+                                        // 对局中不会收到其他消息，忽略
                                     }
                                 },
                                 Err(_) => {
-                                    // eprintln!("Failed to receive message: {}", e);
+                                    // 连接已断开，有会话令牌时尝试重连，成功后服务器会重新发送身份和棋盘
+                                    let resumed = match session_token {
+                                        Some(ref token) => reconnect(&mut terminal, address, preferred_codec, token, &view).await?,
+                                        None => None,
+                                    };
+                                    match resumed {
+                                        Some((new_stream, new_reader, new_codec)) => {
+                                            stream = new_stream;
+                                            reader = new_reader;
+                                            codec = new_codec;
+                                        }
+                                        None => {
+                                            show_match_result(&mut terminal, None, &view)?;
+                                            return restore_terminal();
+                                        }
+                                    }
                                 },
                             }
                         },
@@ -247,9 +855,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
             Err(e) => {
-                a += 1;
-                // println!("{}, Failed to connect: {:?}", a, e);
-                if a == config::CLIENT_MAX_RETRIES {
+                if attempt == config::CLIENT_MAX_RETRIES {
                     let _ = tx_to_async
                         .send(format!("Failed to connect: {:?}", e))
                         .await;
@@ -3,4 +3,78 @@ pub const SERVER_PORT: &str = "8080";
 pub const SERVER_IP: &str = "127.0.0.1";
 
 pub const CLIENT_MAX_RETRIES: usize = 5;
-pub const CLIENT_MAX_RETRIES_PER_REQUEST: u64 = 1;
\ No newline at end of file
+pub const CLIENT_MAX_RETRIES_PER_REQUEST: u64 = 1;
+
+// 网络消息单帧最大字节数，超过时视为协议错误并断开连接
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+// 连接后等待客户端 Hello 的最长时间
+pub const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+// 玩家掉线后为其保留对局的时间，超时未重连则判负
+pub const SESSION_GRACE_PERIOD_SECS: u64 = 30;
+// 服务器向玩家发送心跳的间隔，同时用来测量往返延迟
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 2;
+// 这么久没有收到对方任何数据就认为连接已断开，不必等 TCP 自己发现半开的连接
+pub const DEAD_CONNECTION_TIMEOUT_SECS: u64 = 10;
+// 玩家这么久没有移动则判负
+pub const IDLE_TIMEOUT_SECS: u64 = 120;
+
+// 服务器数据文件，保存排位赛等级分
+pub const SERVER_DATA_FILE: &str = "server_data.json";
+// 客户端保存服务器发放的玩家密钥的文件
+pub const PLAYER_KEYS_FILE: &str = "player_keys.json";
+// 排位队列每隔多久尝试配对一次
+pub const MATCHMAKING_INTERVAL_MS: u64 = 500;
+// 排位赛可接受的等级分差：刚排队时为 BASE，每等一秒放宽 PER_SEC，最多放宽到 MAX
+pub const RATING_GAP_BASE: f64 = 50.0;
+pub const RATING_GAP_PER_SEC: f64 = 10.0;
+pub const RATING_GAP_MAX: f64 = 1000.0;
+
+// 观战延迟的上限
+pub const SPECTATOR_MAX_DELAY_SECS: u32 = 600;
+
+pub const PUZZLE_PROGRESS_FILE: &str = "puzzle_progress.json";
+
+// 一局对局的人数范围，三人及以上时棋盘首尾相连成环
+pub const MATCH_MIN_PLAYERS: u8 = 2;
+pub const MATCH_MAX_PLAYERS: u8 = 8;
+
+// 竞速玩法默认的目标瓷砖和时限，房主可以另行指定，时限须在 MIN 和 MAX 之间
+pub const RACE_DEFAULT_TARGET: u32 = 1024;
+pub const RACE_DEFAULT_TIME_LIMIT_SECS: u32 = 300;
+pub const RACE_MIN_TIME_LIMIT_SECS: u32 = 1;
+pub const RACE_MAX_TIME_LIMIT_SECS: u32 = 3600;
+
+// 垃圾攻击：合并出不小于 BIG_MERGE 的瓷砖或一步合并 CHAIN_MERGES 次以上时攻击对手，
+// 垃圾在对手的队列中等待 DELAY_MS 后以 TILE 落在其棋盘的空格上
+pub const GARBAGE_BIG_MERGE: u32 = 64;
+pub const GARBAGE_CHAIN_MERGES: usize = 3;
+pub const GARBAGE_DELAY_MS: u64 = 3000;
+pub const GARBAGE_TILE: u32 = 2;
+
+// 合作玩法：投票移动时第一票投出后等待其余玩家的时间，排行榜保留的记录条数
+pub const COOP_VOTE_WINDOW_MS: u64 = 1500;
+pub const COOP_LEADERBOARD_SIZE: usize = 10;
+
+// 回合制玩法默认的棋钟和每步加时，房主可以另行指定，须在 MIN、MAX 的范围之内
+pub const TURN_CLOCK_DEFAULT_SECS: u32 = 180;
+pub const TURN_INCREMENT_DEFAULT_SECS: u32 = 2;
+pub const TURN_CLOCK_MIN_SECS: u32 = 10;
+pub const TURN_CLOCK_MAX_SECS: u32 = 3600;
+pub const TURN_INCREMENT_MAX_SECS: u32 = 60;
+
+// 反派出块玩法：出块方默认的出块时限，超时由服务器随机出块，房主可以另行指定，须在 MIN、MAX 的范围之内
+pub const SPAWNER_DEFAULT_SPAWN_SECS: u32 = 5;
+pub const SPAWNER_MIN_SPAWN_SECS: u32 = 1;
+pub const SPAWNER_MAX_SPAWN_SECS: u32 = 60;
+
+// 桥梁时间表：开启和关闭交替，每段持续的毫秒数在 MIN、MAX 之间随机，每次开启可送出的瓷砖数也在范围内随机
+pub const BRIDGE_OPEN_MS_MIN: u64 = 8000;
+pub const BRIDGE_OPEN_MS_MAX: u64 = 20000;
+pub const BRIDGE_CLOSED_MS_MIN: u64 = 3000;
+pub const BRIDGE_CLOSED_MS_MAX: u64 = 8000;
+pub const BRIDGE_SENDING_LIMIT_MIN: u32 = 2;
+pub const BRIDGE_SENDING_LIMIT_MAX: u32 = 8;
+// 相邻两块棋盘之间的桥梁数，不超过棋盘边长，每行（列）至多一座，桥梁开启时会换到空着的行
+pub const BRIDGES_PER_LINK: usize = 2;
+// 每次开启时桥梁为双向的概率，其余时候随机向左或向右单向
+pub const BRIDGE_TWO_WAY_PROBABILITY: f64 = 0.3;
@@ -0,0 +1,135 @@
+use std::io;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+use tokio::time::timeout;
+
+use crate::config;
+use crate::protocol::{negotiate, write_message, Codec, FrameReader, Hello, Message};
+use crate::session::SessionRegistry;
+
+// 每条连接待发送消息的队列长度
+const OUTGOING_QUEUE_SIZE: usize = 32;
+
+/// 握手完成、等待匹配的客户端
+pub struct PendingClient {
+    pub socket: TcpStream,
+    pub reader: FrameReader, // 握手时可能多读到的数据留在读取器中
+    pub codec: Codec,        // 与该客户端协商出的编码，两名玩家可以不同
+    pub resume_token: Option<String>, // 重连的客户端带有会话令牌，应交还给原来的对局
+    pub permit: Option<OwnedSemaphorePermit>, // 服务器的连接名额，连接关闭时才归还
+    pub rated_name: Option<String>, // 经排位队列配对的玩家名，对局结果计入等级分
+}
+
+/// 连接任务发给对局的事件，附带连接编号
+// 对局开始时玩家1、2的连接编号为0、1，重连后的新连接由对局另行编号，旧连接迟到的事件据此丢弃
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    Message(usize, Message),
+    Closed(usize, String), // 连接断开或帧格式错误，附带原因
+}
+
+// 握手：读取客户端的 Hello，检查版本并协商编码，Hello 与应答都使用 JSON
+// 拒绝时先把原因发给客户端再断开，带有未知会话令牌的重连也会被拒绝；features 为服务器开启的功能，原样写进 Welcome
+pub async fn handshake(
+    mut socket: TcpStream,
+    preferred: Codec,
+    board_size: usize,
+    features: &[&str],
+    sessions: &SessionRegistry,
+) -> Option<PendingClient> {
+    let mut reader = FrameReader::new();
+    let limit = Duration::from_secs(config::HANDSHAKE_TIMEOUT_SECS);
+    let hello: Result<Hello, String> = match timeout(limit, reader.read_message(&mut socket, Codec::Json)).await {
+        Ok(Ok(Message::Hello(hello))) => Ok(hello),
+        Ok(Ok(message)) => Err(format!("握手失败：第一条消息应为 Hello，收到 {:?}", message)),
+        Ok(Err(e)) => {
+            eprintln!("Handshake failed: {}", e);
+            return None;
+        }
+        Err(_) => Err("握手超时".to_string()),
+    };
+
+    let server_name = format!("rust2048-server {}", env!("CARGO_PKG_VERSION"));
+    let accepted = hello.and_then(|hello| {
+        println!("Hello from {} (protocol {})", hello.client_name, hello.version);
+        if let Some(ref token) = hello.resume_token {
+            if sessions.find(token).is_none() {
+                return Err("会话已过期，无法恢复对局".to_string());
+            }
+        }
+        let welcome = negotiate(&hello, &server_name, preferred, features, board_size)?;
+        Ok((welcome, hello.resume_token))
+    });
+    match accepted {
+        Ok((welcome, resume_token)) => {
+            let codec = welcome.codec;
+            write_message(&mut socket, Codec::Json, &Message::Welcome(welcome)).await.ok()?;
+            Some(PendingClient { socket, reader, codec, resume_token, permit: None, rated_name: None })
+        }
+        Err(reason) => {
+            eprintln!("Rejected client: {}", reason);
+            let _ = write_message(&mut socket, Codec::Json, &Message::Rejected(reason)).await;
+            let _ = socket.shutdown().await;
+            None
+        }
+    }
+}
+
+/// 把连接拆成读、写两半，各由一个任务负责
+// 读任务把收到的消息连同连接编号发到 events，写任务从返回的发送端取消息写出
+// 读写互不等待，一方空闲不会卡住另一方的广播；返回的发送端全部丢弃后写任务关闭连接
+// 对局期间服务器定时发送心跳，客户端总会回复，所以读写超过 DEAD_CONNECTION_TIMEOUT_SECS 都视为连接已断开
+pub fn spawn_connection(client: PendingClient, connection: usize, events: mpsc::Sender<ConnectionEvent>) -> mpsc::Sender<Message> {
+    let PendingClient { socket, mut reader, codec, permit, .. } = client;
+    let (mut read_half, mut write_half) = socket.into_split();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel::<Message>(OUTGOING_QUEUE_SIZE);
+    let limit = Duration::from_secs(config::DEAD_CONNECTION_TIMEOUT_SECS);
+
+    tokio::spawn(async move {
+        loop {
+            let frame = tokio::select! {
+                frame = timeout(limit, reader.read_frame(&mut read_half)) => frame,
+                _ = events.closed() => break, // 对局已结束，不再读取
+            };
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "连接超时")),
+            };
+            match frame {
+                Ok(frame) => match codec.decode(&frame) {
+                    Ok(message) => {
+                        if events.send(ConnectionEvent::Message(connection, message)).await.is_err() {
+                            break; // 对局已结束
+                        }
+                    }
+                    // 帧已完整读出，解析失败只丢弃这一条
+                    Err(e) => eprintln!("Failed to parse input from connection {}: {}", connection, e),
+                },
+                Err(e) => {
+                    let _ = events.send(ConnectionEvent::Closed(connection, e.to_string())).await;
+                    break;
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(message) = outgoing_rx.recv().await {
+            let written = match timeout(limit, write_message(&mut write_half, codec, &message)).await {
+                Ok(written) => written,
+                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "连接超时")),
+            };
+            if let Err(e) = written {
+                eprintln!("Failed to send to connection {}: {}", connection, e);
+                break;
+            }
+        }
+        let _ = timeout(limit, write_half.shutdown()).await;
+        // 写任务结束时连接才算关闭，此时归还名额
+        drop(permit);
+    });
+
+    outgoing_tx
+}
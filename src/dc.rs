@@ -16,12 +16,53 @@ use std::io;
 
 const TILE_WIDTH: u16 = 6;  // 方块的宽度
 const TILE_HEIGHT: u16 = 3;  // 方块的高度
+const PIPE_COLOR: Color = Color::Rgb(255, 0, 127);  // 管道颜色
 
-pub fn draw_double_board<B: Backend>(frame: &mut Frame<B>, board1: &Vec<Vec<u32>>, board2: &Vec<Vec<u32>>, pipe_data: &[u8]) {
+/// pipes 为每条要画的管道所在的行（从0开始）和正经过它的瓷砖
+pub fn draw_double_board<B: Backend>(frame: &mut Frame<B>, board1: &Vec<Vec<u32>>, board2: &Vec<Vec<u32>>, pipes: &[(usize, &[u32])]) {
     let size = frame.size();
     let block = Block::default().title("Double 2048 Game").borders(Borders::ALL);
     frame.render_widget(block, size);
 
+    let (board1_area, _, board2_area) = double_board_areas(size, 0);
+    draw_board(frame, board1_area, board1);
+    for &(pipe_row, pipe_data) in pipes {
+        let (_, pipe_area, _) = double_board_areas(size, pipe_row);
+        draw_pipe(frame, pipe_area, pipe_data);
+    }
+    draw_board(frame, board2_area, board2);
+}
+
+/// 两个棋盘上下叠放，中间是竖直的桥梁，pipes 为每条要画的管道所在的列（从0开始）和正经过它的瓷砖
+pub fn draw_stacked_board<B: Backend>(frame: &mut Frame<B>, board1: &Vec<Vec<u32>>, board2: &Vec<Vec<u32>>, pipes: &[(usize, &[u32])]) {
+    let size = frame.size();
+    let block = Block::default().title("Double 2048 Game").borders(Borders::ALL);
+    frame.render_widget(block, size);
+
+    let (board1_area, _, board2_area) = stacked_board_areas(size, 0);
+    draw_board(frame, board1_area, board1);
+    for &(pipe_column, pipe_data) in pipes {
+        let (_, pipe_area, _) = stacked_board_areas(size, pipe_column);
+        draw_vertical_pipe(frame, pipe_area, pipe_data);
+    }
+    draw_board(frame, board2_area, board2);
+}
+
+/// 在两个棋盘上方各显示一行说明，例如双方的网络延迟，需在 draw_double_board 或 draw_stacked_board 之后调用
+pub fn draw_board_captions<B: Backend>(frame: &mut Frame<B>, captions: [&str; 2], stacked: bool) {
+    let (board1_area, _, board2_area) = match stacked {
+        false => double_board_areas(frame.size(), 0),
+        true => stacked_board_areas(frame.size(), 0),
+    };
+    for (area, caption) in [board1_area, board2_area].into_iter().zip(captions) {
+        let caption_area = Rect::new(area.x, area.y - 1, area.width, 1);
+        let para = Paragraph::new(caption).style(Style::default().fg(Color::DarkGray));
+        frame.render_widget(para, caption_area);
+    }
+}
+
+// 两个棋盘和管道的位置和尺寸，管道与第 pipe_row 行对齐
+fn double_board_areas(size: Rect, pipe_row: usize) -> (Rect, Rect, Rect) {
     let pipe_tiles_count = 5;  // 管道由五个格子组成
     let pipe_width = TILE_WIDTH * pipe_tiles_count;  // 管道的宽度为五个格子宽
 
@@ -32,25 +73,118 @@ pub fn draw_double_board<B: Backend>(frame: &mut Frame<B>, board1: &Vec<Vec<u32>
 
     // 定义两个棋盘和管道的位置和尺寸
     let board1_area = Rect::new(start_x, size.y + 2, board_width, TILE_HEIGHT * 4);
-    let pipe_area = Rect::new(start_x + board_width, size.y + 2 + TILE_HEIGHT * 2, pipe_width, TILE_HEIGHT);  // 管道在第三行
+    // 瓷砖每行间隔一行，draw_pipe 还会再下移两行，与棋盘的第一行对齐
+    let pipe_area = Rect::new(start_x + board_width, size.y + (TILE_HEIGHT + 1) * pipe_row as u16, pipe_width, TILE_HEIGHT);
     let board2_area = Rect::new(start_x + board_width + pipe_width, size.y + 2, board_width, TILE_HEIGHT * 4);
+    (board1_area, pipe_area, board2_area)
+}
 
-    draw_board(frame, board1_area, board1);
-    draw_pipe(frame, pipe_area, pipe_data);  // 现在传递数据
-    draw_board(frame, board2_area, board2);
+// 上下叠放时两个棋盘和管道的位置和尺寸，管道与第 pipe_column 列对齐，每格只占一行
+fn stacked_board_areas(size: Rect, pipe_column: usize) -> (Rect, Rect, Rect) {
+    let board_width = TILE_WIDTH * 5;
+    let board_height = (TILE_HEIGHT + 1) * 4 - 1;
+    let pipe_height = 5;  // 管道由五个格子组成，每格一行
+    let start_x = size.x + size.width.saturating_sub(board_width) / 2;
+
+    let board1_area = Rect::new(start_x, size.y + 2, board_width, board_height);
+    // 管道上下各空一行，第二块棋盘上方再留一行说明
+    let pipe_area = tile_rect(board1_area, 0, pipe_column);
+    let pipe_area = Rect::new(pipe_area.x, board1_area.y + board_height + 1, TILE_WIDTH, pipe_height);
+    let board2_area = Rect::new(start_x, pipe_area.y + pipe_height + 2, board_width, board_height);
+    (board1_area, pipe_area, board2_area)
 }
 
+/// 三名及以上玩家时的布局：每块棋盘缩成每格一行，按玩家顺序排成网格，captions 显示在各自棋盘上方
+/// bridges 为每座开启的桥梁所在的棋盘、行和标记，标记画在该棋盘右侧与那一行对齐，表示通向右边的邻居
+/// label 和 pipes 画在网格下方，pipes 为这一步经过的每座桥梁的说明和经过它的瓷砖，每座一行
+pub fn draw_multi_board<B: Backend>(
+    frame: &mut Frame<B>,
+    boards: &[Vec<Vec<u32>>],
+    captions: &[String],
+    bridges: &[(usize, usize, &str)],
+    label: &str,
+    pipes: &[(String, &[u32])],
+) {
+    let size = frame.size();
+    let block = Block::default().title("2048 Battle").borders(Borders::ALL);
+    frame.render_widget(block, size);
 
-pub fn draw_board<B: Backend>(frame: &mut Frame<B>, area: Rect, board: &Vec<Vec<u32>>) {
-    let tile_width = TILE_WIDTH;
-    let tile_height = TILE_HEIGHT;
-    let gap: u16 = 1;  // 调整间隙尺寸以达到视觉平衡
+    let areas = multi_board_areas(size, boards);
+    for (i, (area, board)) in areas.iter().zip(boards).enumerate() {
+        let caption = captions.get(i).map(String::as_str).unwrap_or("");
+        let caption_area = Rect::new(area.x, area.y.saturating_sub(1), area.width, 1).intersection(size);
+        frame.render_widget(Paragraph::new(caption).style(Style::default().fg(Color::DarkGray)), caption_area);
+        draw_compact_board(frame, *area, board);
+    }
+    // 棋盘右侧与下一块棋盘之间的空隙里画桥梁标记
+    for &(board, row, marker) in bridges {
+        if let Some(area) = areas.get(board) {
+            let marker_area = Rect::new(area.x + area.width - 1, area.y + row as u16, 2, 1).intersection(size);
+            frame.render_widget(Paragraph::new(marker).style(Style::default().fg(Color::White).bg(PIPE_COLOR)), marker_area);
+        }
+    }
 
+    // 说明文字在最后一行棋盘下方，经过桥梁的瓷砖逐行画在它下面
+    let bottom = areas.iter().map(|area| area.y + area.height).max().unwrap_or(size.y + 2);
+    let x = areas.first().map_or(size.x + 1, |area| area.x);
+    let label_area = Rect::new(x, bottom + 1, size.width.saturating_sub(x), 1).intersection(size);
+    frame.render_widget(Paragraph::new(label).style(Style::default().fg(Color::DarkGray)), label_area);
+    for (i, (pipe_label, pipe_data)) in pipes.iter().enumerate() {
+        draw_compact_pipe(frame, x, bottom + 2 + i as u16, pipe_label, pipe_data);
+    }
+}
+
+// 紧凑布局的管道只占一行：先是说明，后面是五个格子，正经过的瓷砖依次显示在格子里
+fn draw_compact_pipe<B: Backend>(frame: &mut Frame<B>, x: u16, y: u16, label: &str, data: &[u32]) {
+    let bounds = frame.size();
+    let label_width = TILE_WIDTH * 3;
+    frame.render_widget(Paragraph::new(label), Rect::new(x, y, label_width, 1).intersection(bounds));
+    for i in 0..5 {
+        let tile_rect = Rect::new(x + label_width + i as u16 * TILE_WIDTH, y, TILE_WIDTH, 1).intersection(bounds);
+        let number = data.get(i).map_or(String::new(), |num| num.to_string());
+        let para = Paragraph::new(number).alignment(Alignment::Center).style(Style::default().fg(Color::White).bg(PIPE_COLOR));
+        frame.render_widget(para, tile_rect);
+    }
+}
+
+// 紧凑棋盘在网格中的位置，每块棋盘上方留一行说明，下方留一行空隙
+fn multi_board_areas(size: Rect, boards: &[Vec<Vec<u32>>]) -> Vec<Rect> {
+    let board_size = boards.first().map_or(4, |board| board.len()) as u16;
+    let board_width = (TILE_WIDTH + 1) * board_size;
+    let cell_width = board_width + 2;
+    let cell_height = board_size + 2;
+    let columns = (size.width.saturating_sub(2) / cell_width).max(1);
+    let total_width = cell_width * columns.min(boards.len() as u16);
+    let start_x = size.x + size.width.saturating_sub(total_width) / 2;
+    (0..boards.len() as u16)
+        .map(|i| {
+            let (row, column) = (i / columns, i % columns);
+            Rect::new(start_x + column * cell_width, size.y + 2 + row * cell_height, board_width, board_size)
+        })
+        .collect()
+}
+
+// 每格只占一行，数字用半角以免超出格子
+fn draw_compact_board<B: Backend>(frame: &mut Frame<B>, area: Rect, board: &[Vec<u32>]) {
+    let bounds = frame.size();
     for (i, row) in board.iter().enumerate() {
         for (j, &num) in row.iter().enumerate() {
-            let x = area.x + j as u16 * (tile_width + gap + 1);
-            let y = area.y + i as u16 * (tile_height + gap);
-            let tile_rect = Rect::new(x, y, tile_width, tile_height);
+            let tile_rect = Rect::new(area.x + j as u16 * (TILE_WIDTH + 1), area.y + i as u16, TILE_WIDTH, 1).intersection(bounds);
+            let bg_color = get_bg_color(num);
+            let fg_color = if num > 4 { Color::White } else { Color::Black };
+            let number = if num > 0 { num.to_string() } else { String::new() };
+            let para = Paragraph::new(number)
+                .alignment(Alignment::Center)
+                .style(Style::default().fg(fg_color).bg(bg_color).add_modifier(Modifier::BOLD));
+            frame.render_widget(para, tile_rect);
+        }
+    }
+}
+
+pub fn draw_board<B: Backend>(frame: &mut Frame<B>, area: Rect, board: &Vec<Vec<u32>>) {
+    for (i, row) in board.iter().enumerate() {
+        for (j, &num) in row.iter().enumerate() {
+            let tile_rect = tile_rect(area, i, j);
 
             let bg_color = get_bg_color(num);
             let fg_color = if num > 4 { Color::White } else { Color::Black };
@@ -67,30 +201,46 @@ pub fn draw_board<B: Backend>(frame: &mut Frame<B>, area: Rect, board: &Vec<Vec<
     }
 }
 
-pub fn draw_pipe<B: Backend>(frame: &mut Frame<B>, area: Rect, data: &[u8]) {
-    let pipe_color = Color::Rgb(255, 0, 127);  // 管道颜色
+/// 计算棋盘第 i 行第 j 列瓷砖所在的矩形，编辑器等需要定位单个瓷砖的地方共用
+pub fn tile_rect(area: Rect, i: usize, j: usize) -> Rect {
+    let gap: u16 = 1;  // 调整间隙尺寸以达到视觉平衡
+    let x = area.x + j as u16 * (TILE_WIDTH + gap + 1);
+    let y = area.y + i as u16 * (TILE_HEIGHT + gap);
+    Rect::new(x, y, TILE_WIDTH, TILE_HEIGHT)
+}
 
+// data 为正经过桥梁的瓷砖数值
+pub fn draw_pipe<B: Backend>(frame: &mut Frame<B>, area: Rect, data: &[u32]) {
     // 总是绘制5个格子
     for i in 0..5 {
         let x = area.x + i as u16 * TILE_WIDTH;  // 计算每个格子的横坐标
-        let y = area.y;  // 维持在第三行位置
-        let tile_rect = Rect::new(x, y + 2, TILE_WIDTH, TILE_HEIGHT);  // 定义格子的位置和尺寸
+        let y = area.y;  // 与桥梁所在的行对齐
+        let tile_rect = Rect::new(x, y + 2, TILE_WIDTH, TILE_HEIGHT).intersection(frame.size());  // 定义格子的位置和尺寸，超出窗口的部分不画
 
         let content = if i < data.len() {
-            format_number(data[i] as u32)  // 格式化存在的数据
+            format_number(data[i])  // 格式化存在的数据
         } else {
             String::from(" ")  // 数据不存在则显示空格
         };
 
         let para = Paragraph::new(content)
             .alignment(Alignment::Center)
-            .block(Block::default().borders(Borders::NONE).style(Style::default().bg(pipe_color)))
+            .block(Block::default().borders(Borders::NONE).style(Style::default().bg(PIPE_COLOR)))
             .style(Style::default().fg(Color::White));  // 设置文字和背景颜色
 
         frame.render_widget(para, tile_rect);  // 将段落渲染到对应的矩形区域
     }
 }
 
+// 竖直的管道，五个格子从上到下排列，data 为正经过桥梁的瓷砖数值
+fn draw_vertical_pipe<B: Backend>(frame: &mut Frame<B>, area: Rect, data: &[u32]) {
+    for i in 0..area.height as usize {
+        let tile_rect = Rect::new(area.x, area.y + i as u16, area.width, 1).intersection(frame.size());
+        let number = data.get(i).map_or(String::new(), |num| num.to_string());
+        let para = Paragraph::new(number).alignment(Alignment::Center).style(Style::default().fg(Color::White).bg(PIPE_COLOR));
+        frame.render_widget(para, tile_rect);
+    }
+}
 
 fn format_number(num: u32) -> String {
     num.to_string().chars()
@@ -0,0 +1,188 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use rand::Rng;
+use std::io;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame, Terminal,
+};
+
+use crate::dc::{draw_board, tile_rect};
+use crate::GameBoard;
+
+// 随机填充时使用的最大指数，2^7 = 128，避免生成难以游玩的局面
+const RANDOM_FILL_MAX_EXPONENT: u32 = 7;
+// 瓷砖允许的最大指数，与记谱一致
+const MAX_EXPONENT: u32 = 31;
+
+/// 编辑器退出时的结果
+pub enum EditorOutcome {
+    Play(GameBoard),         // 从编辑好的局面开始游戏
+    Quit(Option<String>),    // 直接退出，附带最后一次导出的记谱
+}
+
+/// 棋盘编辑器，光标在棋盘上移动，数字键设置瓷砖指数
+pub struct Editor {
+    board: GameBoard,
+    cursor: (usize, usize), // (行, 列)
+    exported: Option<String>,
+    message: String,
+}
+
+impl Editor {
+    pub fn new(board: GameBoard) -> Self {
+        Self {
+            board,
+            cursor: (0, 0),
+            exported: None,
+            message: String::from("编辑完成后按 Enter 开始游戏"),
+        }
+    }
+
+    // 处理一次按键，返回 Some 表示编辑结束
+    pub fn handle_key(&mut self, code: KeyCode) -> Option<EditorOutcome> {
+        let size = self.board.size();
+        let (row, col) = self.cursor;
+        match code {
+            KeyCode::Up => self.cursor.0 = row.saturating_sub(1),
+            KeyCode::Down => self.cursor.0 = (row + 1).min(size - 1),
+            KeyCode::Left => self.cursor.1 = col.saturating_sub(1),
+            KeyCode::Right => self.cursor.1 = (col + 1).min(size - 1),
+            KeyCode::Char(ch @ '0'..='9') => {
+                // 数字键直接设置指数，0 为清空
+                self.set_exponent(ch.to_digit(10).unwrap());
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                let exponent = self.current_exponent();
+                self.set_exponent((exponent + 1).min(MAX_EXPONENT));
+            }
+            KeyCode::Char('-') => {
+                let exponent = self.current_exponent();
+                self.set_exponent(exponent.saturating_sub(1));
+            }
+            KeyCode::Char('c') => {
+                self.board.reset_board();
+                self.message = String::from("棋盘已清空");
+            }
+            KeyCode::Char('r') => {
+                self.random_fill();
+                self.message = String::from("已随机填充");
+            }
+            KeyCode::Char('e') => {
+                match self.board.to_notation() {
+                    Ok(notation) => {
+                        self.message = format!("导出: {}", notation);
+                        self.exported = Some(notation);
+                    }
+                    Err(e) => self.message = format!("无法导出: {}", e),
+                }
+            }
+            KeyCode::Enter => {
+                if self.board.return_score().1 == 0 {
+                    self.message = String::from("空棋盘无法开始游戏");
+                } else {
+                    return Some(EditorOutcome::Play(self.board.clone()));
+                }
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                return Some(EditorOutcome::Quit(self.exported.clone()));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn current_exponent(&self) -> u32 {
+        let (row, col) = self.cursor;
+        let tile = self.board.get_tiles()[row][col];
+        if tile == 0 {
+            0
+        } else {
+            tile.ilog2()
+        }
+    }
+
+    fn set_exponent(&mut self, exponent: u32) {
+        let (row, col) = self.cursor;
+        let tiles = self.board.get_tiles_mut();
+        tiles[row][col] = if exponent == 0 { 0 } else { 1 << exponent };
+    }
+
+    // 随机填充，每格为空或 2 到 2^RANDOM_FILL_MAX_EXPONENT 之间的瓷砖
+    fn random_fill(&mut self) {
+        let mut rng = rand::thread_rng();
+        let size = self.board.size();
+        let mut tiles = vec![vec![0; size]; size];
+        for row in tiles.iter_mut() {
+            for tile in row.iter_mut() {
+                let exponent = rng.gen_range(0..=RANDOM_FILL_MAX_EXPONENT);
+                *tile = if exponent == 0 { 0 } else { 1 << exponent };
+            }
+        }
+        self.board.set_tiles(tiles);
+    }
+}
+
+/// 运行编辑器，直到开始游戏或退出
+pub fn run_editor<B: Backend>(
+    terminal: &mut Terminal<B>,
+    board: GameBoard,
+) -> Result<EditorOutcome, io::Error> {
+    let mut editor = Editor::new(board);
+    loop {
+        terminal.draw(|f| draw_editor(f, &editor))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(outcome) = editor.handle_key(key.code) {
+                return Ok(outcome);
+            }
+        }
+    }
+}
+
+fn draw_editor<B: Backend>(frame: &mut Frame<B>, editor: &Editor) {
+    let size = frame.size();
+    let block = Block::default().title("棋盘编辑器").borders(Borders::ALL);
+    frame.render_widget(block, size);
+
+    // 复用双人界面的棋盘渲染，左侧绘制棋盘
+    let tiles = editor.board.get_tiles();
+    let board_area = Rect::new(size.x + 3, size.y + 2, size.width.saturating_sub(6), size.height.saturating_sub(4));
+    draw_board(frame, board_area, tiles);
+
+    // 光标使用黄色边框框住当前瓷砖
+    let (row, col) = editor.cursor;
+    let tile = tile_rect(board_area, row, col);
+    let cursor_rect = Rect::new(tile.x.saturating_sub(1), tile.y.saturating_sub(1), tile.width + 2, tile.height + 2);
+    let cursor = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD));
+    frame.render_widget(cursor, cursor_rect);
+
+    // 右侧为操作说明和状态
+    let last_tile = tile_rect(board_area, tiles.len() - 1, tiles.len() - 1);
+    let help_x = last_tile.x + last_tile.width + 4;
+    let help_area = Rect::new(help_x, size.y + 1, size.width.saturating_sub(help_x + 1), size.height.saturating_sub(2));
+    let style = Style::default().fg(Color::White);
+    let text = vec![
+        Spans::from(Span::styled("方向键 - 移动光标", style)),
+        Spans::from(Span::styled("0-9 - 设置指数 (0为清空)", style)),
+        Spans::from(Span::styled("+/- - 指数加减", style)),
+        Spans::from(Span::styled("C - 清空棋盘", style)),
+        Spans::from(Span::styled("R - 随机填充", style)),
+        Spans::from(Span::styled("E - 导出记谱", style)),
+        Spans::from(Span::styled("Enter - 开始游戏", style)),
+        Spans::from(Span::styled("Q - 退出", style)),
+        Spans::from(""),
+        Spans::from(Span::styled(editor.message.clone(), Style::default().fg(Color::Yellow))),
+    ];
+    let paragraph = Paragraph::new(text)
+        .block(Block::default().title("操作说明").borders(Borders::ALL))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(paragraph, help_area);
+}
@@ -41,6 +41,79 @@ impl Direction {
     }
 }
 
+/// 棋盘的八种对称变换（二面体群），x为列、y为行
+// 与 game.rs 中 Grid::flip 类似，但作用于 GameBoard 的二维数组，且支持任意边长
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    RotateClockwise,
+    Rotate180,
+    RotateCounterClockwise,
+    FlipHorizontal, // 左右翻转
+    FlipVertical,   // 上下翻转
+    Transpose,      // 沿主对角线翻转
+    AntiTranspose,  // 沿副对角线翻转
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::RotateClockwise,
+        Symmetry::Rotate180,
+        Symmetry::RotateCounterClockwise,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    // 边长为 size 的棋盘上，原来在 pos 的瓷砖变换后所在的位置
+    pub fn map_position(&self, pos: Position, size: usize) -> Position {
+        let s = size - 1;
+        let (x, y) = (pos.x, pos.y);
+        let (x, y) = match self {
+            Symmetry::Identity => (x, y),
+            Symmetry::RotateClockwise => (s - y, x),
+            Symmetry::Rotate180 => (s - x, s - y),
+            Symmetry::RotateCounterClockwise => (y, s - x),
+            Symmetry::FlipHorizontal => (s - x, y),
+            Symmetry::FlipVertical => (x, s - y),
+            Symmetry::Transpose => (y, x),
+            Symmetry::AntiTranspose => (s - y, s - x),
+        };
+        Position { x, y }
+    }
+
+    // 变换后的方向：原棋盘向 direction 移动再变换，等于先变换再向返回的方向移动
+    pub fn map_direction(&self, direction: Direction) -> Direction {
+        // 在3x3棋盘上把中心旁边的格子变换一次，看它落到中心的哪一侧
+        let neighbour = match direction {
+            Direction::Up => Position { x: 1, y: 0 },
+            Direction::Down => Position { x: 1, y: 2 },
+            Direction::Left => Position { x: 0, y: 1 },
+            Direction::Right => Position { x: 2, y: 1 },
+            Direction::Quit | Direction::None => return direction,
+        };
+        match self.map_position(neighbour, 3) {
+            Position { x: 1, y: 0 } => Direction::Up,
+            Position { x: 1, y: 2 } => Direction::Down,
+            Position { x: 0, y: 1 } => Direction::Left,
+            Position { x: 2, y: 1 } => Direction::Right,
+            _ => unreachable!("对称变换保持3x3棋盘的中心不动"),
+        }
+    }
+
+    // 逆变换，只有两个旋转互为逆，其余变换的逆是自身
+    pub fn inverse(&self) -> Self {
+        match self {
+            Symmetry::RotateClockwise => Symmetry::RotateCounterClockwise,
+            Symmetry::RotateCounterClockwise => Symmetry::RotateClockwise,
+            other => *other,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct GameBoard {
     tiles: Vec<Vec<u32>>,        // 用二维向量表示棋盘
     history: Vec<Vec<Vec<u32>>>, // 存储历史棋盘状态
@@ -50,15 +123,38 @@ pub struct GameBoard {
 
 impl GameBoard {
     pub fn new() -> Self {
+        Self::with_size(4) // 默认为4x4的棋盘
+    }
+
+    // 生成指定边长的空棋盘，记谱导入的局面可能不是4x4
+    pub fn with_size(size: usize) -> Self {
         Self {
-            tiles: vec![vec![0; 4]; 4], // 默认为4x4的棋盘
-            history: Vec::new(),        // 初始化空的历史记录
+            tiles: vec![vec![0; size]; size],
+            history: Vec::new(), // 初始化空的历史记录
             check_should_be_used_after_spawn: false,
             reach_2048: false,
         }
     }
 
+    // 直接用已有的二维数组构造棋盘，边长由数组决定
+    pub fn from_tiles(tiles: Vec<Vec<u32>>) -> Self {
+        let mut board = Self::with_size(tiles.len());
+        board.tiles = tiles;
+        board
+    }
+
+    // 棋盘边长
+    pub fn size(&self) -> usize {
+        self.tiles.len()
+    }
+
     pub fn spawn_tile(&mut self) {
+        let mut rng = rand::thread_rng();
+        self.spawn_tile_with_rng(&mut rng);
+    }
+
+    // 使用指定的随机数生成器生成新tile，谜题用固定种子保证生成序列可以复现
+    pub fn spawn_tile_with_rng<R: Rng>(&mut self, rng: &mut R) {
         self.check_should_be_used_after_spawn = true;
         // 先检查是否还有空位
         if !self.if_have_empty_tile() {
@@ -67,15 +163,14 @@ impl GameBoard {
 
         // 在棋盘上随机位置生成新的数字块
         // 10%概率生成4 90%概率生成2
-        let mut rng = rand::thread_rng();
         let mut num = rng.gen_range(0..9); // 生成一个0到9之间的随机整数
         match num {
             0 => num = 4,
             _ => num = 2,
         }
         let mut empty_space = vec![];
-        for i in 0..4 {
-            for j in 0..4 {
+        for i in 0..self.size() {
+            for j in 0..self.size() {
                 if self.tiles[i][j] == 0 {
                     empty_space.push((i, j));
                 }
@@ -90,6 +185,95 @@ impl GameBoard {
         self.check_should_be_used_after_spawn = true;
     }
 
+    // 在指定位置放置tile，x为列、y为行，位置越界或已被占用时返回false
+    pub fn place_tile(&mut self, x: usize, y: usize, value: u32) -> bool {
+        if y >= self.size() || x >= self.size() || self.tiles[y][x] != 0 {
+            return false;
+        }
+        self.tiles[y][x] = value;
+        self.check_should_be_used_after_spawn = true;
+        true
+    }
+
+    // 由对手指定新tile的位置和数值，与随机生成一样只能是2或4
+    pub fn spawn_tile_at(&mut self, x: usize, y: usize, value: u32) -> Result<(), String> {
+        if value != 2 && value != 4 {
+            return Err(format!("新瓷砖只能是2或4，不能是{}", value));
+        }
+        if !self.place_tile(x, y, value) {
+            return Err(format!("第{}行第{}列不是空格", y + 1, x + 1));
+        }
+        Ok(())
+    }
+
+    // 判断向某个方向移动是否会改变棋盘，不修改当前棋盘
+    pub fn can_move(&self, direction: Direction) -> bool {
+        let mut board = GameBoard::from_tiles(self.tiles.clone());
+        board.move_tiles(direction);
+        board.tiles != self.tiles
+    }
+
+    // 向某个方向移动时合并出的tile，不修改当前棋盘，合并规则与 move_abstract 相同
+    pub fn merged_tiles(&self, direction: Direction) -> Vec<u32> {
+        let size = self.size();
+        // 按移动方向取出每一行或每一列，靠近移动终点的在前
+        let line = |i: usize| -> Vec<u32> {
+            match direction {
+                Direction::Left => self.tiles[i].clone(),
+                Direction::Right => self.tiles[i].iter().rev().copied().collect(),
+                Direction::Up => (0..size).map(|j| self.tiles[j][i]).collect(),
+                Direction::Down => (0..size).rev().map(|j| self.tiles[j][i]).collect(),
+                Direction::None | Direction::Quit => vec![],
+            }
+        };
+        let mut merged = vec![];
+        for i in 0..size {
+            let mut pending = None;
+            for tile in line(i).into_iter().filter(|&tile| tile != 0) {
+                if pending == Some(tile) {
+                    merged.push(tile * 2);
+                    pending = None;
+                } else {
+                    pending = Some(tile);
+                }
+            }
+        }
+        merged
+    }
+
+    // 棋盘上非空tile的数量
+    pub fn count_tiles(&self) -> usize {
+        self.tiles.iter().flatten().filter(|&&tile| tile != 0).count()
+    }
+
+    // 返回对称变换后的新棋盘，不带历史记录
+    pub fn transformed(&self, symmetry: Symmetry) -> GameBoard {
+        let size = self.size();
+        let mut tiles = vec![vec![0; size]; size];
+        for (y, row) in self.tiles.iter().enumerate() {
+            for (x, &tile) in row.iter().enumerate() {
+                let pos = symmetry.map_position(Position { x, y }, size);
+                tiles[pos.y][pos.x] = tile;
+            }
+        }
+        GameBoard::from_tiles(tiles)
+    }
+
+    // 规范形式：八种变换中按行比较最小的棋盘，对称的棋盘得到同一个规范形式
+    // 同时返回从当前棋盘到规范形式所用的变换，规范棋盘上的走法经 inverse().map_direction 换算回当前棋盘
+    pub fn canonical(&self) -> (GameBoard, Symmetry) {
+        Symmetry::ALL
+            .iter()
+            .map(|&symmetry| (self.transformed(symmetry), symmetry))
+            .min_by(|(a, _), (b, _)| a.tiles.cmp(&b.tiles))
+            .unwrap()
+    }
+
+    // 规范形式的瓷砖数组，可直接作为 HashMap 的键
+    pub fn canonical_tiles(&self) -> Vec<Vec<u32>> {
+        self.canonical().0.tiles
+    }
+
     pub fn move_tiles(&mut self, direction: Direction) {
         // 根据用户输入的方向移动和合并数字块
         self.save_current_state();
@@ -99,7 +283,7 @@ impl GameBoard {
             Direction::Down => self.move_down(true),
             Direction::Left => self.move_left(true),
             Direction::Right => self.move_right(true),
-            Direction::None => panic!("Should not go to move_tiles function with None direction"),
+            Direction::None | Direction::Quit => panic!("Should not go to move_tiles function with None direction"),
         }
         // // 内置检查，理论上移动前后不会有数据差别
         // let score = self.return_score();
@@ -127,7 +311,7 @@ impl GameBoard {
             Direction::Down => self.move_down(false),
             Direction::Left => self.move_left(false),
             Direction::Right => self.move_right(false),
-            Direction::None => panic!("Should not go to move_tiles function with None direction"),
+            Direction::None | Direction::Quit => panic!("Should not go to move_tiles function with None direction"),
         }
         // // 内置检查，理论上移动前后不会有数据差别
         // let score = self.return_score();
@@ -218,8 +402,9 @@ impl GameBoard {
     }
 
     pub fn reset_board(&mut self) {
-        // 重置棋盘到初始状态
-        self.tiles = vec![vec![0; 4]; 4];
+        // 重置棋盘到初始状态，保持原有边长
+        let size = self.size();
+        self.tiles = vec![vec![0; size]; size];
         self.history = Vec::new(); // 清空历史记录
     }
 
@@ -227,8 +412,8 @@ impl GameBoard {
         // 返回 总分数和最大分数
         let mut max = 0;
         let mut score = 0;
-        for i in 0..4 {
-            for j in 0..4 {
+        for i in 0..self.size() {
+            for j in 0..self.size() {
                 score += self.tiles[i][j];
                 if self.tiles[i][j] > max {
                     max = self.tiles[i][j];
@@ -287,6 +472,12 @@ impl GameBoard {
     pub fn print_state_with(&mut self, other: &GameBoard, animated_vector: Option<Vec<u32>>) {
         // 打印棋盘，方便做调试
         println!("===================="); //换个行
+        if self.size() != 4 || other.size() != 4 {
+            // 非4x4的棋盘（如记谱导入的局面）直接逐个打印
+            self.print_state();
+            other.print_state();
+            return;
+        }
         for i in 0..4 {
             for j in 0..4 {
                 print!("{} ", self.tiles[i][j]);
@@ -379,20 +570,21 @@ impl GameBoard {
         new_line
     }
     fn move_left(&mut self, if_merge: bool) {
-        for i in 0..4 {
+        for i in 0..self.size() {
             self.tiles[i] = self.move_abstract(self.tiles[i].clone(), if_merge);
         }
     }
     fn move_right(&mut self, if_merge: bool) {
-        for i in 0..4 {
+        let size = self.size();
+        for i in 0..size {
             // 需要反向使用abstract
             let mut line = vec![];
-            for j in (0..4).rev() {
+            for j in (0..size).rev() {
                 line.push(self.tiles[i][j]);
             }
             line = self.move_abstract(line, if_merge);
-            for j in (0..4).rev() {
-                self.tiles[i][j] = line[4 - 1 - j];
+            for j in (0..size).rev() {
+                self.tiles[i][j] = line[size - 1 - j];
             }
             // 2 2 2 0
             // 0 0 2 4
@@ -402,34 +594,36 @@ impl GameBoard {
         }
     }
     fn move_up(&mut self,if_merge: bool) {
-        for i in 0..4 {
+        let size = self.size();
+        for i in 0..size {
             // 需要反向使用abstract
             let mut line = vec![];
-            for j in 0..4 {
+            for j in 0..size {
                 line.push(self.tiles[j][i]);
             }
             line = self.move_abstract(line, if_merge);
-            for j in (0..4) {
+            for j in (0..size) {
                 self.tiles[j][i] = line[j];
             }
         }
     }
     fn move_down(&mut self, if_merge: bool) {
-        for i in (0..4) {
+        let size = self.size();
+        for i in (0..size) {
             // 需要反向使用abstract
             let mut line = vec![];
-            for j in (0..4).rev() {
+            for j in (0..size).rev() {
                 line.push(self.tiles[j][i]);
             }
             line = self.move_abstract(line, if_merge);
-            for j in (0..4).rev() {
-                self.tiles[j][i] = line[4 - 1 - j];
+            for j in (0..size).rev() {
+                self.tiles[j][i] = line[size - 1 - j];
             }
         }
     }
     fn if_have_empty_tile(&mut self) -> bool {
-        for i in 0..4 {
-            for j in 0..4 {
+        for i in 0..self.size() {
+            for j in 0..self.size() {
                 if self.tiles[i][j] == 0 {
                     return true;
                 }
@@ -604,6 +798,35 @@ mod tests_base {
             new_line, line_new
         );
     }
+
+    #[test]
+    fn test_merged_tiles() {
+        let game = GameBoard::from_tiles(vec![
+            vec![2, 2, 2, 2],
+            vec![4, 2, 2, 4],
+            vec![8, 0, 0, 8],
+            vec![2, 4, 8, 16],
+        ]);
+        assert_eq!(game.merged_tiles(Direction::Left), vec![4, 4, 4, 16]);
+        assert_eq!(game.merged_tiles(Direction::Right), vec![4, 4, 4, 16]);
+        assert_eq!(game.merged_tiles(Direction::Up), vec![4, 4]);
+        assert_eq!(game.merged_tiles(Direction::Down).len(), 2);
+        // 合并出的tile与真正移动后的结果一致，且不修改棋盘
+        let mut moved = GameBoard::from_tiles(game.get_tiles().clone());
+        moved.move_tiles(Direction::Left);
+        assert_eq!(moved.get_tiles()[2], vec![16, 0, 0, 0]);
+        assert_eq!(game.get_tiles()[2], vec![8, 0, 0, 8]);
+    }
+
+    #[test]
+    fn test_spawn_tile_at() {
+        let mut game = GameBoard::from_tiles(vec![vec![2, 0], vec![0, 0]]);
+        assert!(game.spawn_tile_at(1, 0, 4).is_ok());
+        assert!(game.spawn_tile_at(0, 1, 8).is_err());
+        assert!(game.spawn_tile_at(0, 0, 2).is_err());
+        assert!(game.spawn_tile_at(2, 0, 2).is_err());
+        assert_eq!(game.get_tiles(), &vec![vec![2, 4], vec![0, 0]]);
+    }
 }
 
 // 对移动功能的单元测试
@@ -846,4 +1069,99 @@ mod tests_move {
             game.tiles, expected
         );
     }
+
+    #[test]
+    fn test_move_tiles_small_board() {
+        // 非4x4的棋盘同样可以移动
+        let mut game = GameBoard::from_tiles(vec![
+            vec![2, 2, 0],
+            vec![0, 4, 4],
+            vec![2, 0, 2],
+        ]);
+        game.move_tiles(Direction::Right);
+        let expected = vec![
+            vec![0, 0, 4],
+            vec![0, 0, 8],
+            vec![0, 0, 4],
+        ];
+        assert_eq!(
+            game.tiles, expected,
+            "3x3向右合并失败: 实际 {:?}, 期望 {:?}",
+            game.tiles, expected
+        );
+        game.move_tiles(Direction::Left);
+        let expected = vec![
+            vec![4, 0, 0],
+            vec![8, 0, 0],
+            vec![4, 0, 0],
+        ];
+        assert_eq!(game.tiles, expected, "3x3向左移动失败");
+    }
+}
+
+// 对称变换的单元测试
+#[cfg(test)]
+mod tests_symmetry {
+    use super::*;
+
+    const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    fn sample_board() -> GameBoard {
+        GameBoard::from_tiles(vec![
+            vec![2, 2, 0, 8],
+            vec![0, 4, 4, 0],
+            vec![16, 0, 2, 2],
+            vec![2, 0, 0, 32],
+        ])
+    }
+
+    #[test]
+    fn test_rotate_clockwise() {
+        let board = GameBoard::from_tiles(vec![vec![2, 4], vec![8, 16]]);
+        let rotated = board.transformed(Symmetry::RotateClockwise);
+        assert_eq!(rotated.get_tiles(), &vec![vec![8, 2], vec![16, 4]]);
+        assert_eq!(Symmetry::RotateClockwise.map_direction(Direction::Up), Direction::Right);
+    }
+
+    #[test]
+    fn test_moves_commute_with_symmetry() {
+        // 先移动再变换，与先变换再按映射后的方向移动，结果相同
+        let board = sample_board();
+        for &symmetry in Symmetry::ALL.iter() {
+            for &direction in DIRECTIONS.iter() {
+                let mut moved = board.clone();
+                moved.move_tiles(direction);
+                let mut transformed = board.transformed(symmetry);
+                transformed.move_tiles(symmetry.map_direction(direction));
+                assert_eq!(
+                    moved.transformed(symmetry).get_tiles(),
+                    transformed.get_tiles(),
+                    "{:?} 下 {:?} 移动不一致",
+                    symmetry,
+                    direction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse() {
+        let board = sample_board();
+        for &symmetry in Symmetry::ALL.iter() {
+            let back = board.transformed(symmetry).transformed(symmetry.inverse());
+            assert_eq!(back.get_tiles(), board.get_tiles(), "{:?} 的逆变换错误", symmetry);
+        }
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let board = sample_board();
+        let canonical = board.canonical_tiles();
+        for &symmetry in Symmetry::ALL.iter() {
+            assert_eq!(board.transformed(symmetry).canonical_tiles(), canonical);
+        }
+        // 返回的变换把原棋盘变为规范棋盘
+        let (canonical_board, symmetry) = board.canonical();
+        assert_eq!(board.transformed(symmetry).get_tiles(), canonical_board.get_tiles());
+    }
 }
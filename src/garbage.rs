@@ -0,0 +1,130 @@
+use rand::Rng;
+use std::collections::VecDeque;
+use tokio::time::Instant;
+
+use crate::config;
+use crate::GameBoard;
+
+/// 一步移动发起的攻击：合并出大瓷砖，以及一步之内合并多次（连锁）都会攻击对手
+// 合并出 GARBAGE_BIG_MERGE 记 1，每大一倍多记 1；连锁达到 GARBAGE_CHAIN_MERGES 次记 1，每多一次多记 1
+pub fn attack_power(merged: &[u32]) -> u32 {
+    let big: u32 = merged
+        .iter()
+        .filter(|&&tile| tile >= config::GARBAGE_BIG_MERGE)
+        .map(|&tile| tile.ilog2() - config::GARBAGE_BIG_MERGE.ilog2() + 1)
+        .sum();
+    let chain = (merged.len() + 1).saturating_sub(config::GARBAGE_CHAIN_MERGES) as u32;
+    big + chain
+}
+
+/// 一名玩家即将落下的垃圾，按到达先后排列
+// 垃圾要过一段时间才落下，在此之前玩家自己的攻击会先抵消最早到达的垃圾
+#[derive(Debug, Default)]
+pub struct GarbageQueue {
+    pending: VecDeque<(Instant, u32)>, // 落下的时刻和块数
+}
+
+impl GarbageQueue {
+    pub fn push(&mut self, due: Instant, amount: u32) {
+        if amount > 0 {
+            self.pending.push_back((due, amount));
+        }
+    }
+
+    // 用攻击抵消排队中的垃圾，返回抵消之后剩下的攻击
+    pub fn cancel(&mut self, mut power: u32) -> u32 {
+        while power > 0 {
+            let Some((_, amount)) = self.pending.front_mut() else { break };
+            let cancelled = power.min(*amount);
+            *amount -= cancelled;
+            power -= cancelled;
+            if *amount == 0 {
+                self.pending.pop_front();
+            }
+        }
+        power
+    }
+
+    // 取出 now 之前到期的垃圾，返回总块数
+    pub fn take_due(&mut self, now: Instant) -> u32 {
+        let mut total = 0;
+        while let Some(&(due, amount)) = self.pending.front() {
+            if due > now {
+                break;
+            }
+            total += amount;
+            self.pending.pop_front();
+        }
+        total
+    }
+
+    // 最早一批垃圾落下的时刻
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending.front().map(|&(due, _)| due)
+    }
+
+    // 排队中的垃圾总块数
+    pub fn total(&self) -> u32 {
+        self.pending.iter().map(|&(_, amount)| amount).sum()
+    }
+}
+
+// 把 amount 块垃圾随机落在空格上，空格不够时多出的垃圾作废，返回实际落下的块数
+pub fn drop_garbage<R: Rng>(board: &mut GameBoard, amount: u32, rng: &mut R) -> u32 {
+    let size = board.size();
+    let mut empty: Vec<(usize, usize)> = (0..size)
+        .flat_map(|y| (0..size).map(move |x| (x, y)))
+        .filter(|&(x, y)| board.get_tiles()[y][x] == 0)
+        .collect();
+    let mut dropped = 0;
+    while dropped < amount && !empty.is_empty() {
+        let (x, y) = empty.swap_remove(rng.gen_range(0..empty.len()));
+        board.place_tile(x, y, config::GARBAGE_TILE);
+        dropped += 1;
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests_garbage {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_attack_power() {
+        assert_eq!(attack_power(&[]), 0);
+        assert_eq!(attack_power(&[4, 8]), 0);
+        assert_eq!(attack_power(&[64]), 1);
+        assert_eq!(attack_power(&[256]), 3);
+        // 三次合并算连锁，再加上一个64
+        assert_eq!(attack_power(&[4, 4, 64]), 2);
+        assert_eq!(attack_power(&[4, 4, 4, 4]), 2);
+    }
+
+    #[test]
+    fn test_cancel_and_take_due() {
+        let now = Instant::now();
+        let mut queue = GarbageQueue::default();
+        queue.push(now, 2);
+        queue.push(now + Duration::from_secs(1), 3);
+        assert_eq!(queue.total(), 5);
+        // 先抵消最早到的那批，剩下的攻击返回
+        assert_eq!(queue.cancel(3), 0);
+        assert_eq!(queue.total(), 2);
+        assert_eq!(queue.next_due(), Some(now + Duration::from_secs(1)));
+        assert_eq!(queue.take_due(now), 0);
+        assert_eq!(queue.take_due(now + Duration::from_secs(1)), 2);
+        assert_eq!(queue.cancel(4), 4);
+        assert_eq!(queue.next_due(), None);
+    }
+
+    #[test]
+    fn test_drop_garbage_fills_empty_cells() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut board = GameBoard::from_tiles(vec![vec![4, 8], vec![0, 0]]);
+        assert_eq!(drop_garbage(&mut board, 5, &mut rng), 2);
+        assert_eq!(board.get_tiles(), &vec![vec![4, 8], vec![config::GARBAGE_TILE; 2]]);
+    }
+}
@@ -80,7 +80,8 @@ impl IOManager {
     //     None
     // }
 
-    pub async fn read_input_async(&mut self, character: u8) -> Option<Direction> {
+    // 读取一次按键，返回键码，需要方向以外的按键时使用，方向由 key_direction 换算
+    pub async fn read_key_async(&mut self) -> Option<KeyCode> {
         // Check if the current time since the last input is less than the set interval
         if self.last_input_time.elapsed() < self.io_response_interval {
             return None;
@@ -94,34 +95,7 @@ impl IOManager {
                 // Note: This is a blocking call
                 if key_event.kind == KeyEventKind::Press {
                     self.update_last_input_time(); // Update the last input time
-                    return Some(match key_event.code {
-                        KeyCode::Up | KeyCode::Char('w') => {
-                            // print_info(character);
-                            // println!("↑");
-                            Direction::Up
-                        }
-                        KeyCode::Left | KeyCode::Char('a') => {
-                            // print_info(character);
-                            // println!("←");
-                            Direction::Left
-                        }
-                        KeyCode::Down | KeyCode::Char('s') => {
-                            // print_info(character);
-                            // println!("↓");
-                            Direction::Down
-                        }
-                        KeyCode::Right | KeyCode::Char('d') => {
-                            // print_info(character);
-                            // println!("→");
-                            Direction::Right
-                        }
-                        KeyCode::Char('q') => {
-                            // print_info(character);
-                            // println!("→");
-                            Direction::Quit
-                        }
-                        _ => Direction::None,
-                    });
+                    return Some(key_event.code);
                 }
             }
         }
@@ -207,6 +181,18 @@ impl IOManager {
     }
 }
 
+// 方向键和 WASD 对应移动方向，q 为退出，其余按键为 None
+pub fn key_direction(code: KeyCode) -> Direction {
+    match code {
+        KeyCode::Up | KeyCode::Char('w') => Direction::Up,
+        KeyCode::Left | KeyCode::Char('a') => Direction::Left,
+        KeyCode::Down | KeyCode::Char('s') => Direction::Down,
+        KeyCode::Right | KeyCode::Char('d') => Direction::Right,
+        KeyCode::Char('q') => Direction::Quit,
+        _ => Direction::None,
+    }
+}
+
 pub fn print_info(character: u8) {
     // if character == 1 {
     //     print!("left board choosing ");
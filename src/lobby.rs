@@ -0,0 +1,811 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval_at, timeout, Duration, Instant, MissedTickBehavior};
+
+use crate::config;
+use crate::connection::PendingClient;
+use crate::game_board::GameBoard;
+use crate::matchmaking::{Matchmaker, Pairing};
+use crate::protocol::{all_features, write_message, MatchMode, Message, RoomInfo, RoomSettings, FEATURE_STACKED};
+use crate::spectate::LiveMatches;
+
+// 房间码的长度和字符集，去掉了容易看错的 0、O、1、I
+const ROOM_CODE_LENGTH: usize = 4;
+const ROOM_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// 凑齐人数的一局，交给服务器建立对局，房主为玩家1，加入者按加入顺序排在后面
+pub struct NewMatch {
+    pub host: PendingClient,
+    pub guests: Vec<PendingClient>,
+    pub title: String, // 观战列表中显示的标题
+    pub mode: MatchMode,
+    pub position: Option<String>, // 开局局面记谱，为空时随机开局
+    pub stacked: bool, // 两人对局时棋盘上下叠放，桥梁架在列上
+}
+
+// 一个等待对手的房间，加入者经 guests 交给房主的大厅任务，info.joined 为已加入的人数
+struct Room {
+    info: RoomInfo,
+    guests: mpsc::Sender<PendingClient>,
+}
+
+// 自己创建的房间，加入者在凑齐人数前由房主的大厅任务保管，房主的任务同时读取他们的连接
+struct OwnRoom {
+    info: RoomInfo,
+    receiver: mpsc::Receiver<PendingClient>,
+    guests: Vec<PendingClient>,
+    heard: Vec<Instant>, // 各加入者最近一次发来消息的时刻
+}
+
+impl OwnRoom {
+    fn add_guest(&mut self, guest: PendingClient) {
+        self.guests.push(guest);
+        self.heard.push(Instant::now());
+        self.info.joined += 1;
+    }
+
+    // 把第几个加入者移出房间，空出的位置在大厅的列表中同样可见
+    fn remove_guest(&mut self, index: usize, lobby: &Lobby) -> PendingClient {
+        self.heard.remove(index);
+        self.info.joined -= 1;
+        lobby.leave(&self.info.code);
+        self.guests.remove(index)
+    }
+
+    // 从大厅中关闭房间，连同管道中还没取出的加入者，返回所有已加入的客户端
+    fn close(self, lobby: &Lobby) -> (RoomInfo, Vec<PendingClient>) {
+        let OwnRoom { info, mut receiver, mut guests, .. } = self;
+        lobby.close(&info.code);
+        while let Ok(guest) = receiver.try_recv() {
+            guests.push(guest);
+        }
+        (info, guests)
+    }
+}
+
+/// 大厅中所有等待对手的房间
+// 锁只在查表时短暂持有，不会跨越 await
+pub struct Lobby {
+    rooms: Mutex<HashMap<String, Room>>,
+    board_size: usize, // 服务器的棋盘边长，房间的开局局面须与之一致
+    features: Vec<&'static str>, // 服务器开启的功能，房间只能选开启了的玩法和布局
+    heartbeat_interval: Duration,
+    dead_timeout: Duration, // 这么久没收到大厅中客户端的任何消息就断开，归还它的连接名额
+}
+
+impl Lobby {
+    pub fn new(board_size: usize) -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            board_size,
+            features: all_features(),
+            heartbeat_interval: Duration::from_secs(config::HEARTBEAT_INTERVAL_SECS),
+            dead_timeout: Duration::from_secs(config::DEAD_CONNECTION_TIMEOUT_SECS),
+        }
+    }
+
+    pub fn set_features(&mut self, features: Vec<&'static str>) {
+        self.features = features;
+    }
+
+    // 创建房间，返回房间信息和接收加入者的一端，人数和竞速设置须在 config 规定的范围内，开局局面须能解析且边长一致
+    // 上下叠放时只有一对棋盘；玩法和上下叠放须是服务器开启了的功能
+    fn open(&self, settings: RoomSettings) -> Result<(RoomInfo, mpsc::Receiver<PendingClient>), String> {
+        if !(config::MATCH_MIN_PLAYERS..=config::MATCH_MAX_PLAYERS).contains(&settings.players) {
+            return Err(format!(
+                "房间人数须在 {} 到 {} 之间",
+                config::MATCH_MIN_PLAYERS,
+                config::MATCH_MAX_PLAYERS
+            ));
+        }
+        settings.mode.validate(settings.players)?;
+        if !self.features.contains(&settings.mode.feature()) {
+            return Err(format!("服务器没有开启这种玩法：{}", settings.mode.describe()));
+        }
+        if settings.stacked && !self.features.contains(&FEATURE_STACKED) {
+            return Err("服务器没有开启上下叠放".to_string());
+        }
+        if settings.stacked && settings.players != 2 {
+            return Err("上下叠放只支持两人对局".to_string());
+        }
+        if let Some(ref position) = settings.position {
+            let board = GameBoard::from_notation(position).map_err(|e| format!("开局局面无效：{}", e))?;
+            if board.size() != self.board_size {
+                return Err(format!("开局局面的边长须为 {}", self.board_size));
+            }
+        }
+        let mut rooms = self.rooms.lock().unwrap();
+        let code = loop {
+            let code = generate_code();
+            if !rooms.contains_key(&code) {
+                break code;
+            }
+        };
+        let info = RoomInfo {
+            code: code.clone(),
+            name: settings.name,
+            private: settings.private,
+            players: settings.players,
+            joined: 1,
+            mode: settings.mode,
+            position: settings.position,
+            stacked: settings.stacked,
+        };
+        let (guests, receiver) = mpsc::channel(settings.players as usize - 1);
+        rooms.insert(code, Room { info: info.clone(), guests });
+        Ok((info, receiver))
+    }
+
+    // 关闭房间
+    fn close(&self, code: &str) {
+        self.rooms.lock().unwrap().remove(code);
+    }
+
+    // 加入者离开房间，空出一个位置
+    fn leave(&self, code: &str) {
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(code) {
+            room.info.joined -= 1;
+        }
+    }
+
+    // 公开房间列表，按名称排序
+    fn list(&self) -> Vec<RoomInfo> {
+        let mut rooms: Vec<RoomInfo> = self
+            .rooms
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|room| !room.info.private)
+            .map(|room| room.info.clone())
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.code.cmp(&b.code)));
+        rooms
+    }
+
+    // 加入房间，人满后房间随之从列表中移除；失败时把客户端连同原因交还
+    // 查找房间和交出客户端在同一次加锁内完成，房主关闭房间时要么房间还在，要么加入者已经在管道里
+    fn join(&self, code: &str, client: PendingClient) -> Result<(), (PendingClient, String)> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = match rooms.get_mut(code) {
+            Some(room) => room,
+            None => return Err((client, format!("房间 {} 不存在或对局已开始", code))),
+        };
+        // 管道容量等于空位数，人满的房间已经移除，不会出现 Full
+        if let Err(TrySendError::Closed(client) | TrySendError::Full(client)) = room.guests.try_send(client) {
+            rooms.remove(code);
+            return Err((client, "房间已关闭".to_string()));
+        }
+        room.info.joined += 1;
+        if room.info.joined == room.info.players {
+            rooms.remove(code);
+        }
+        Ok(())
+    }
+}
+
+// 随机生成房间码
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| ROOM_CODE_CHARS[rng.gen_range(0..ROOM_CODE_CHARS.len())] as char)
+        .collect()
+}
+
+// 房间中发生的事：有人加入，或已加入的第几人发来消息、断开
+enum RoomEvent {
+    Joined(Option<PendingClient>),
+    Guest(usize, Result<Message, io::Error>),
+}
+
+// 等待有人加入或已加入者发来消息，没有房间时永远等待
+// 读取器把读到一半的帧留在缓冲区里，select 中途放弃读取不会丢数据
+async fn wait_for_room(room: &mut Option<OwnRoom>) -> RoomEvent {
+    let Some(OwnRoom { receiver, guests, .. }) = room else {
+        return std::future::pending().await;
+    };
+    let mut reads: Vec<_> = guests
+        .iter_mut()
+        .map(|guest| {
+            let codec = guest.codec;
+            Box::pin(guest.reader.read_message(&mut guest.socket, codec))
+        })
+        .collect();
+    poll_fn(|cx| {
+        if let Poll::Ready(guest) = receiver.poll_recv(cx) {
+            return Poll::Ready(RoomEvent::Joined(guest));
+        }
+        for (index, read) in reads.iter_mut().enumerate() {
+            if let Poll::Ready(message) = read.as_mut().poll(cx) {
+                return Poll::Ready(RoomEvent::Guest(index, message));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+// 给大厅中的客户端写一条消息，对方不再接收时超时放弃，不会一直占着大厅的任务
+async fn send(client: &mut PendingClient, message: &Message, limit: Duration) -> Result<(), io::Error> {
+    match timeout(limit, write_message(&mut client.socket, client.codec, message)).await {
+        Ok(written) => written,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "连接超时")),
+    }
+}
+
+// 把房间的人数变化告诉房间中的所有人，写给房主失败时返回错误，写给加入者失败留给下一次读取发现
+async fn send_room_update(own: &mut OwnRoom, host: &mut PendingClient, limit: Duration) -> Result<(), io::Error> {
+    let update = Message::RoomUpdate(own.info.clone());
+    for guest in own.guests.iter_mut() {
+        let _ = send(guest, &update, limit).await;
+    }
+    send(host, &update, limit).await
+}
+
+// 房间关闭时把已加入的客户端送回大厅，附带原因
+// serve 里再启动 serve，需要擦除类型才能算出它的 Future 是 Send
+fn return_to_lobby(
+    guests: Vec<PendingClient>,
+    reason: &str,
+    lobby: &Arc<Lobby>,
+    matchmaker: &Arc<Matchmaker>,
+    live: &Arc<LiveMatches>,
+    matches: &mpsc::Sender<NewMatch>,
+) {
+    for mut guest in guests {
+        let (lobby, matchmaker, live, matches) = (lobby.clone(), matchmaker.clone(), live.clone(), matches.clone());
+        let reason = reason.to_string();
+        let task: Pin<Box<dyn Future<Output = ()> + Send>> = Box::pin(async move {
+            if send(&mut guest, &Message::LobbyError(reason), lobby.dead_timeout).await.is_ok() {
+                serve(guest, lobby, matchmaker, live, matches).await;
+            }
+        });
+        tokio::spawn(task);
+    }
+}
+
+// 排位队列中的一名玩家
+struct Queued {
+    id: u64,
+    name: String,
+    joined: Instant,
+    pairing: oneshot::Receiver<Pairing>,
+}
+
+// 等待排位配对，不在队列中时永远等待
+async fn wait_for_pairing(queued: &mut Option<Queued>) -> Option<Pairing> {
+    match queued {
+        Some(queued) => (&mut queued.pairing).await.ok(),
+        None => std::future::pending().await,
+    }
+}
+
+/// 握手后的客户端在大厅中的任务：处理建房、列表、加入、离开、排位和观战，直到进入对局或断开
+// 房间人满时，由房主一方把所有客户端发到 matches 建立对局，房主为玩家1；人满之前每有人加入都告诉房间中的所有人
+// 加入者交出连接后由房主的任务读取，加入者发来 LeaveRoom 时回到大厅，掉线时移出房间，两种情况都告诉其余的人
+// 排位配对时同样由先排队的一方建立对局，双方连接都带上玩家名，对局结果计入等级分
+// 大厅中同样定时发送心跳，房主或加入者超过 dead_timeout 没有任何消息就视为掉线，连接关闭后连接名额随之归还
+pub async fn serve(
+    mut client: PendingClient,
+    lobby: Arc<Lobby>,
+    matchmaker: Arc<Matchmaker>,
+    live: Arc<LiveMatches>,
+    matches: mpsc::Sender<NewMatch>,
+) {
+    let mut room: Option<OwnRoom> = None;
+    let mut queued: Option<Queued> = None;
+    let limit = lobby.dead_timeout;
+    let mut heard = Instant::now();
+    let mut heartbeat = interval_at(Instant::now() + lobby.heartbeat_interval, lobby.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut next_ping = 0;
+    loop {
+        let codec = client.codec;
+        let message = tokio::select! {
+            message = client.reader.read_message(&mut client.socket, codec) => {
+                heard = Instant::now();
+                message
+            }
+            _ = heartbeat.tick() => {
+                if heard.elapsed() > limit {
+                    println!("Client timed out in the lobby");
+                    break;
+                }
+                next_ping += 1;
+                if send(&mut client, &Message::Ping(next_ping), limit).await.is_err() {
+                    break;
+                }
+                let Some(own) = room.as_mut() else { continue };
+                let silent: Vec<usize> = (0..own.guests.len()).filter(|&index| own.heard[index].elapsed() > limit).collect();
+                for &index in silent.iter().rev() {
+                    println!("Guest timed out in room {}", own.info.code);
+                    own.remove_guest(index, &lobby);
+                }
+                for guest in own.guests.iter_mut() {
+                    let _ = send(guest, &Message::Ping(next_ping), limit).await;
+                }
+                if !silent.is_empty() && send_room_update(own, &mut client, limit).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            event = wait_for_room(&mut room) => {
+                let own = room.as_mut().unwrap();
+                match event {
+                    // 房间不在列表中就不会再有人加入，管道不会在房主之前关闭
+                    RoomEvent::Joined(guest) => {
+                        own.add_guest(guest.unwrap());
+                        if own.info.joined == own.info.players {
+                            let OwnRoom { info, guests, .. } = room.take().unwrap();
+                            let _ = matches.send(NewMatch { host: client, guests, title: info.name, mode: info.mode, position: info.position, stacked: info.stacked }).await;
+                            return;
+                        }
+                    }
+                    RoomEvent::Guest(index, Ok(Message::LeaveRoom)) => {
+                        let guest = own.remove_guest(index, &lobby);
+                        return_to_lobby(vec![guest], "已离开房间", &lobby, &matchmaker, &live, &matches);
+                    }
+                    RoomEvent::Guest(index, Ok(Message::Pong(_))) => {
+                        own.heard[index] = Instant::now();
+                        continue;
+                    }
+                    RoomEvent::Guest(index, Ok(message)) => {
+                        own.heard[index] = Instant::now();
+                        eprintln!("Unexpected message from guest in room {}: {:?}", own.info.code, message);
+                        continue;
+                    }
+                    RoomEvent::Guest(index, Err(e)) => {
+                        println!("Guest left room {}: {}", own.info.code, e);
+                        own.remove_guest(index, &lobby);
+                    }
+                }
+                if send_room_update(own, &mut client, limit).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            pairing = wait_for_pairing(&mut queued) => {
+                let Queued { name, joined, .. } = queued.take().unwrap();
+                client.rated_name = Some(name.clone());
+                let returned = match pairing {
+                    Some(Pairing::Host(guest)) => match guest.await {
+                        Ok(guest) => {
+                            let title = format!("{} vs {}", name, guest.rated_name.as_deref().unwrap_or("?"));
+                            // 排位赛总是经典玩法，随机开局
+                            let new_match = NewMatch { host: client, guests: vec![guest], title, mode: MatchMode::Classic, position: None, stacked: false };
+                            let _ = matches.send(new_match).await;
+                            return;
+                        }
+                        Err(_) => client, // 对手已离开
+                    },
+                    Some(Pairing::Guest(host)) => match host.send(client) {
+                        Ok(()) => return,
+                        Err(client) => client, // 对手已离开
+                    },
+                    None => client,
+                };
+                // 对手在配对的瞬间离开了，沿用原来的排队时刻重新排队
+                client = returned;
+                client.rated_name = None;
+                if let Ok((id, pairing)) = matchmaker.enqueue(&name, joined) {
+                    queued = Some(Queued { id, name, joined, pairing });
+                }
+                continue;
+            }
+        };
+        let reply = match message {
+            Ok(Message::Pong(_)) => None,
+            Ok(Message::ListRooms) => Some(Message::RoomList(lobby.list())),
+            Ok(Message::ListMatches) => Some(Message::MatchList(live.list())),
+            Ok(Message::ListCoopScores) => Some(Message::CoopScores(matchmaker.coop_scores())),
+            Ok(Message::CreateRoom(_)) | Ok(Message::JoinRoom(_)) | Ok(Message::Spectate(_)) if queued.is_some() => {
+                Some(Message::LobbyError("请先退出排位队列".to_string()))
+            }
+            Ok(Message::CreateRoom(settings)) => {
+                if let Some(own) = room.take() {
+                    let (_, guests) = own.close(&lobby);
+                    return_to_lobby(guests, "房主已关闭房间", &lobby, &matchmaker, &live, &matches);
+                }
+                match lobby.open(settings) {
+                    Ok((info, receiver)) => {
+                        println!("Room {} opened: {} ({} players, {:?})", info.code, info.name, info.players, info.mode);
+                        room = Some(OwnRoom { info: info.clone(), receiver, guests: vec![], heard: vec![] });
+                        Some(Message::RoomCreated(info))
+                    }
+                    Err(reason) => Some(Message::LobbyError(reason)),
+                }
+            }
+            Ok(Message::JoinRoom(code)) => {
+                let code = code.trim().to_uppercase();
+                if room.as_ref().map_or(false, |own| own.info.code == code) {
+                    Some(Message::LobbyError("不能加入自己创建的房间".to_string()))
+                } else {
+                    match lobby.join(&code, client) {
+                        Ok(()) => {
+                            if let Some(own) = room.take() {
+                                let (_, guests) = own.close(&lobby);
+                                return_to_lobby(guests, "房主已关闭房间", &lobby, &matchmaker, &live, &matches);
+                            }
+                            return;
+                        }
+                        Err((returned, reason)) => {
+                            client = returned;
+                            Some(Message::LobbyError(reason))
+                        }
+                    }
+                }
+            }
+            Ok(Message::LeaveRoom) => {
+                if let Some(own) = room.take() {
+                    // 关闭前恰好有人加入、凑齐了人数时，仍然开始对局
+                    let (info, guests) = own.close(&lobby);
+                    if guests.len() + 1 == info.players as usize {
+                        let _ = matches.send(NewMatch { host: client, guests, title: info.name, mode: info.mode, position: info.position, stacked: info.stacked }).await;
+                        return;
+                    }
+                    return_to_lobby(guests, "房主已关闭房间", &lobby, &matchmaker, &live, &matches);
+                }
+                None
+            }
+            Ok(Message::Spectate(_)) if room.is_some() => Some(Message::LobbyError("请先离开房间".to_string())),
+            Ok(Message::Spectate(request)) => match live.watch(request, client) {
+                Ok(()) => return,
+                Err((returned, reason)) => {
+                    client = returned;
+                    Some(Message::LobbyError(reason))
+                }
+            },
+            Ok(Message::JoinQueue(_)) if room.is_some() => Some(Message::LobbyError("请先离开房间".to_string())),
+            Ok(Message::JoinQueue(_)) if queued.is_some() => None,
+            Ok(Message::JoinQueue(request)) => {
+                let name = request.name.trim().to_string();
+                let claimed = match name.is_empty() {
+                    true => Err("排位赛需要玩家名".to_string()),
+                    false => matchmaker.claim(&name, request.key.as_deref()),
+                };
+                match claimed {
+                    Ok(issued) => {
+                        // 新发放的密钥先于排队结果发出，客户端保存后下次排位带上
+                        if let Some(key) = issued {
+                            if send(&mut client, &Message::PlayerKey(key), limit).await.is_err() {
+                                break;
+                            }
+                        }
+                        let joined = Instant::now();
+                        match matchmaker.enqueue(&name, joined) {
+                            Ok((id, pairing)) => {
+                                let rating = matchmaker.rating(&name).round() as u32;
+                                println!("{} joined the rated queue ({})", name, rating);
+                                queued = Some(Queued { id, name, joined, pairing });
+                                Some(Message::Queued(rating))
+                            }
+                            Err(reason) => Some(Message::LobbyError(reason)),
+                        }
+                    }
+                    Err(reason) => Some(Message::LobbyError(reason)),
+                }
+            }
+            Ok(Message::LeaveQueue) => {
+                // 已经配对时忽略，照常开始对局
+                if let Some(ref ticket) = queued {
+                    if matchmaker.dequeue(ticket.id) {
+                        queued = None;
+                    }
+                }
+                None
+            }
+            Ok(message) => {
+                eprintln!("Unexpected message in lobby: {:?}", message);
+                None
+            }
+            Err(e) => {
+                println!("Client left the lobby: {}", e);
+                break;
+            }
+        };
+        if let Some(reply) = reply {
+            if send(&mut client, &reply, limit).await.is_err() {
+                break;
+            }
+        }
+    }
+    // 连接已断开，关闭房间、退出队列，已加入的客户端回到大厅
+    if let Some(own) = room.take() {
+        let (_, guests) = own.close(&lobby);
+        return_to_lobby(guests, "房主已离开，房间已关闭", &lobby, &matchmaker, &live, &matches);
+    }
+    if let Some(ticket) = queued.take() {
+        matchmaker.dequeue(ticket.id);
+    }
+}
+
+#[cfg(test)]
+mod tests_lobby {
+    use super::*;
+    use crate::protocol::{Codec, FrameReader, QueueRequest};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Semaphore;
+
+    // 用本地 TCP 连接模拟一个握手完成的客户端，返回客户端一端和服务器一端
+    async fn connect() -> (TcpStream, PendingClient) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let pending = PendingClient { socket, reader: FrameReader::new(), codec: Codec::Json, resume_token: None, permit: None, rated_name: None };
+        (client, pending)
+    }
+
+    fn settings(name: &str, private: bool) -> RoomSettings {
+        RoomSettings { name: name.to_string(), private, players: 2, mode: MatchMode::Classic, position: None, stacked: false }
+    }
+
+    #[tokio::test]
+    async fn test_private_rooms_not_listed() {
+        let lobby = Lobby::new(4);
+        let (public, _r1) = lobby.open(settings("公开", false)).unwrap();
+        let (private, _r2) = lobby.open(settings("私密", true)).unwrap();
+        assert_eq!(public.code.len(), ROOM_CODE_LENGTH);
+        assert_ne!(public.code, private.code);
+        assert_eq!(lobby.list(), vec![public]);
+    }
+
+    #[test]
+    fn test_room_position_must_fit_board() {
+        let lobby = Lobby::new(4);
+        let with_position = |position: &str| RoomSettings { position: Some(position.to_string()), ..settings("局面", false) };
+        let (info, _receiver) = lobby.open(with_position("4 0000/0000/0000/00aa")).unwrap();
+        assert_eq!(info.position.as_deref(), Some("4 0000/0000/0000/00aa"));
+        assert!(lobby.open(with_position("4 0000/0000")).unwrap_err().contains("无效"));
+        assert!(lobby.open(with_position("3 000/000/000")).unwrap_err().contains("边长"));
+    }
+
+    #[test]
+    fn test_stacked_room_needs_two_players() {
+        let lobby = Lobby::new(4);
+        let (info, _receiver) = lobby.open(RoomSettings { stacked: true, ..settings("叠放", false) }).unwrap();
+        assert!(info.stacked);
+        let three = RoomSettings { players: 3, stacked: true, ..settings("三人叠放", false) };
+        assert!(lobby.open(three).unwrap_err().contains("两人"));
+    }
+
+    #[test]
+    fn test_disabled_features_rejected() {
+        let mut lobby = Lobby::new(4);
+        lobby.set_features(crate::protocol::features_without("stacked,mode-garbage").unwrap());
+        assert!(lobby.open(RoomSettings { stacked: true, ..settings("叠放", false) }).is_err());
+        assert!(lobby.open(RoomSettings { mode: MatchMode::Garbage, ..settings("垃圾", false) }).is_err());
+        assert!(lobby.open(RoomSettings { mode: MatchMode::BattleRoyale, ..settings("淘汰", false) }).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_join_closed_room_returns_client() {
+        let lobby = Lobby::new(4);
+        let (info, receiver) = lobby.open(settings("房间", true)).unwrap();
+        drop(receiver); // 房主已断开
+        let (_stream, client) = connect().await;
+        let (client, _) = lobby.join(&info.code, client).unwrap_err();
+        let (_, reason) = lobby.join(&info.code, client).unwrap_err();
+        assert!(reason.contains("不存在"));
+    }
+
+    #[tokio::test]
+    async fn test_join_by_code_starts_match() {
+        let lobby = Arc::new(Lobby::new(4));
+        let (matches_tx, mut matches_rx) = mpsc::channel(1);
+        let (mut host, host_pending) = connect().await;
+        let (mut guest, guest_pending) = connect().await;
+        let matchmaker = Arc::new(Matchmaker::new());
+        let live = Arc::new(LiveMatches::new());
+        tokio::spawn(serve(host_pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+        tokio::spawn(serve(guest_pending, lobby.clone(), matchmaker, live, matches_tx));
+
+        let mut host_reader = FrameReader::new();
+        write_message(&mut host, Codec::Json, &Message::CreateRoom(settings("同事", true))).await.unwrap();
+        let code = match host_reader.read_message(&mut host, Codec::Json).await.unwrap() {
+            Message::RoomCreated(info) => info.code,
+            other => panic!("应收到 RoomCreated，实际 {:?}", other),
+        };
+
+        // 私密房间不在列表中，凭房间码仍能加入，小写也可以
+        let mut guest_reader = FrameReader::new();
+        write_message(&mut guest, Codec::Json, &Message::ListRooms).await.unwrap();
+        let listed = guest_reader.read_message(&mut guest, Codec::Json).await.unwrap();
+        assert!(matches!(listed, Message::RoomList(ref rooms) if rooms.is_empty()));
+        write_message(&mut guest, Codec::Json, &Message::JoinRoom(code.to_lowercase())).await.unwrap();
+
+        let new_match = matches_rx.recv().await.unwrap();
+        assert_eq!(new_match.title, "同事");
+        assert_eq!(new_match.host.socket.peer_addr().unwrap(), host.local_addr().unwrap());
+        assert_eq!(new_match.guests[0].socket.peer_addr().unwrap(), guest.local_addr().unwrap());
+        assert!(lobby.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rated_queue_pairs_players() {
+        let lobby = Arc::new(Lobby::new(4));
+        let matchmaker = Arc::new(Matchmaker::new());
+        tokio::spawn(matchmaker.clone().run());
+        let (matches_tx, mut matches_rx) = mpsc::channel(1);
+        let mut streams = vec![];
+        let mut keys = vec![];
+        for name in ["alice", "bob"] {
+            let (mut stream, pending) = connect().await;
+            tokio::spawn(serve(pending, lobby.clone(), matchmaker.clone(), Arc::new(LiveMatches::new()), matches_tx.clone()));
+            let request = QueueRequest { name: name.to_string(), key: None };
+            write_message(&mut stream, Codec::Json, &Message::JoinQueue(request)).await.unwrap();
+            // 第一次排位先收到密钥，再收到排队结果
+            let mut reader = FrameReader::new();
+            match reader.read_message(&mut stream, Codec::Json).await.unwrap() {
+                Message::PlayerKey(key) => keys.push(key),
+                other => panic!("应收到 PlayerKey，实际 {:?}", other),
+            }
+            let queued = reader.read_message(&mut stream, Codec::Json).await.unwrap();
+            assert!(matches!(queued, Message::Queued(1500)));
+            streams.push(stream);
+        }
+
+        // 同分的两人立即配对，先排队的是玩家1
+        let new_match = matches_rx.recv().await.unwrap();
+        assert_eq!(new_match.title, "alice vs bob");
+        assert_eq!(new_match.host.rated_name.as_deref(), Some("alice"));
+        assert_eq!(new_match.guests[0].rated_name.as_deref(), Some("bob"));
+
+        // 没有密钥就不能以别人认领过的名字排位，带上密钥则照常排队
+        let (mut stream, pending) = connect().await;
+        tokio::spawn(serve(pending, lobby.clone(), matchmaker.clone(), Arc::new(LiveMatches::new()), matches_tx.clone()));
+        let mut reader = FrameReader::new();
+        let request = QueueRequest { name: "alice".to_string(), key: None };
+        write_message(&mut stream, Codec::Json, &Message::JoinQueue(request)).await.unwrap();
+        assert!(matches!(reader.read_message(&mut stream, Codec::Json).await.unwrap(), Message::LobbyError(_)));
+        let request = QueueRequest { name: "alice".to_string(), key: Some(keys[0].clone()) };
+        write_message(&mut stream, Codec::Json, &Message::JoinQueue(request)).await.unwrap();
+        assert!(matches!(reader.read_message(&mut stream, Codec::Json).await.unwrap(), Message::Queued(_)));
+    }
+
+    #[tokio::test]
+    async fn test_room_waits_until_full() {
+        let lobby = Arc::new(Lobby::new(4));
+        let matchmaker = Arc::new(Matchmaker::new());
+        let live = Arc::new(LiveMatches::new());
+        let (matches_tx, mut matches_rx) = mpsc::channel(1);
+        let too_many = RoomSettings { players: config::MATCH_MAX_PLAYERS + 1, ..settings("太多", false) };
+        assert!(lobby.open(too_many).is_err());
+
+        let (mut host, host_pending) = connect().await;
+        tokio::spawn(serve(host_pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+        let mut host_reader = FrameReader::new();
+        let three = RoomSettings { players: 3, ..settings("三人", false) };
+        write_message(&mut host, Codec::Json, &Message::CreateRoom(three)).await.unwrap();
+        let code = match host_reader.read_message(&mut host, Codec::Json).await.unwrap() {
+            Message::RoomCreated(info) => info.code,
+            other => panic!("应收到 RoomCreated，实际 {:?}", other),
+        };
+
+        // 第一人加入后房间仍在列表中，房主和加入者都收到人数变化
+        let mut guests = vec![];
+        for _ in 0..2 {
+            let (mut guest, pending) = connect().await;
+            tokio::spawn(serve(pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+            write_message(&mut guest, Codec::Json, &Message::JoinRoom(code.clone())).await.unwrap();
+            guests.push(guest);
+            if guests.len() == 1 {
+                let update = host_reader.read_message(&mut host, Codec::Json).await.unwrap();
+                assert!(matches!(update, Message::RoomUpdate(ref info) if info.joined == 2 && info.players == 3));
+                let update = FrameReader::new().read_message(&mut guests[0], Codec::Json).await.unwrap();
+                assert!(matches!(update, Message::RoomUpdate(_)));
+                assert_eq!(lobby.list()[0].joined, 2);
+            }
+        }
+
+        // 人满后对局开始，加入者按加入顺序排列
+        let new_match = matches_rx.recv().await.unwrap();
+        assert_eq!(new_match.guests.len(), 2);
+        for (pending, guest) in new_match.guests.iter().zip(&guests) {
+            assert_eq!(pending.socket.peer_addr().unwrap(), guest.local_addr().unwrap());
+        }
+        assert!(lobby.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_guest_leaves_or_drops_from_room() {
+        let lobby = Arc::new(Lobby::new(4));
+        let matchmaker = Arc::new(Matchmaker::new());
+        let live = Arc::new(LiveMatches::new());
+        let (matches_tx, _matches_rx) = mpsc::channel(1);
+        let (mut host, host_pending) = connect().await;
+        tokio::spawn(serve(host_pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+        let mut host_reader = FrameReader::new();
+        let three = RoomSettings { players: 3, ..settings("三人", false) };
+        write_message(&mut host, Codec::Json, &Message::CreateRoom(three)).await.unwrap();
+        let code = match host_reader.read_message(&mut host, Codec::Json).await.unwrap() {
+            Message::RoomCreated(info) => info.code,
+            other => panic!("应收到 RoomCreated，实际 {:?}", other),
+        };
+        let join = |code: String| {
+            let (lobby, matchmaker, live, matches_tx) = (lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone());
+            async move {
+                let (mut guest, pending) = connect().await;
+                tokio::spawn(serve(pending, lobby, matchmaker, live, matches_tx));
+                write_message(&mut guest, Codec::Json, &Message::JoinRoom(code)).await.unwrap();
+                let mut reader = FrameReader::new();
+                assert!(matches!(reader.read_message(&mut guest, Codec::Json).await.unwrap(), Message::RoomUpdate(_)));
+                (guest, reader)
+            }
+        };
+
+        // 加入者主动离开：房主收到人数变化，加入者回到大厅还能继续操作
+        let (mut guest, mut reader) = join(code.clone()).await;
+        assert!(matches!(host_reader.read_message(&mut host, Codec::Json).await.unwrap(), Message::RoomUpdate(ref info) if info.joined == 2));
+        write_message(&mut guest, Codec::Json, &Message::LeaveRoom).await.unwrap();
+        assert!(matches!(host_reader.read_message(&mut host, Codec::Json).await.unwrap(), Message::RoomUpdate(ref info) if info.joined == 1));
+        assert!(matches!(reader.read_message(&mut guest, Codec::Json).await.unwrap(), Message::LobbyError(_)));
+        assert_eq!(lobby.list()[0].joined, 1);
+        write_message(&mut guest, Codec::Json, &Message::ListRooms).await.unwrap();
+        assert!(matches!(reader.read_message(&mut guest, Codec::Json).await.unwrap(), Message::RoomList(ref rooms) if rooms.len() == 1));
+
+        // 加入者掉线：房主收到人数变化，房间列表中的人数也恢复
+        let (guest, _reader) = join(code).await;
+        assert!(matches!(host_reader.read_message(&mut host, Codec::Json).await.unwrap(), Message::RoomUpdate(ref info) if info.joined == 2));
+        drop(guest);
+        assert!(matches!(host_reader.read_message(&mut host, Codec::Json).await.unwrap(), Message::RoomUpdate(ref info) if info.joined == 1));
+        assert_eq!(lobby.list()[0].joined, 1);
+    }
+
+    // 读取下一条不是心跳的消息，answer 为真时像客户端一样回复心跳
+    async fn read_skipping_pings(reader: &mut FrameReader, stream: &mut TcpStream, answer: bool) -> Message {
+        loop {
+            match reader.read_message(stream, Codec::Json).await.unwrap() {
+                Message::Ping(sequence) if answer => write_message(stream, Codec::Json, &Message::Pong(sequence)).await.unwrap(),
+                Message::Ping(_) => {}
+                message => return message,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_clients_time_out_and_return_permit() {
+        let mut lobby = Lobby::new(4);
+        lobby.heartbeat_interval = Duration::from_millis(50);
+        lobby.dead_timeout = Duration::from_millis(300);
+        let lobby = Arc::new(lobby);
+        let matchmaker = Arc::new(Matchmaker::new());
+        let live = Arc::new(LiveMatches::new());
+        let (matches_tx, _matches_rx) = mpsc::channel(1);
+        let semaphore = Arc::new(Semaphore::new(1));
+        let (mut host, mut host_pending) = connect().await;
+        host_pending.permit = Some(semaphore.clone().try_acquire_owned().unwrap());
+        tokio::spawn(serve(host_pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+        let mut host_reader = FrameReader::new();
+        let three = RoomSettings { players: 3, ..settings("三人", false) };
+        write_message(&mut host, Codec::Json, &Message::CreateRoom(three)).await.unwrap();
+        let code = match read_skipping_pings(&mut host_reader, &mut host, true).await {
+            Message::RoomCreated(info) => info.code,
+            other => panic!("应收到 RoomCreated，实际 {:?}", other),
+        };
+
+        // 加入者连接还在但从不回复心跳，房主照常回复，加入者被移出房间
+        let (mut guest, pending) = connect().await;
+        tokio::spawn(serve(pending, lobby.clone(), matchmaker.clone(), live.clone(), matches_tx.clone()));
+        write_message(&mut guest, Codec::Json, &Message::JoinRoom(code)).await.unwrap();
+        let update = read_skipping_pings(&mut host_reader, &mut host, true).await;
+        assert!(matches!(update, Message::RoomUpdate(ref info) if info.joined == 2));
+        let update = read_skipping_pings(&mut host_reader, &mut host, true).await;
+        assert!(matches!(update, Message::RoomUpdate(ref info) if info.joined == 1));
+        assert_eq!(lobby.list()[0].joined, 1);
+
+        // 房主也不再回复，房间关闭，连接名额归还
+        timeout(Duration::from_secs(5), async {
+            while !lobby.list().is_empty() || semaphore.available_permits() == 0 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
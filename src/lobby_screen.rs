@@ -0,0 +1,531 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use std::collections::BTreeMap;
+use std::{fs, io};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration, Instant};
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Alignment, Constraint, Direction as Dire, Layout},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Terminal,
+};
+
+use crate::config;
+use crate::notation;
+use crate::protocol::{
+    write_message, Codec, CoopScore, FrameReader, MatchInfo, MatchMode, Message, PlayerIdentity, QueueRequest, RoomInfo,
+    RoomSettings, SpectateRequest, FEATURE_STACKED,
+};
+
+// 房间码输入框最多接受的字符数
+const MAX_CODE_INPUT: usize = 8;
+
+// 大厅界面所处的状态
+enum Mode {
+    Browsing,             // 浏览公开房间
+    EnteringCode(String), // 正在输入房间码
+    Waiting(RoomInfo),    // 已创建房间，等待对手加入
+    Joined(RoomInfo),     // 已加入别人的房间，等待人满
+    Queued(Option<u32>, Instant), // 排位匹配中，附带服务器告知的等级分和开始排队的时刻
+    Matches,              // 浏览进行中的对局，选择观战
+    Leaderboard,          // 查看合作玩法排行榜
+}
+
+/// 离开大厅的方式
+pub enum LobbyOutcome {
+    Play(PlayerIdentity), // 对局开始，附带自己的玩家身份
+    Spectate(MatchInfo),  // 开始观战
+}
+
+// 按键处理的结果
+enum Action {
+    Send(Vec<Message>),
+    Quit,
+    None,
+}
+
+struct LobbyScreen {
+    rooms: Vec<RoomInfo>,
+    list_state: ListState,
+    matches: Vec<MatchInfo>,
+    match_state: ListState,
+    coop_scores: Vec<CoopScore>,
+    mode: Mode,
+    status: String, // 最近一次请求失败的原因等提示
+    player_name: String,
+    room_size: u8,  // 新建房间的对局人数
+    room_mode: MatchMode, // 新建房间的玩法
+    room_stacked: bool,   // 新建的两人房间是否上下叠放
+    player_keys: Result<BTreeMap<String, String>, String>, // 服务器发放的玩家密钥，密钥文件读不出时为原因
+    features: Vec<String>, // 服务器在 Welcome 中声明开启的功能，没开启的玩法和布局不提供选择
+}
+
+impl LobbyScreen {
+    fn new(player_name: String, features: Vec<String>) -> Self {
+        Self {
+            rooms: vec![],
+            list_state: ListState::default(),
+            matches: vec![],
+            match_state: ListState::default(),
+            coop_scores: vec![],
+            mode: Mode::Browsing,
+            status: String::new(),
+            player_name,
+            room_size: config::MATCH_MIN_PLAYERS,
+            room_mode: MatchMode::default(),
+            room_stacked: false,
+            player_keys: load_player_keys(),
+            features,
+        }
+    }
+
+    fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    fn create_room(&self, private: bool) -> Action {
+        // 指定了 --position 时所有人都从这个局面开始
+        let settings = RoomSettings {
+            name: format!("{} 的房间", self.player_name),
+            private,
+            players: self.room_size,
+            mode: self.room_mode,
+            position: notation::position_from_args(),
+            stacked: self.room_stacked && self.room_size == 2,
+        };
+        Action::Send(vec![Message::CreateRoom(settings)])
+    }
+
+    // 新建房间的布局说明，只有两人房间能上下叠放
+    fn layout(&self) -> &'static str {
+        if self.room_stacked && self.room_size == 2 { " 上下叠放" } else { "" }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) -> Action {
+        match self.mode {
+            Mode::Browsing => match code {
+                KeyCode::Up | KeyCode::Down => {
+                    move_selection(&mut self.list_state, self.rooms.len(), code == KeyCode::Up);
+                    Action::None
+                }
+                KeyCode::Enter => match self.list_state.selected().and_then(|selected| self.rooms.get(selected)) {
+                    Some(room) => Action::Send(vec![Message::JoinRoom(room.code.clone())]),
+                    None => Action::None,
+                },
+                KeyCode::Char('r') => Action::Send(vec![Message::ListRooms]),
+                KeyCode::Char('+') | KeyCode::Char('=') => {
+                    self.room_size = (self.room_size + 1).min(config::MATCH_MAX_PLAYERS);
+                    Action::None
+                }
+                KeyCode::Char('-') => {
+                    self.room_size = (self.room_size - 1).max(config::MATCH_MIN_PLAYERS);
+                    Action::None
+                }
+                // 跳过服务器没有开启的玩法，一轮都没有时停在原处
+                KeyCode::Char('g') => {
+                    let mut mode = self.room_mode;
+                    for _ in 0..MatchMode::ALL.len() {
+                        mode = mode.next();
+                        if self.supports(mode.feature()) {
+                            self.room_mode = mode_from_args(mode);
+                            break;
+                        }
+                    }
+                    Action::None
+                }
+                KeyCode::Char('v') if !self.supports(FEATURE_STACKED) => {
+                    self.status = "服务器没有开启上下叠放".to_string();
+                    Action::None
+                }
+                KeyCode::Char('v') => {
+                    self.room_stacked = !self.room_stacked;
+                    Action::None
+                }
+                KeyCode::Char('c') => self.create_room(false),
+                KeyCode::Char('p') => self.create_room(true),
+                KeyCode::Char('j') => {
+                    self.mode = Mode::EnteringCode(String::new());
+                    Action::None
+                }
+                KeyCode::Char('m') => {
+                    self.mode = Mode::Queued(None, Instant::now());
+                    let key = self.player_keys.as_ref().ok().and_then(|keys| keys.get(&self.player_name).cloned());
+                    Action::Send(vec![Message::JoinQueue(QueueRequest { name: self.player_name.clone(), key })])
+                }
+                KeyCode::Char('s') => {
+                    self.mode = Mode::Matches;
+                    Action::Send(vec![Message::ListMatches])
+                }
+                KeyCode::Char('b') => {
+                    self.mode = Mode::Leaderboard;
+                    Action::Send(vec![Message::ListCoopScores])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+            Mode::EnteringCode(ref mut input) => match code {
+                KeyCode::Char(c) if c.is_ascii_alphanumeric() && input.len() < MAX_CODE_INPUT => {
+                    input.push(c.to_ascii_uppercase());
+                    Action::None
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    Action::None
+                }
+                KeyCode::Enter if !input.is_empty() => {
+                    let code = input.clone();
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::JoinRoom(code)])
+                }
+                KeyCode::Esc => {
+                    self.mode = Mode::Browsing;
+                    Action::None
+                }
+                _ => Action::None,
+            },
+            Mode::Waiting(_) => match code {
+                KeyCode::Esc | KeyCode::Char('l') => {
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::LeaveRoom, Message::ListRooms])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+            // 连接已交给房主，由房主的任务读取 LeaveRoom 并把我们送回大厅
+            Mode::Joined(_) => match code {
+                KeyCode::Esc | KeyCode::Char('l') => {
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::LeaveRoom, Message::ListRooms])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+            Mode::Matches => match code {
+                KeyCode::Up | KeyCode::Down => {
+                    move_selection(&mut self.match_state, self.matches.len(), code == KeyCode::Up);
+                    Action::None
+                }
+                KeyCode::Enter => match self.match_state.selected().and_then(|selected| self.matches.get(selected)) {
+                    Some(info) => {
+                        let request = SpectateRequest { match_id: info.id, delay_secs: spectate_delay() };
+                        Action::Send(vec![Message::Spectate(request)])
+                    }
+                    None => Action::None,
+                },
+                KeyCode::Char('r') => Action::Send(vec![Message::ListMatches]),
+                KeyCode::Esc => {
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::ListRooms])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+            Mode::Leaderboard => match code {
+                KeyCode::Char('r') => Action::Send(vec![Message::ListCoopScores]),
+                KeyCode::Esc => {
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::ListRooms])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+            Mode::Queued(..) => match code {
+                KeyCode::Esc => {
+                    self.mode = Mode::Browsing;
+                    Action::Send(vec![Message::LeaveQueue, Message::ListRooms])
+                }
+                KeyCode::Char('q') => Action::Quit,
+                _ => Action::None,
+            },
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::RoomList(rooms) => {
+                self.rooms = rooms;
+                clamp_selection(&mut self.list_state, self.rooms.len());
+            }
+            Message::MatchList(matches) => {
+                self.matches = matches;
+                clamp_selection(&mut self.match_state, self.matches.len());
+            }
+            Message::CoopScores(scores) => self.coop_scores = scores,
+            Message::RoomCreated(info) => {
+                self.status.clear();
+                self.mode = Mode::Waiting(info);
+            }
+            Message::RoomUpdate(info) => {
+                self.status.clear();
+                self.mode = match self.mode {
+                    Mode::Waiting(_) => Mode::Waiting(info),
+                    _ => Mode::Joined(info),
+                };
+            }
+            // 第一次以这个名字排位，保存密钥，以后排位带上
+            Message::PlayerKey(key) => {
+                let saved = self.player_keys.as_mut().map_err(|e| e.clone()).and_then(|keys| {
+                    keys.insert(self.player_name.clone(), key);
+                    save_player_keys(keys)
+                });
+                if let Err(e) = saved {
+                    self.status = format!("玩家密钥没有保存，下次无法以 {} 排位：{}", self.player_name, e);
+                }
+            }
+            Message::Queued(rating) => {
+                if let Mode::Queued(ref mut known, _) = self.mode {
+                    *known = Some(rating);
+                }
+            }
+            Message::LobbyError(reason) => {
+                // 排位失败或所在的房间被关闭，回到房间列表
+                if let Mode::Queued(..) | Mode::Joined(_) = self.mode {
+                    self.mode = Mode::Browsing;
+                }
+                self.status = reason;
+            }
+            // 大厅中不会收到其他消息，忽略
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
+        let help = match self.mode {
+            Mode::Browsing => "↑↓ 选择  Enter 加入  c 创建公开房间  p 创建私密房间  +/- 房间人数  g 玩法  v 两人上下叠放  j 输入房间码  m 排位赛  s 观战  b 合作排行榜  r 刷新  q 退出",
+            Mode::EnteringCode(_) => "输入对手告诉你的房间码，Enter 加入，Esc 返回",
+            Mode::Waiting(_) => "把房间码告诉对手，人满后对局立即开始  Esc 离开房间  q 退出",
+            Mode::Joined(_) => "已加入房间，人满后对局立即开始  Esc 离开房间  q 退出",
+            Mode::Queued(..) => "正在寻找等级分相近的对手，等得越久范围越大  Esc 取消  q 退出",
+            Mode::Matches => "↑↓ 选择  Enter 观战  r 刷新  Esc 返回  q 退出",
+            Mode::Leaderboard => "合作玩法的最高共同得分  r 刷新  Esc 返回  q 退出",
+        };
+        let status = match self.mode {
+            Mode::EnteringCode(ref input) => format!("房间码：{}_", input),
+            _ => self.status.clone(),
+        };
+        // 观战模式列出进行中的对局，否则列出公开房间
+        let (items, empty_title, title): (Vec<ListItem>, _, _) = match self.mode {
+            Mode::Matches => (
+                self.matches.iter().map(|info| ListItem::new(format!("#{}  {}", info.id, info.title))).collect(),
+                "进行中的对局（暂无，按 r 刷新）".to_string(),
+                "进行中的对局".to_string(),
+            ),
+            Mode::Leaderboard => (
+                self.coop_scores
+                    .iter()
+                    .enumerate()
+                    .map(|(place, entry)| {
+                        let text = format!(
+                            "{:>2}. {}  {}  合作·{}  {}人",
+                            place + 1,
+                            entry.score,
+                            entry.team,
+                            entry.control.describe(),
+                            entry.players
+                        );
+                        ListItem::new(text)
+                    })
+                    .collect(),
+                "合作排行榜（暂无记录）".to_string(),
+                "合作排行榜".to_string(),
+            ),
+            _ => (
+                self.rooms
+                    .iter()
+                    .map(|room| {
+                        let layout = if room.stacked { "  上下叠放" } else { "" };
+                        let text = format!("{}  {}  {}  {}/{}人{}", room.code, room.name, room.mode.describe(), room.joined, room.players, layout);
+                        ListItem::new(text)
+                    })
+                    .collect(),
+                format!("公开房间（暂无，按 c 创建）  新建房间：{}人 {}{}", self.room_size, self.room_mode.describe(), self.layout()),
+                format!("公开房间  新建房间：{}人 {}{}", self.room_size, self.room_mode.describe(), self.layout()),
+            ),
+        };
+        // 等待中时显示在中间的文字
+        let waiting = match self.mode {
+            Mode::Waiting(ref room) | Mode::Joined(ref room) => {
+                let kind = if room.private { "私密房间" } else { "公开房间" };
+                Some(("等待中", vec![
+                    Spans::from(format!("{}：{}（{}）", kind, room.name, room.mode.describe())),
+                    Spans::from(Span::styled(
+                        format!("房间码  {}", room.code),
+                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                    )),
+                    Spans::from(format!("等待对手加入… {}/{}人", room.joined, room.players)),
+                ]))
+            }
+            Mode::Queued(rating, since) => {
+                let rating = rating.map_or("--".to_string(), |rating| rating.to_string());
+                Some(("排位赛", vec![
+                    Spans::from(format!("玩家：{}", self.player_name)),
+                    Spans::from(Span::styled(
+                        format!("等级分  {}", rating),
+                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                    )),
+                    Spans::from(format!("匹配中… 已等待 {} 秒", since.elapsed().as_secs())),
+                ]))
+            }
+            _ => None,
+        };
+        let list_state = match self.mode {
+            Mode::Matches => &mut self.match_state,
+            _ => &mut self.list_state,
+        };
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Dire::Vertical)
+                .margin(1)
+                .constraints([Constraint::Length(3), Constraint::Min(3), Constraint::Length(3)].as_ref())
+                .split(f.size());
+
+            let help = Paragraph::new(help)
+                .block(Block::default().title("对战大厅").borders(Borders::ALL))
+                .alignment(Alignment::Center);
+            f.render_widget(help, chunks[0]);
+
+            match waiting {
+                Some((title, text)) => {
+                    let paragraph = Paragraph::new(text)
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .alignment(Alignment::Center);
+                    f.render_widget(paragraph, chunks[1]);
+                }
+                None => {
+                    let title = if items.is_empty() { empty_title.as_str() } else { title.as_str() };
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL))
+                        .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                        .highlight_symbol("> ");
+                    f.render_stateful_widget(list, chunks[1], list_state);
+                }
+            }
+
+            let status = Paragraph::new(Span::styled(status, Style::default().fg(Color::Red)))
+                .block(Block::default().borders(Borders::ALL));
+            f.render_widget(status, chunks[2]);
+        })?;
+        Ok(())
+    }
+}
+
+// 上下移动列表中的选中项
+fn move_selection(state: &mut ListState, len: usize, up: bool) {
+    let selected = match state.selected() {
+        Some(selected) if up => selected.saturating_sub(1),
+        Some(selected) => (selected + 1).min(len.saturating_sub(1)),
+        None => 0,
+    };
+    state.select(if len == 0 { None } else { Some(selected) });
+}
+
+// 列表刷新后保持选中项在范围内
+fn clamp_selection(state: &mut ListState, len: usize) {
+    let selected = match state.selected() {
+        _ if len == 0 => None,
+        Some(selected) => Some(selected.min(len - 1)),
+        None => Some(0),
+    };
+    state.select(selected);
+}
+
+// 观战延迟秒数，"--spectate-delay=<秒>" 指定，默认不延迟
+fn spectate_delay() -> u32 {
+    std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.strip_prefix("--spectate-delay=").and_then(|secs| secs.parse().ok()))
+        .unwrap_or(0)
+}
+
+// 竞速玩法的目标和时限由 "--race-target=<瓷砖>"、"--race-time=<秒>" 指定，
+// 回合制玩法的棋钟和每步加时由 "--clock=<秒>"、"--increment=<秒>" 指定，
+// 反派出块玩法的出块时限由 "--spawn-time=<秒>" 指定，默认取自 config
+fn mode_from_args(mode: MatchMode) -> MatchMode {
+    let arg = |prefix: &str| std::env::args().skip(1).find_map(|arg| arg.strip_prefix(prefix).and_then(|value| value.parse().ok()));
+    match mode {
+        MatchMode::Race { target, time_limit_secs } => MatchMode::Race {
+            target: arg("--race-target=").unwrap_or(target),
+            time_limit_secs: arg("--race-time=").unwrap_or(time_limit_secs),
+        },
+        MatchMode::TurnBased { clock_secs, increment_secs } => MatchMode::TurnBased {
+            clock_secs: arg("--clock=").unwrap_or(clock_secs),
+            increment_secs: arg("--increment=").unwrap_or(increment_secs),
+        },
+        MatchMode::Spawner { spawn_secs } => MatchMode::Spawner { spawn_secs: arg("--spawn-time=").unwrap_or(spawn_secs) },
+        other => other,
+    }
+}
+
+// 按玩家名保存的密钥，文件不存在时为空；读不出时返回原因，不去覆盖它，以免丢掉其他名字的密钥
+fn load_player_keys() -> Result<BTreeMap<String, String>, String> {
+    match fs::read_to_string(config::PLAYER_KEYS_FILE) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", config::PLAYER_KEYS_FILE, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(format!("{}: {}", config::PLAYER_KEYS_FILE, e)),
+    }
+}
+
+fn save_player_keys(keys: &BTreeMap<String, String>) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(keys).map_err(|e| e.to_string())?;
+    fs::write(config::PLAYER_KEYS_FILE, text).map_err(|e| e.to_string())
+}
+
+// 玩家名，用于房间名和排位赛等级分，"--name=<玩家名>" 指定，默认为系统用户名
+fn player_name() -> String {
+    if let Some(name) = std::env::args().skip(1).find_map(|arg| arg.strip_prefix("--name=").map(str::to_string)) {
+        if !name.trim().is_empty() {
+            return name.trim().to_string();
+        }
+    }
+    match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        Ok(user) if !user.is_empty() => user,
+        _ => "玩家".to_string(),
+    }
+}
+
+/// 大厅界面：浏览公开房间、创建房间、凭房间码加入、排位或观战，直到对局或观战开始
+// 玩家按 q 退出时返回 None
+pub async fn run_lobby(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    stream: &mut TcpStream,
+    reader: &mut FrameReader,
+    codec: Codec,
+    features: &[String],
+) -> Result<Option<LobbyOutcome>, Box<dyn std::error::Error>> {
+    let mut screen = LobbyScreen::new(player_name(), features.to_vec());
+    write_message(stream, codec, &Message::ListRooms).await?;
+    terminal.clear()?;
+    loop {
+        screen.draw(terminal)?;
+
+        if event::poll(Duration::from_millis(10))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match screen.handle_key(key.code) {
+                        Action::Send(messages) => {
+                            for message in messages {
+                                write_message(stream, codec, &message).await?;
+                            }
+                        }
+                        Action::Quit => return Ok(None), // 断开连接后服务器会关闭我们的房间
+                        Action::None => {}
+                    }
+                }
+            }
+        }
+
+        // 读取可以被超时安全地打断，没读完的帧留到下一轮
+        if let Ok(message) = timeout(Duration::from_millis(50), reader.read_message(stream, codec)).await {
+            match message? {
+                Message::PlayerIdentity(identity) => return Ok(Some(LobbyOutcome::Play(identity))),
+                Message::Spectating(info) => return Ok(Some(LobbyOutcome::Spectate(info))),
+                // 大厅中也会收到心跳，不回复会被当作掉线
+                Message::Ping(sequence) => write_message(stream, codec, &Message::Pong(sequence)).await?,
+                message => screen.handle_message(message),
+            }
+        }
+    }
+}
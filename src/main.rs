@@ -16,6 +16,7 @@ mod game_board;
 mod game_controller;
 mod io_manager;
 mod bridge;
+mod notation;
 
 
 use game_board::{Direction, TileMovement, Position};
@@ -152,8 +153,10 @@ fn animate_move<B: Backend>(
     let tile_height: u16 = 5;
     let gap: u16 = 1;
 
-    let start_x = (size.as_ref().unwrap().width.saturating_sub(tile_width * 4 + (gap * 3))) / 2;
-    let start_y = (size.as_ref().unwrap().height.saturating_sub(tile_height * 4 + (gap * 3))) / 2;
+    // 棋盘边长不一定是4（例如从记谱导入的局面）
+    let board_size = game_board.size() as u16;
+    let start_x = (size.as_ref().unwrap().width.saturating_sub(tile_width * board_size + (gap * (board_size - 1)))) / 2;
+    let start_y = (size.as_ref().unwrap().height.saturating_sub(tile_height * board_size + (gap * (board_size - 1)))) / 2;
 
     let num_steps = 5;  // 动画的步骤数
 
@@ -179,7 +182,7 @@ fn animate_move<B: Backend>(
 
     // 动画结束后再次绘制静态的棋盘状态
     terminal.draw(|frame| {
-        draw_board(frame, &game_board.get_tiles());
+        draw_board(frame, game_board.get_tiles());
     })?;
 
     Ok(())  // 确保返回一个 Result
@@ -192,8 +195,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 期盼逻辑
     // 允许 10ms 后续这种参数放config
     let mut io_manager = IOManager::new(10);
-    let mut game_board = GameBoard::new();
-    game_board.spawn_tile();
+    // 若指定了 --position 则从该局面开始，否则随机开局
+    let mut game_board = match notation::position_from_args() {
+        Some(position) => GameBoard::from_notation(&position)?,
+        None => {
+            let mut board = GameBoard::new();
+            board.spawn_tile();
+            board
+        }
+    };
 
 
 
@@ -206,7 +216,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     
     terminal.draw(|f| {
-        draw_board(f, &game_board.get_tiles());
+        draw_board(f, game_board.get_tiles());
     })?;
 
 
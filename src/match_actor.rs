@@ -0,0 +1,1874 @@
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{interval_at, sleep_until, Duration, Instant, Interval, MissedTickBehavior};
+
+use crate::bridge_schedule::BridgeSchedule;
+use crate::config;
+use crate::connection::{spawn_connection, ConnectionEvent, PendingClient};
+use crate::game_board::Direction;
+use crate::garbage::{attack_power, drop_garbage, GarbageQueue};
+use crate::protocol::{
+    BridgeForecast, BridgeLink, BridgePhase, BridgeStatus, ClockStatus, CoopControl, CoopStatus, Elimination, GameState, Latency, MatchEndReason, MatchInfo, MatchMode, MatchResult,
+    Message, PlayerIdentity, RaceStatus, SpawnerStatus, TilePlacement,
+};
+use crate::session::{Reconnect, SessionRegistry};
+use crate::spectate::{delayed, LiveMatches, Spectator, SpectatorSender};
+use crate::{Bridge, GameBoard};
+
+// 重连队列长度
+const RECONNECT_QUEUE_SIZE: usize = 4;
+// 等待加入的观众队列长度
+const SPECTATOR_QUEUE_SIZE: usize = 8;
+
+// 对局任务每次被唤醒的原因
+enum Wakeup {
+    Event(ConnectionEvent),
+    Reconnect(Reconnect),
+    Spectator(Spectator),
+    GraceExpired(usize), // 该玩家掉线后未在宽限期内重连
+    Heartbeat,           // 该向在线的玩家发送心跳了
+    IdleExpired(usize),  // 该玩家太久没有移动
+    TimeUp,              // 竞速玩法的时限到了
+    GarbageDue(usize),   // 该玩家排队中的垃圾该落下了
+    VoteClosed,          // 合作玩法的投票窗口关闭了
+    OutOfTime(usize),    // 回合制玩法中该玩家的棋钟走完了
+    SpawnExpired,        // 反派出块玩法中出块方没有在时限内出块
+    BridgeChange,        // 桥梁时间表的下一段开始了
+}
+
+/// 一局对战，独占所有玩家的棋盘和桥梁，所有改动都在这一个任务里按顺序处理，不需要加锁
+// 连接任务把收到的消息发到 events，对局通过 players 中的发送端把消息交给各自的写任务
+// 以玩家下标索引的字段长度都等于玩家人数，只有合作玩法中 boards 只有一块
+pub struct MatchActor {
+    boards: Vec<GameBoard>,
+    bridges: Vec<(BridgeLink, Bridge)>,   // 每座桥梁连接的两块棋盘和桥梁本身
+    players: Vec<mpsc::Sender<Message>>,
+    connections: Vec<usize>,              // 每名玩家当前连接的编号
+    next_connection: usize,
+    disconnected: Vec<Option<Instant>>,   // 玩家掉线的时刻，重连后清空
+    events_tx: mpsc::Sender<ConnectionEvent>, // 重连后新连接的读任务也发到这里
+    events: mpsc::Receiver<ConnectionEvent>,
+    reconnect_tx: mpsc::Sender<Reconnect>,
+    reconnects: mpsc::Receiver<Reconnect>,
+    sessions: Option<Arc<SessionRegistry>>,
+    tokens: Vec<String>,                  // 各玩家的会话令牌，未开启重连时为空
+    grace_period: Duration,
+    heartbeat_interval: Duration,
+    idle_timeout: Duration,
+    last_action: Vec<Instant>,            // 玩家最近一次移动的时刻，用来判断是否长时间未操作
+    next_ping: u64,
+    pings: Vec<Option<(u64, Instant)>>,   // 尚未收到回复的心跳序号和发送时刻
+    latency: Latency,
+    spectators: Vec<SpectatorSender>, // 观众只接收棋盘、延迟和结果，发来的消息一律忽略
+    spectator_tx: mpsc::Sender<Spectator>,
+    spectator_rx: mpsc::Receiver<Spectator>,
+    live: Option<(Arc<LiveMatches>, MatchInfo)>, // 登记在观战列表中的对局
+    mode: MatchMode,
+    eliminated: Vec<usize>,               // 淘汰赛中已出局的玩家，按出局先后排列
+    deadline: Option<Instant>,            // 竞速玩法时间到的时刻，开局时设置
+    garbage: Vec<GarbageQueue>,           // 垃圾攻击玩法中各玩家即将落下的垃圾
+    garbage_delay: Duration,              // 垃圾从发出到落下的时间
+    turn: usize,                          // 合作玩法轮流移动和回合制玩法中轮到的玩家，反派出块玩法中本轮的滑动方
+    votes: Vec<(usize, Direction)>,       // 合作玩法本轮的投票，按投票先后排列
+    vote_deadline: Option<Instant>,       // 本轮投票窗口关闭的时刻，第一票投出时设置
+    clocks: Vec<Duration>,                // 回合制玩法中各玩家棋钟的剩余时间，不含轮到的玩家本回合已用的时间
+    turn_started: Instant,                // 本回合开始的时刻
+    spawn_deadline: Option<Instant>,      // 反派出块玩法中出块的时限，滑动方移动后设置，出块后清空
+    schedules: Vec<(BridgeLink, ScheduledBridge)>, // 每座桥梁各自的时间表，与 bridges 一一对应，为空时桥梁保持创建时的设置
+}
+
+// 一座桥梁的时间表，next 为下一段和它开始的时刻
+struct ScheduledBridge {
+    schedule: BridgeSchedule,
+    next: (Instant, BridgePhase),
+}
+
+impl MatchActor {
+    // players 中第 i 名玩家的连接编号应为 i，boards 与 players 一一对应，合作玩法中只传一块共用的棋盘
+    pub fn new(
+        boards: Vec<GameBoard>,
+        bridges: Vec<(BridgeLink, Bridge)>,
+        players: Vec<mpsc::Sender<Message>>,
+        events_tx: mpsc::Sender<ConnectionEvent>,
+        events: mpsc::Receiver<ConnectionEvent>,
+    ) -> Self {
+        assert!(boards.len() == players.len() || boards.len() == 1);
+        let count = players.len();
+        let (reconnect_tx, reconnects) = mpsc::channel(RECONNECT_QUEUE_SIZE);
+        let (spectator_tx, spectator_rx) = mpsc::channel(SPECTATOR_QUEUE_SIZE);
+        Self {
+            boards,
+            bridges,
+            players,
+            connections: (0..count).collect(),
+            next_connection: count,
+            disconnected: vec![None; count],
+            events_tx,
+            events,
+            reconnect_tx,
+            reconnects,
+            sessions: None,
+            tokens: vec![],
+            grace_period: Duration::ZERO,
+            heartbeat_interval: Duration::from_secs(config::HEARTBEAT_INTERVAL_SECS),
+            idle_timeout: Duration::from_secs(config::IDLE_TIMEOUT_SECS),
+            last_action: vec![Instant::now(); count],
+            next_ping: 0,
+            pings: vec![None; count],
+            latency: Latency { players_ms: vec![None; count] },
+            spectators: vec![],
+            spectator_tx,
+            spectator_rx,
+            live: None,
+            mode: MatchMode::Classic,
+            eliminated: vec![],
+            deadline: None,
+            garbage: (0..count).map(|_| GarbageQueue::default()).collect(),
+            garbage_delay: Duration::from_millis(config::GARBAGE_DELAY_MS),
+            turn: 0,
+            votes: vec![],
+            vote_deadline: None,
+            clocks: vec![],
+            turn_started: Instant::now(),
+            spawn_deadline: None,
+            schedules: vec![],
+        }
+    }
+
+    // 设置玩法，默认为经典玩法
+    pub fn set_mode(&mut self, mode: MatchMode) {
+        self.mode = mode;
+    }
+
+    // 修改垃圾从发出到落下的时间，默认取自 config，只有测试需要缩短
+    #[cfg(test)]
+    pub fn set_garbage_delay(&mut self, delay: Duration) {
+        self.garbage_delay = delay;
+    }
+
+    // 按每局的种子为每座桥梁各排一份时间表，开局后每座桥梁分别定时开关、换行、换方向和额度，每次变化都发给所有人
+    // 第 i 座桥梁的种子为 seed + i，同一对棋盘之间的各座桥梁、不同棋盘之间的桥梁互不相干
+    pub fn enable_bridge_schedule(&mut self, seed: u64) {
+        let now = Instant::now();
+        let lines = self.boards.first().map_or(0, |board| board.size()) as u8;
+        self.schedules = self
+            .bridges
+            .iter()
+            .enumerate()
+            .map(|(index, (link, bridge))| {
+                let mut schedule = BridgeSchedule::new(seed.wrapping_add(index as u64), bridge.line() as u8, lines, bridge.is_vertical());
+                let next = (now, schedule.next_phase());
+                (*link, ScheduledBridge { schedule, next })
+            })
+            .collect();
+    }
+
+    // 登记到观战列表，观众可以凭对局编号加入，对局结束时从列表中移除
+    pub fn enable_spectating(&mut self, live: Arc<LiveMatches>, title: &str) {
+        let info = live.register(title, self.spectator_tx.clone());
+        self.live = Some((live, info));
+    }
+
+    // 修改心跳间隔和未操作判负的时限，默认取自 config，只有测试需要缩短
+    #[cfg(test)]
+    pub fn set_timeouts(&mut self, heartbeat_interval: Duration, idle_timeout: Duration) {
+        self.heartbeat_interval = heartbeat_interval;
+        self.idle_timeout = idle_timeout;
+    }
+
+    // 开启断线重连：为每名玩家登记会话令牌，掉线的玩家在 grace_period 内可凭令牌回到对局
+    pub fn enable_resume(&mut self, sessions: Arc<SessionRegistry>, grace_period: Duration) {
+        self.tokens = (0..self.players.len())
+            .map(|player| sessions.register(player, self.reconnect_tx.clone()))
+            .collect();
+        self.sessions = Some(sessions);
+        self.grace_period = grace_period;
+    }
+
+    // 运行到对局结束为止，结束时把结果发给所有玩家并返回
+    // 返回后发送端被丢弃，写任务发完队列中的消息后关闭连接
+    pub async fn run(mut self) -> MatchResult {
+        self.start();
+        // 第一次心跳在一个间隔之后，不和开局的消息挤在一起
+        let mut heartbeat = interval_at(Instant::now() + self.heartbeat_interval, self.heartbeat_interval);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let result = loop {
+            let outcome = match self.next_wakeup(&mut heartbeat).await {
+                Wakeup::Event(event) => self.handle_event(event),
+                Wakeup::Reconnect((player, client)) => {
+                    self.resume(player, client);
+                    None
+                }
+                Wakeup::Spectator((client, delay)) => {
+                    self.add_spectator(client, delay);
+                    None
+                }
+                Wakeup::GraceExpired(player) => {
+                    println!("Player {} did not reconnect in time", player + 1);
+                    self.send_to_others(player, Message::OpponentLeft(player as u8 + 1));
+                    self.lose(player, MatchEndReason::OpponentLeft)
+                }
+                Wakeup::Heartbeat => {
+                    self.ping();
+                    None
+                }
+                Wakeup::IdleExpired(player) => {
+                    println!("Player {} has been idle for too long", player + 1);
+                    self.lose(player, MatchEndReason::Idle)
+                }
+                Wakeup::TimeUp => {
+                    println!("Time is up");
+                    let everyone: Vec<usize> = (0..self.boards.len()).collect();
+                    Some((self.leader(&everyone), MatchEndReason::TimeUp))
+                }
+                Wakeup::GarbageDue(player) => {
+                    let amount = self.garbage[player].take_due(Instant::now());
+                    let dropped = drop_garbage(&mut self.boards[player], amount, &mut rand::thread_rng());
+                    println!("Dropped {} garbage tiles on player {}", dropped, player + 1);
+                    self.broadcast();
+                    self.decide()
+                }
+                Wakeup::VoteClosed => {
+                    self.close_vote();
+                    self.decide()
+                }
+                Wakeup::OutOfTime(player) => {
+                    println!("Player {} ran out of time", player + 1);
+                    self.lose(player, MatchEndReason::OutOfTime)
+                }
+                Wakeup::BridgeChange => {
+                    self.change_bridges();
+                    self.broadcast();
+                    None
+                }
+                Wakeup::SpawnExpired => {
+                    println!("Spawner ran out of time, spawning randomly");
+                    self.boards[self.turn].spawn_tile();
+                    self.finish_spawn();
+                    self.decide()
+                }
+            };
+            if let Some((winner, reason)) = outcome {
+                break self.end_match(winner, reason);
+            }
+        };
+        if let Some(ref sessions) = self.sessions {
+            for token in self.tokens.iter() {
+                sessions.remove(token);
+            }
+        }
+        result
+    }
+
+    // 等待下一个事件、重连、心跳，或最早的宽限期、未操作时限、竞速时限、投票窗口、棋钟、出块时限到期，垃圾该落下或桥梁该变化了
+    // 掉线的玩家只计宽限期，不计未操作时限；已出局的玩家两者都不计
+    async fn next_wakeup(&mut self, heartbeat: &mut Interval) -> Wakeup {
+        let expiry = self
+            .alive()
+            .into_iter()
+            .filter_map(|player| self.disconnected[player].map(|at| (at + self.grace_period, player)))
+            .min();
+        let idle_deadline = self
+            .alive()
+            .into_iter()
+            .filter(|&player| self.disconnected[player].is_none() && self.counts_idle(player))
+            .map(|player| (self.last_action[player] + self.idle_timeout, player))
+            .min();
+        let idle = async {
+            match idle_deadline {
+                Some((deadline, player)) => {
+                    sleep_until(deadline).await;
+                    player
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let grace = async {
+            match expiry {
+                Some((deadline, player)) => {
+                    sleep_until(deadline).await;
+                    player
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let garbage_due = self
+            .garbage
+            .iter()
+            .enumerate()
+            .filter_map(|(player, queue)| queue.next_due().map(|due| (due, player)))
+            .min();
+        let garbage = async {
+            match garbage_due {
+                Some((due, player)) => {
+                    sleep_until(due).await;
+                    player
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let deadline = self.deadline;
+        let time_up = async {
+            match deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        let flag_fall = self.clocks.get(self.turn).map(|&clock| (self.turn_started + clock, self.turn));
+        let out_of_time = async {
+            match flag_fall {
+                Some((deadline, player)) => {
+                    sleep_until(deadline).await;
+                    player
+                }
+                None => std::future::pending().await,
+            }
+        };
+        let spawn_deadline = self.spawn_deadline;
+        let spawn_expired = async {
+            match spawn_deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        let bridge_change = self.schedules.iter().map(|(_, scheduled)| scheduled.next.0).min();
+        let bridge_changed = async {
+            match bridge_change {
+                Some(at) => sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        let vote_deadline = self.vote_deadline;
+        let vote_closed = async {
+            match vote_deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            // 对局自己持有 events_tx，管道不会关闭
+            Some(event) = self.events.recv() => Wakeup::Event(event),
+            Some(reconnect) = self.reconnects.recv() => Wakeup::Reconnect(reconnect),
+            Some(spectator) = self.spectator_rx.recv() => Wakeup::Spectator(spectator),
+            player = grace => Wakeup::GraceExpired(player),
+            _ = heartbeat.tick() => Wakeup::Heartbeat,
+            player = idle => Wakeup::IdleExpired(player),
+            _ = time_up => Wakeup::TimeUp,
+            player = garbage => Wakeup::GarbageDue(player),
+            _ = vote_closed => Wakeup::VoteClosed,
+            player = out_of_time => Wakeup::OutOfTime(player),
+            _ = spawn_expired => Wakeup::SpawnExpired,
+            _ = bridge_changed => Wakeup::BridgeChange,
+        }
+    }
+
+    // 该玩家是否受未操作时限约束：回合制玩法由棋钟代替；反派出块玩法只计等待移动的滑动方，出块方由出块时限约束
+    fn counts_idle(&self, player: usize) -> bool {
+        match self.mode {
+            MatchMode::TurnBased { .. } => false,
+            MatchMode::Spawner { .. } => player == self.turn && self.spawn_deadline.is_none(),
+            _ => true,
+        }
+    }
+
+    // 处理连接发来的事件，对局结束时返回 (胜者下标, 原因)
+    fn handle_event(&mut self, event: ConnectionEvent) -> Option<(Option<usize>, MatchEndReason)> {
+        let (connection, message) = match event {
+            ConnectionEvent::Message(connection, message) => (connection, Some(message)),
+            ConnectionEvent::Closed(connection, reason) => {
+                println!("Connection {} closed: {}", connection, reason);
+                (connection, None)
+            }
+        };
+        // 被重连替换掉的旧连接迟到的事件直接丢弃
+        let player = self.connections.iter().position(|&c| c == connection)?;
+        // 出局的玩家只是在观战，掉线也不影响对局
+        if self.eliminated.contains(&player) {
+            match message {
+                Some(Message::Pong(sequence)) => self.record_pong(player, sequence),
+                None => {
+                    self.disconnected[player] = Some(Instant::now());
+                    self.pings[player] = None;
+                }
+                _ => {}
+            }
+            return None;
+        }
+        match message {
+            Some(Message::PlayerAction(action)) => {
+                self.last_action[player] = Instant::now();
+                match self.mode {
+                    MatchMode::Coop(control) => self.handle_coop_action(player, control, action.direction),
+                    MatchMode::TurnBased { increment_secs, .. } => self.handle_turn(player, increment_secs, action.direction),
+                    MatchMode::Spawner { spawn_secs } => self.handle_slide(player, spawn_secs, action.direction),
+                    _ => self.handle_action(player, action.direction),
+                }
+                self.decide()
+            }
+            Some(Message::Pong(sequence)) => {
+                self.record_pong(player, sequence);
+                None
+            }
+            Some(Message::SpawnTile(placement)) => {
+                self.handle_spawn(player, placement);
+                self.decide()
+            }
+            Some(Message::Forfeit) => {
+                println!("Player {} forfeited", player + 1);
+                self.lose(player, MatchEndReason::Forfeit)
+            }
+            Some(message) => {
+                eprintln!("Unexpected message from player {}: {:?}", player + 1, message);
+                None
+            }
+            None if self.tokens.is_empty() => {
+                // 未开启重连，掉线直接判负
+                self.send_to_others(player, Message::OpponentLeft(player as u8 + 1));
+                self.lose(player, MatchEndReason::OpponentLeft)
+            }
+            None => {
+                println!("Player {} disconnected, waiting for reconnect", player + 1);
+                self.disconnected[player] = Some(Instant::now());
+                self.pings[player] = None;
+                self.send_to_others(player, Message::OpponentDisconnected(player as u8 + 1));
+                None
+            }
+        }
+    }
+
+    // 玩家凭令牌重连：换上新连接，重新发送身份、令牌和当前棋盘
+    // 旧连接可能还没发现断开（例如半开的 TCP 连接），直接丢弃它的发送端即可
+    fn resume(&mut self, player: usize, client: PendingClient) {
+        println!("Player {} reconnected", player + 1);
+        let connection = self.next_connection;
+        self.next_connection += 1;
+        self.players[player] = spawn_connection(client, connection, self.events_tx.clone());
+        self.connections[player] = connection;
+        self.disconnected[player] = None;
+        // 重连后重新计算未操作时间
+        self.last_action[player] = Instant::now();
+
+        let identity = PlayerIdentity { player_number: player as u8 + 1 };
+        self.send_to(player, Message::PlayerIdentity(identity));
+        self.send_to(player, Message::SessionToken(self.tokens[player].clone()));
+        self.broadcast();
+        self.send_to_others(player, Message::OpponentReconnected(player as u8 + 1));
+    }
+
+    // 观众加入：先告诉它观看的是哪一局，然后发送当前棋盘，有延迟时棋盘和之后的消息都经延迟转发
+    fn add_spectator(&mut self, client: PendingClient, delay: Duration) {
+        let info = match self.live {
+            Some((_, ref info)) => info.clone(),
+            None => return,
+        };
+        let connection = self.next_connection;
+        self.next_connection += 1;
+        let sender = spawn_connection(client, connection, self.events_tx.clone());
+        let _ = sender.try_send(Message::Spectating(info));
+        let sender = match delay.is_zero() {
+            true => SpectatorSender::Live(sender),
+            false => SpectatorSender::Delayed(delayed(sender, delay)),
+        };
+        sender.send(Message::GameState(Box::new(self.game_state(vec![]))));
+        println!("Spectator joined (connection {}, delay {:?})", connection, delay);
+        self.spectators.push(sender);
+    }
+
+    // 发给所有观众，已断开的观众随之移除
+    fn send_to_spectators(&mut self, message: &Message) {
+        self.spectators.retain(|spectator| spectator.send(message.clone()));
+    }
+
+    // 给在线的玩家发送心跳，上一次心跳还没回复的不再计算延迟
+    fn ping(&mut self) {
+        let sequence = self.next_ping;
+        self.next_ping += 1;
+        for player in 0..self.players.len() {
+            if self.disconnected[player].is_none() {
+                self.pings[player] = Some((sequence, Instant::now()));
+                self.send_to(player, Message::Ping(sequence));
+            }
+        }
+    }
+
+    // 收到心跳回复，更新该玩家的往返延迟并告诉所有人
+    fn record_pong(&mut self, player: usize, sequence: u64) {
+        let sent = match self.pings[player] {
+            Some((expected, sent)) if expected == sequence => sent,
+            _ => return, // 过期的回复
+        };
+        self.pings[player] = None;
+        self.latency.players_ms[player] = Some(sent.elapsed().as_millis() as u32);
+        let message = Message::Latency(self.latency.clone());
+        for player in 0..self.players.len() {
+            self.send_to(player, message.clone());
+        }
+        self.send_to_spectators(&message);
+    }
+
+    // 根据所有棋盘判断对局是否结束，返回 (胜者下标, 原因)，胜者为 None 表示平局
+    fn decide(&mut self) -> Option<(Option<usize>, MatchEndReason)> {
+        match self.mode {
+            MatchMode::Classic | MatchMode::Garbage | MatchMode::TurnBased { .. } => self.decide_classic(),
+            MatchMode::BattleRoyale => self.decide_battle_royale(),
+            MatchMode::Race { target, .. } => self.decide_race(target),
+            MatchMode::Coop(_) => is_locked(&self.boards[0]).then_some((None, MatchEndReason::NoMoves)),
+            MatchMode::Spawner { .. } => self.decide_spawner(),
+        }
+    }
+
+    // 滑动方出块后无路可走时本轮结束，交换角色开始下一轮，滑动的是新滑动方自己的棋盘；
+    // 每人都滑动过一轮后比较两块棋盘的分数，合成2048也不提前结束
+    fn decide_spawner(&mut self) -> Option<(Option<usize>, MatchEndReason)> {
+        if self.spawn_deadline.is_some() || !is_locked(&self.boards[self.turn]) {
+            return None;
+        }
+        if self.turn + 1 == self.players.len() {
+            let everyone: Vec<usize> = (0..self.players.len()).collect();
+            return Some((self.leader(&everyone), MatchEndReason::RolesSwapped));
+        }
+        self.turn += 1;
+        println!("Roles swapped, player {} is now sliding", self.turn + 1);
+        self.last_action[self.turn] = Instant::now();
+        self.broadcast();
+        None
+    }
+
+    // 先合成 target 者胜，同一步有多人合成时比较他们的分数；无路可走的玩家只是停在原地，
+    // 所有人都无路可走时不必等到时间到，直接比较全体分数
+    fn decide_race(&self, target: u32) -> Option<(Option<usize>, MatchEndReason)> {
+        let everyone: Vec<usize> = (0..self.boards.len()).collect();
+        let reached: Vec<usize> =
+            everyone.iter().copied().filter(|&player| self.boards[player].return_score().1 >= target).collect();
+        if !reached.is_empty() {
+            return Some((self.leader(&reached), MatchEndReason::ReachedTarget));
+        }
+        if everyone.iter().all(|&player| is_locked(&self.boards[player])) {
+            return Some((self.leader(&everyone), MatchEndReason::NoMoves));
+        }
+        None
+    }
+
+    // 先合成2048者胜；有人无路可走时对局结束，其余玩家中分数最高者胜；所有人同时结束时比较全体分数
+    fn decide_classic(&self) -> Option<(Option<usize>, MatchEndReason)> {
+        let everyone: Vec<usize> = (0..self.boards.len()).collect();
+        let won: Vec<usize> = everyone.iter().copied().filter(|&player| self.boards[player].return_if_win()).collect();
+        if !won.is_empty() {
+            return Some((self.leader(&won), MatchEndReason::Reached2048));
+        }
+        let (locked, moving): (Vec<usize>, Vec<usize>) =
+            everyone.iter().partition(|&&player| is_locked(&self.boards[player]));
+        match (locked.is_empty(), moving.is_empty()) {
+            (true, _) => None,
+            (false, true) => Some((self.leader(&everyone), MatchEndReason::NoMoves)),
+            (false, false) => Some((self.leader(&moving), MatchEndReason::NoMoves)),
+        }
+    }
+
+    // 无路可走的幸存者出局，只剩一人时对局结束；剩下的人同时无路可走时比较他们的分数
+    // 瓷砖会经桥梁送到邻居的棋盘上，一次移动可能让别人无路可走
+    fn decide_battle_royale(&mut self) -> Option<(Option<usize>, MatchEndReason)> {
+        let alive = self.alive();
+        let locked: Vec<usize> = alive.iter().copied().filter(|&player| is_locked(&self.boards[player])).collect();
+        if locked.len() == alive.len() {
+            return Some((self.leader(&alive), MatchEndReason::NoMoves));
+        }
+        for player in locked {
+            if let Some(outcome) = self.lose(player, MatchEndReason::NoMoves) {
+                return Some(outcome);
+            }
+        }
+        None
+    }
+
+    // 玩家输了：淘汰赛中该玩家出局，只剩一人时对局结束；合作玩法中全队一起结束；
+    // 其他玩法中对局随即结束，其余玩家中分数最高者胜
+    fn lose(&mut self, player: usize, reason: MatchEndReason) -> Option<(Option<usize>, MatchEndReason)> {
+        if let MatchMode::Coop(_) = self.mode {
+            return Some((None, reason));
+        }
+        if self.mode != MatchMode::BattleRoyale {
+            return Some((self.leader(&self.others(player)), reason));
+        }
+        self.eliminate(player, reason);
+        match self.alive().as_slice() {
+            [last] => Some((Some(*last), reason)),
+            _ => None,
+        }
+    }
+
+    // 淘汰一名玩家：桥梁改连到下一名幸存的邻居，告诉所有人出局者和当前排名，再发送改连后的棋盘
+    fn eliminate(&mut self, player: usize, reason: MatchEndReason) {
+        println!("Player {} is eliminated: {}", player + 1, reason.describe());
+        self.eliminated.push(player);
+        reroute(&mut self.bridges, player);
+        reroute(&mut self.schedules, player);
+        let elimination = Elimination {
+            player: player as u8 + 1,
+            reason,
+            place: (self.players.len() - self.eliminated.len() + 1) as u8,
+            standings: self.standings().iter().map(|&player| player as u8 + 1).collect(),
+        };
+        let message = Message::Eliminated(elimination);
+        for player in 0..self.players.len() {
+            self.deliver(player, message.clone());
+        }
+        self.send_to_spectators(&message);
+        self.broadcast();
+    }
+
+    // 尚未出局的玩家
+    fn alive(&self) -> Vec<usize> {
+        (0..self.players.len()).filter(|player| !self.eliminated.contains(player)).collect()
+    }
+
+    // 当前排名：幸存者按分数从高到低，分数相同时编号小的在前，出局者按出局先后倒序排在后面
+    fn standings(&self) -> Vec<usize> {
+        let mut standings = self.alive();
+        standings.sort_by_key(|&player| std::cmp::Reverse(self.score(player)));
+        standings.extend(self.eliminated.iter().rev());
+        standings
+    }
+
+    // candidates 中分数最高的玩家，最高分不止一人时为 None
+    fn leader(&self, candidates: &[usize]) -> Option<usize> {
+        let best = candidates.iter().map(|&player| self.score(player)).max()?;
+        let mut leaders = candidates.iter().filter(|&&player| self.score(player) == best);
+        match (leaders.next(), leaders.next()) {
+            (Some(&player), None) => Some(player),
+            _ => None,
+        }
+    }
+
+    // 玩家的分数，合作玩法中就是共同得分
+    fn score(&self, player: usize) -> u32 {
+        self.boards[self.board_of(player)].return_score().0
+    }
+
+    // 玩家操作的棋盘下标，合作玩法中所有人都操作第一块
+    fn board_of(&self, player: usize) -> usize {
+        if self.boards.len() == 1 {
+            0
+        } else {
+            player
+        }
+    }
+
+    // 除 player 之外的所有玩家
+    fn others(&self, player: usize) -> Vec<usize> {
+        (0..self.players.len()).filter(|&other| other != player).collect()
+    }
+
+    fn end_match(&mut self, winner: Option<usize>, reason: MatchEndReason) -> MatchResult {
+        let result = MatchResult {
+            winner: winner.map(|player| player as u8 + 1),
+            reason,
+            boards: self.boards.iter().map(|board| board.get_tiles().clone()).collect(),
+            scores: self.boards.iter().map(|board| board.return_score().0).collect(),
+            standings: {
+                // 胜者总是第一名
+                let mut standings = self.standings();
+                standings.retain(|&player| Some(player) != winner);
+                winner.into_iter().chain(standings).map(|player| player as u8 + 1).collect()
+            },
+        };
+        println!("Match over: {:?}", result);
+        // 先从观战列表中移除，结束后不再接受观众
+        if let Some((ref live, ref info)) = self.live {
+            live.remove(info.id);
+        }
+        for player in 0..self.players.len() {
+            self.deliver(player, Message::MatchOver(result.clone()));
+        }
+        self.send_to_spectators(&Message::MatchOver(result.clone()));
+        result
+    }
+
+    // 初始化各玩家状态：空棋盘随机放置一个瓷砖，从记谱导入的局面则保持原样，然后告诉玩家身份并发送棋盘
+    fn start(&mut self) {
+        for board in self.boards.iter_mut() {
+            if board.return_score().1 == 0 {
+                board.spawn_tile();
+            }
+        }
+        self.last_action = vec![Instant::now(); self.players.len()];
+        match self.mode {
+            MatchMode::Race { time_limit_secs, .. } => {
+                self.deadline = Some(Instant::now() + Duration::from_secs(time_limit_secs as u64));
+            }
+            MatchMode::TurnBased { clock_secs, .. } => {
+                self.clocks = vec![Duration::from_secs(clock_secs as u64); self.players.len()];
+                self.turn_started = Instant::now();
+            }
+            _ => {}
+        }
+        self.change_bridges();
+        for player in 0..self.players.len() {
+            let identity = PlayerIdentity { player_number: player as u8 + 1 };
+            self.send_to(player, Message::PlayerIdentity(identity));
+            if let Some(token) = self.tokens.get(player) {
+                self.send_to(player, Message::SessionToken(token.clone()));
+            }
+        }
+        self.broadcast();
+    }
+
+    // 向右（竖直桥梁时向下）经自己在左侧的桥梁、向左（向上）经自己在右侧的桥梁把瓷砖送给邻居，再移动自己的棋盘
+    // 同一侧可有多座桥梁，每座能通过的桥梁各送出自己那一行的瓷砖
+    fn handle_action(&mut self, player: usize, direction: Direction) {
+        if matches!(direction, Direction::Quit | Direction::None) || self.eliminated.contains(&player) {
+            return;
+        }
+        let routes: Vec<usize> = (0..self.bridges.len())
+            .filter(|&index| {
+                let (link, bridge) = &self.bridges[index];
+                bridge.is_vertical() == matches!(direction, Direction::Up | Direction::Down)
+                    && match direction {
+                        Direction::Right | Direction::Down => link.left == player,
+                        _ => link.right == player,
+                    }
+            })
+            .collect();
+        // merged 为这一步合并出的瓷砖，送上桥的瓷砖不参与合并
+        let (animated, merged) = match routes.first() {
+            Some(&first) => {
+                let link = self.bridges[first].0;
+                let neighbor = if link.left == player { link.right } else { link.left };
+                let (own, other) = pair_mut(&mut self.boards, player, neighbor);
+                let animated: Vec<(usize, Vec<u32>)> = routes
+                    .into_iter()
+                    .filter_map(|index| self.bridges[index].1.send_through_bridge(other, own, direction).map(|tiles| (index, tiles)))
+                    .collect();
+                let merged = own.merged_tiles(direction);
+                own.move_tiles(direction);
+                own.spawn_tile();
+                own.print_state_with(other, animated.first().map(|(_, tiles)| tiles.clone())); // 打印当前游戏状态
+                (animated, merged)
+            }
+            None => {
+                let own = &mut self.boards[player];
+                let merged = own.merged_tiles(direction);
+                own.move_tiles(direction);
+                own.spawn_tile();
+                own.print_state();
+                (vec![], merged)
+            }
+        };
+        if self.mode == MatchMode::Garbage {
+            self.attack(player, &merged);
+        }
+        self.broadcast_with(animated);
+    }
+
+    // 已到时的桥梁换到自己时间表的下一段，移到这一段的行上，额度重新计算；开局时每座桥梁都从第一段开始
+    // 同一对棋盘之间每行至多一座桥梁，要去的行已被另一座占着时暂时留在原来的行，等那一行空出来再移过去；没有时间表时什么也不做
+    fn change_bridges(&mut self) {
+        let now = Instant::now();
+        for index in 0..self.bridges.len() {
+            let Some((_, scheduled)) = self.schedules.get_mut(index) else { break };
+            let (at, phase) = scheduled.next;
+            if at > now {
+                continue;
+            }
+            scheduled.next = (now + Duration::from_millis(phase.duration_ms), scheduled.schedule.next_phase());
+            let link = self.bridges[index].0;
+            let taken = self
+                .bridges
+                .iter()
+                .enumerate()
+                .any(|(other, (other_link, bridge))| other != index && *other_link == link && bridge.line() == phase.line as usize);
+            let bridge = &mut self.bridges[index].1;
+            let line = if taken { bridge.line() } else { phase.line as usize };
+            bridge.update_status(phase.two_way, phase.direction, phase.open, line, line, phase.sending_limit as usize);
+            println!("Bridge between players {} and {} on row {} changed: {:?}", link.left + 1, link.right + 1, line + 1, phase);
+        }
+    }
+
+    // 垃圾攻击：先抵消自己排队中的垃圾，剩下的发给下一名玩家，过 garbage_delay 后落下
+    fn attack(&mut self, player: usize, merged: &[u32]) {
+        let power = attack_power(merged);
+        if power == 0 {
+            return;
+        }
+        let remaining = self.garbage[player].cancel(power);
+        if remaining == 0 {
+            return;
+        }
+        let count = self.players.len();
+        let target = (1..count).map(|offset| (player + offset) % count).find(|other| !self.eliminated.contains(other));
+        if let Some(target) = target {
+            println!("Player {} sends {} garbage to player {}", player + 1, remaining, target + 1);
+            self.garbage[target].push(Instant::now() + self.garbage_delay, remaining);
+        }
+    }
+
+    // 回合制：只有轮到的玩家能移动，不改变棋盘的移动不算一步；走完一步停下自己的棋钟并加时，轮到下一名玩家
+    fn handle_turn(&mut self, player: usize, increment_secs: u32, direction: Direction) {
+        if player != self.turn
+            || matches!(direction, Direction::Quit | Direction::None)
+            || !self.boards[player].can_move(direction)
+        {
+            return;
+        }
+        let now = Instant::now();
+        let used = now - self.turn_started;
+        self.clocks[player] = self.clocks[player].saturating_sub(used) + Duration::from_secs(increment_secs as u64);
+        self.turn = (player + 1) % self.players.len();
+        self.turn_started = now;
+        self.handle_action(player, direction);
+    }
+
+    // 反派出块：只有滑动方在出块之后能移动，不改变棋盘的移动不算一步；移动后不生成瓷砖，等出块方在时限内出块
+    fn handle_slide(&mut self, player: usize, spawn_secs: u32, direction: Direction) {
+        if player != self.turn
+            || self.spawn_deadline.is_some()
+            || matches!(direction, Direction::Quit | Direction::None)
+            || !self.boards[player].can_move(direction)
+        {
+            return;
+        }
+        let board = &mut self.boards[player];
+        board.move_tiles(direction);
+        board.print_state();
+        self.spawn_deadline = Some(Instant::now() + Duration::from_secs(spawn_secs as u64));
+        self.broadcast();
+    }
+
+    // 出块方在滑动方的棋盘上放置新瓷砖，不在等待出块、不是出块方或位置数值不合法时忽略
+    fn handle_spawn(&mut self, player: usize, placement: TilePlacement) {
+        if !matches!(self.mode, MatchMode::Spawner { .. }) || player == self.turn || self.spawn_deadline.is_none() {
+            eprintln!("Unexpected spawn from player {}: {:?}", player + 1, placement);
+            return;
+        }
+        match self.boards[self.turn].spawn_tile_at(placement.x as usize, placement.y as usize, placement.value) {
+            Ok(()) => self.finish_spawn(),
+            Err(e) => eprintln!("Rejected spawn from player {}: {}", player + 1, e),
+        }
+    }
+
+    // 出块完成，轮到滑动方移动，等待出块的时间不算未操作
+    fn finish_spawn(&mut self) {
+        self.spawn_deadline = None;
+        self.last_action[self.turn] = Instant::now();
+        self.boards[self.turn].print_state();
+        self.broadcast();
+    }
+
+    // 合作玩法：按移动权决定这一步是否生效，轮流和分轴时立即移动，投票时先记下，窗口关闭或人齐后再移动
+    // 不改变棋盘的移动不算一步，也不算一票，免得白白让出移动权
+    fn handle_coop_action(&mut self, player: usize, control: CoopControl, direction: Direction) {
+        if matches!(direction, Direction::Quit | Direction::None)
+            || !control.allows(player, direction)
+            || !self.boards[0].can_move(direction)
+        {
+            return;
+        }
+        match control {
+            CoopControl::Alternate if player != self.turn => {}
+            CoopControl::Alternate => {
+                self.turn = (player + 1) % self.players.len();
+                self.move_shared(direction);
+            }
+            CoopControl::SplitAxis => self.move_shared(direction),
+            CoopControl::Vote => {
+                self.votes.retain(|&(voter, _)| voter != player);
+                self.votes.push((player, direction));
+                self.vote_deadline.get_or_insert(Instant::now() + Duration::from_millis(config::COOP_VOTE_WINDOW_MS));
+                let online = (0..self.players.len()).filter(|&other| self.disconnected[other].is_none()).count();
+                if self.votes.len() >= online {
+                    self.close_vote();
+                } else {
+                    self.broadcast();
+                }
+            }
+        }
+    }
+
+    // 结束本轮投票，得票最多的方向生效，平票时最先投出的优先
+    fn close_vote(&mut self) {
+        self.vote_deadline = None;
+        let votes = std::mem::take(&mut self.votes);
+        let count = |direction: Direction| votes.iter().filter(|&&(_, vote)| vote == direction).count();
+        let best = votes.iter().map(|&(_, direction)| count(direction)).max();
+        match votes.iter().find(|&&(_, direction)| Some(count(direction)) == best) {
+            Some(&(_, direction)) => self.move_shared(direction),
+            None => self.broadcast(),
+        }
+    }
+
+    // 移动共用的棋盘，全队都算刚操作过
+    fn move_shared(&mut self, direction: Direction) {
+        let board = &mut self.boards[0];
+        board.move_tiles(direction);
+        board.spawn_tile();
+        board.print_state();
+        self.last_action = vec![Instant::now(); self.players.len()];
+        self.broadcast();
+    }
+
+    // 发送棋盘当前状态给所有玩家和观众
+    fn broadcast(&mut self) {
+        self.broadcast_with(vec![]);
+    }
+
+    // animated 为这一步送过桥的瓷砖和它们经过的桥梁下标
+    fn broadcast_with(&mut self, animated: Vec<(usize, Vec<u32>)>) {
+        let state = self.game_state(animated);
+        let message = Message::GameState(Box::new(state));
+        for player in 0..self.players.len() {
+            self.send_to(player, message.clone());
+        }
+        self.send_to_spectators(&message);
+    }
+
+    // 当前棋盘状态，第 i 块始终是玩家 i+1 的棋盘，方便客户端区分
+    fn game_state(&mut self, animated: Vec<(usize, Vec<u32>)>) -> GameState {
+        GameState {
+            boards: self.boards.iter().map(|board| board.get_tiles().to_vec()).collect(),
+            reach_2048: self.boards.iter_mut().map(|board| board.check_game_over()).collect(),
+            links: self.bridges.iter().map(|(link, _)| *link).collect(),
+            animated,
+            eliminated: (0..self.players.len()).map(|player| self.eliminated.contains(&player)).collect(),
+            race: match (self.mode, self.deadline) {
+                (MatchMode::Race { target, .. }, Some(deadline)) => Some(RaceStatus {
+                    target,
+                    time_left_ms: deadline.saturating_duration_since(Instant::now()).as_millis() as u64,
+                }),
+                _ => None,
+            },
+            incoming_garbage: self.garbage.iter().map(GarbageQueue::total).collect(),
+            coop: match self.mode {
+                MatchMode::Coop(control) => Some(CoopStatus {
+                    control,
+                    turn: (control == CoopControl::Alternate).then_some(self.turn as u8 + 1),
+                    votes: (0..self.players.len())
+                        .map(|player| self.votes.iter().find(|&&(voter, _)| voter == player).map(|&(_, direction)| direction))
+                        .collect(),
+                }),
+                _ => None,
+            },
+            clocks: (!self.clocks.is_empty()).then(|| {
+                let used = Instant::now() - self.turn_started;
+                ClockStatus {
+                    turn: self.turn as u8 + 1,
+                    remaining_ms: self
+                        .clocks
+                        .iter()
+                        .enumerate()
+                        .map(|(player, &clock)| {
+                            let clock = if player == self.turn { clock.saturating_sub(used) } else { clock };
+                            clock.as_millis() as u64
+                        })
+                        .collect(),
+                }
+            }),
+            spawner: match self.mode {
+                MatchMode::Spawner { .. } => Some(SpawnerStatus {
+                    slider: self.turn as u8 + 1,
+                    spawner: ((self.turn + 1) % self.players.len()) as u8 + 1,
+                    spawn_time_left_ms: self
+                        .spawn_deadline
+                        .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_millis() as u64),
+                }),
+                _ => None,
+            },
+            bridges: self
+                .bridges
+                .iter()
+                .map(|(_, bridge)| BridgeStatus {
+                    open: bridge.is_open(),
+                    line: bridge.line() as u8,
+                    direction: bridge.direction(),
+                    two_way: bridge.is_two_way(),
+                    remaining: bridge.sending_limit() as u32,
+                })
+                .collect(),
+            bridge_forecasts: self
+                .schedules
+                .iter()
+                .map(|&(_, ScheduledBridge { next: (at, next), .. })| BridgeForecast {
+                    next,
+                    next_in_ms: at.saturating_duration_since(Instant::now()).as_millis() as u64,
+                })
+                .collect(),
+        }
+    }
+
+    // 交给玩家的写任务发送，不等待
+    // 写任务已退出说明连接已断开，稍后会收到断开事件；队列已满说明连接卡住了，
+    // 此时丢弃消息而不是让整局停下来等它，重连后会重新发送完整的棋盘
+    fn send_to(&self, player: usize, message: Message) {
+        if let Err(e) = self.players[player].try_send(message) {
+            eprintln!("Dropped message to player {}: {}", player + 1, e);
+        }
+    }
+
+    // 出局和对局结束这类消息不能丢：队列已满时另起任务等待空位，对局不必停下来等
+    // 等了 DEAD_CONNECTION_TIMEOUT_SECS 还没有空位时，写任务也早已因超时断开了连接
+    fn deliver(&self, player: usize, message: Message) {
+        match self.players[player].try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(message)) => {
+                let sender = self.players[player].clone();
+                let limit = Duration::from_secs(config::DEAD_CONNECTION_TIMEOUT_SECS);
+                tokio::spawn(async move {
+                    if let Err(e) = sender.send_timeout(message, limit).await {
+                        eprintln!("Dropped message to player {}: {}", player + 1, e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Dropped message to player {}: {}", player + 1, e),
+        }
+    }
+
+    // 发给除 player 之外的所有玩家
+    fn send_to_others(&self, player: usize, message: Message) {
+        for other in self.others(player) {
+            self.send_to(other, message.clone());
+        }
+    }
+}
+
+// 出局玩家两侧的桥梁改连：左邻居通向他的桥改通他的右邻居，他通向右邻居的桥拆除
+// 改连后两端相同的桥梁（只剩一人时）也一并拆除；桥梁的时间表跟着桥梁一起改连，两边始终一一对应
+fn reroute<T>(bridges: &mut Vec<(BridgeLink, T)>, player: usize) {
+    let right = bridges.iter().find(|(link, _)| link.left == player).map(|(link, _)| link.right);
+    bridges.retain(|(link, _)| link.left != player);
+    match right {
+        Some(right) => {
+            for (link, _) in bridges.iter_mut() {
+                if link.right == player {
+                    link.right = right;
+                }
+            }
+            bridges.retain(|(link, _)| link.left != link.right);
+        }
+        None => bridges.retain(|(link, _)| link.right != player),
+    }
+}
+
+// 同时借出两块不同的棋盘，first 和 second 不能相同
+fn pair_mut(boards: &mut [GameBoard], first: usize, second: usize) -> (&mut GameBoard, &mut GameBoard) {
+    assert_ne!(first, second);
+    if first < second {
+        let (head, tail) = boards.split_at_mut(second);
+        (&mut head[first], &mut tail[0])
+    } else {
+        let (head, tail) = boards.split_at_mut(first);
+        (&mut tail[0], &mut head[second])
+    }
+}
+
+// 四个方向都无法移动
+fn is_locked(board: &GameBoard) -> bool {
+    ![Direction::Up, Direction::Down, Direction::Left, Direction::Right]
+        .iter()
+        .any(|&direction| board.can_move(direction))
+}
+
+#[cfg(test)]
+mod tests_match_actor {
+    use super::*;
+    use crate::protocol::{Codec, FrameReader, PlayerAction};
+    use tokio::net::{TcpListener, TcpStream};
+
+    type Configure<'a> = Box<dyn FnOnce(&mut MatchActor) + 'a>;
+
+    // 测试用的对局，不经过网络，直接用管道模拟每名玩家的连接；人数由 spawn 返回的接收端个数决定
+    // 默认为经典玩法，玩家围成一环，每座桥梁都架在第三行、双向常开，不排时间表；合作玩法只有一块共用的棋盘，没有桥梁
+    // 棋盘默认随机开局，也可以像服务器的开局局面一样按记谱给定
+    struct TestMatch<'a> {
+        mode: MatchMode,
+        bridges: Option<Vec<Bridge>>, // 只在玩家1、2之间架起的桥梁，玩家1在左侧（竖直桥梁时在上方）
+        positions: Vec<(usize, &'a str)>,
+        configure: Vec<Configure<'a>>,
+    }
+
+    impl<'a> TestMatch<'a> {
+        fn new() -> Self {
+            Self { mode: MatchMode::Classic, bridges: None, positions: vec![], configure: vec![] }
+        }
+
+        fn mode(mut self, mode: MatchMode) -> Self {
+            self.mode = mode;
+            self
+        }
+
+        fn bridges(mut self, bridges: Vec<Bridge>) -> Self {
+            self.bridges = Some(bridges);
+            self
+        }
+
+        fn position(mut self, board: usize, position: &'a str) -> Self {
+            self.positions.push((board, position));
+            self
+        }
+
+        // 开局前对对局做的其他设置，例如超时和重连
+        fn configure(mut self, configure: impl FnOnce(&mut MatchActor) + 'a) -> Self {
+            self.configure.push(Box::new(configure));
+            self
+        }
+
+        fn spawn<const N: usize>(self) -> (mpsc::Sender<ConnectionEvent>, [mpsc::Receiver<Message>; N]) {
+            let (events_tx, events_rx) = mpsc::channel(8);
+            let (senders, receivers): (Vec<_>, Vec<_>) = (0..N).map(|_| mpsc::channel(8)).unzip();
+            let (mut boards, bridges) = match (self.mode, self.bridges) {
+                (MatchMode::Coop(_), _) => (vec![GameBoard::new()], vec![]),
+                (_, Some(bridges)) => (
+                    (0..N).map(|_| GameBoard::new()).collect(),
+                    bridges.into_iter().map(|bridge| (BridgeLink { left: 0, right: 1 }, bridge)).collect(),
+                ),
+                (_, None) => (
+                    (0..N).map(|_| GameBoard::new()).collect(),
+                    BridgeLink::ring(N)
+                        .into_iter()
+                        .map(|link| (link, Bridge::new(true, Direction::Right, true, 2, 2, 999999)))
+                        .collect(),
+                ),
+            };
+            for (board, position) in self.positions {
+                boards[board] = GameBoard::from_notation(position).unwrap();
+            }
+            let mut actor = MatchActor::new(boards, bridges, senders, events_tx.clone(), events_rx);
+            actor.set_mode(self.mode);
+            for configure in self.configure {
+                configure(&mut actor);
+            }
+            tokio::spawn(actor.run());
+            (events_tx, receivers.try_into().unwrap())
+        }
+    }
+
+    // 开启重连的两人对局，宽限期很短
+    fn resumable(sessions: Arc<SessionRegistry>) -> (mpsc::Sender<ConnectionEvent>, [mpsc::Receiver<Message>; 2]) {
+        TestMatch::new().configure(|actor| actor.enable_resume(sessions, Duration::from_millis(200))).spawn()
+    }
+
+    // 读到棋盘为 tiles 的状态之后的下一个状态
+    async fn receive_state_after(rx: &mut mpsc::Receiver<Message>, tiles: &[Vec<u32>]) -> GameState {
+        while receive_state(rx).await.boards[0] != tiles {}
+        receive_state(rx).await
+    }
+
+    // 读到下一个棋盘状态为止
+    async fn receive_state(rx: &mut mpsc::Receiver<Message>) -> GameState {
+        loop {
+            match rx.recv().await {
+                Some(Message::GameState(state)) => return *state,
+                Some(_) => continue,
+                None => panic!("对局意外结束"),
+            }
+        }
+    }
+
+    // 读到会话令牌为止
+    async fn receive_token(rx: &mut mpsc::Receiver<Message>) -> String {
+        loop {
+            if let Some(Message::SessionToken(token)) = rx.recv().await {
+                return token;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_action_reaches_idle_player() {
+        let (events, [mut rx1, mut rx2]) = TestMatch::new().spawn();
+        for rx in [&mut rx1, &mut rx2] {
+            assert!(matches!(rx.recv().await, Some(Message::PlayerIdentity(_))));
+            assert!(matches!(rx.recv().await, Some(Message::GameState(_))));
+        }
+
+        // 玩家2不发送任何消息，也能立即收到玩家1移动后的棋盘
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+        assert!(matches!(rx2.recv().await, Some(Message::GameState(_))));
+        assert!(matches!(rx1.recv().await, Some(Message::GameState(_))));
+    }
+
+    // 读完对局结束前发来的所有消息
+    async fn drain(rx: &mut mpsc::Receiver<Message>) -> Vec<Message> {
+        let mut messages = vec![];
+        while let Some(message) = rx.recv().await {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_ends_match() {
+        let (events, [_rx1, mut rx2]) = TestMatch::new().spawn();
+        events.send(ConnectionEvent::Closed(0, "test".to_string())).await.unwrap();
+        // 对局结束后发送端被丢弃，另一方先收到对手离开，再收到结果
+        let messages = drain(&mut rx2).await;
+        let n = messages.len();
+        assert!(matches!(messages[n - 2], Message::OpponentLeft(1)));
+        match &messages[n - 1] {
+            Message::MatchOver(result) => {
+                assert_eq!(result.winner, Some(2));
+                assert_eq!(result.reason, MatchEndReason::OpponentLeft);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forfeit() {
+        let (events, [mut rx1, mut rx2]) = TestMatch::new().spawn();
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        for rx in [&mut rx1, &mut rx2] {
+            match drain(rx).await.last() {
+                Some(Message::MatchOver(result)) => {
+                    assert_eq!(result.winner, Some(1));
+                    assert_eq!(result.reason, MatchEndReason::Forfeit);
+                }
+                other => panic!("应收到 MatchOver，实际 {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_match_over_reaches_full_queue() {
+        let (events, [mut rx1, _rx2]) = TestMatch::new().spawn();
+        // 玩家1不读取，开局消息加上每一步的棋盘很快填满他的队列，之后的棋盘被丢弃
+        for i in 0..8 {
+            let direction = if i % 2 == 0 { Direction::Left } else { Direction::Right };
+            events.send(ConnectionEvent::Message(0, Message::PlayerAction(PlayerAction { direction }))).await.unwrap();
+        }
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        // 等对局取走所有事件再读，读取腾出的空位不会让消息恰好塞进队列
+        while events.capacity() < events.max_capacity() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let messages = drain(&mut rx1).await;
+        assert_eq!(messages.len(), 9);
+        assert!(matches!(messages.last(), Some(Message::MatchOver(result)) if result.winner == Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_reaching_2048_wins() {
+        let (events, [_rx1, mut rx2]) = TestMatch::new().position(1, "4 0000/0000/0000/00aa").spawn();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(1, action)).await.unwrap();
+        match drain(&mut rx2).await.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.winner, Some(2));
+                assert_eq!(result.reason, MatchEndReason::Reached2048);
+                assert_eq!(result.boards[1][3][0], 2048);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ring_of_three_players() {
+        // 玩家3的第三行是空的，玩家1第三行最左边有一个2
+        let (events, mut receivers) =
+            TestMatch::new().position(2, "4 1000/0000/0000/0000").position(0, "4 0000/0000/1000/0000").spawn::<3>();
+        // 玩家1向左，经环上最后一座桥送给玩家3
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+
+        let state = loop {
+            match receivers[1].recv().await {
+                Some(Message::GameState(state)) if !state.animated.is_empty() => break state,
+                Some(_) => continue,
+                None => panic!("对局意外结束"),
+            }
+        };
+        assert_eq!(state.boards.len(), 3);
+        assert_eq!(state.links, BridgeLink::ring(3));
+        assert_eq!(state.animated, vec![(2, vec![2])]);
+        assert_eq!(state.boards[2][2][0], 2);
+
+        // 任何一人离开都结束对局，其余每人都收到离开通知
+        events.send(ConnectionEvent::Closed(1, "test".to_string())).await.unwrap();
+        for player in [0, 2] {
+            let messages = drain(&mut receivers[player]).await;
+            assert!(messages.iter().any(|message| matches!(message, Message::OpponentLeft(2))));
+            match messages.last() {
+                Some(Message::MatchOver(result)) => assert_eq!(result.scores.len(), 3),
+                other => panic!("应收到 MatchOver，实际 {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reroute_skips_eliminated() {
+        let links = |bridges: &Vec<(BridgeLink, Bridge)>| bridges.iter().map(|(link, _)| *link).collect::<Vec<_>>();
+        let mut bridges: Vec<(BridgeLink, Bridge)> = BridgeLink::ring(4)
+            .into_iter()
+            .map(|link| (link, Bridge::new(false, Direction::Right, true, 2, 2, 999999)))
+            .collect();
+        // 玩家2出局后玩家1直接连到玩家3
+        reroute(&mut bridges, 1);
+        assert_eq!(links(&bridges), vec![BridgeLink { left: 0, right: 2 }, BridgeLink { left: 2, right: 3 }, BridgeLink { left: 3, right: 0 }]);
+        // 剩两人时两边各一座桥，剩一人时不再有桥
+        reroute(&mut bridges, 3);
+        assert_eq!(links(&bridges), vec![BridgeLink { left: 0, right: 2 }, BridgeLink { left: 2, right: 0 }]);
+        reroute(&mut bridges, 2);
+        assert!(bridges.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_battle_royale_last_player_standing() {
+        let (events, mut receivers) = TestMatch::new().mode(MatchMode::BattleRoyale).spawn::<3>();
+        // 玩家2认输只是出局，对局继续，出局者照常收到棋盘
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        let elimination = loop {
+            if let Some(Message::Eliminated(elimination)) = receivers[0].recv().await {
+                break elimination;
+            }
+        };
+        assert_eq!((elimination.player, elimination.place), (2, 3));
+        assert_eq!(elimination.standings.last(), Some(&2));
+        let state = loop {
+            if let Some(Message::GameState(state)) = receivers[1].recv().await {
+                if state.eliminated[1] {
+                    break state;
+                }
+            }
+        };
+        assert_eq!(state.links, vec![BridgeLink { left: 0, right: 2 }, BridgeLink { left: 2, right: 0 }]);
+
+        // 出局者再认输不影响对局，玩家1认输后玩家3获胜
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        events.send(ConnectionEvent::Message(0, Message::Forfeit)).await.unwrap();
+        match drain(&mut receivers[1]).await.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.winner, Some(3));
+                assert_eq!(result.standings, vec![3, 1, 2]);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_race_reaching_target_wins() {
+        let (events, [_rx1, mut rx2]) = TestMatch::new()
+            .mode(MatchMode::Race { target: 8, time_limit_secs: 60 })
+            .position(1, "4 0000/0000/0000/0022")
+            .spawn();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(1, action)).await.unwrap();
+        let messages = drain(&mut rx2).await;
+        // 开局的棋盘就带着目标和剩余时间
+        match messages.iter().find(|message| matches!(message, Message::GameState(_))) {
+            Some(Message::GameState(state)) => {
+                let race = state.race.unwrap();
+                assert_eq!(race.target, 8);
+                assert!(race.time_left_ms > 50_000);
+            }
+            _ => panic!("应收到 GameState"),
+        }
+        match messages.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.winner, Some(2));
+                assert_eq!(result.reason, MatchEndReason::ReachedTarget);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_race_time_up_highest_score_wins() {
+        let (_events, mut receivers) = TestMatch::new()
+            .mode(MatchMode::Race { target: 2048, time_limit_secs: 1 })
+            .position(2, "4 0000/0000/0000/0005")
+            .spawn::<3>();
+        let start = Instant::now();
+        for rx in receivers.iter_mut() {
+            match drain(rx).await.last() {
+                Some(Message::MatchOver(result)) => {
+                    assert_eq!(result.winner, Some(3));
+                    assert_eq!(result.reason, MatchEndReason::TimeUp);
+                    assert_eq!(result.standings[0], 3);
+                }
+                other => panic!("应收到 MatchOver，实际 {:?}", other),
+            }
+        }
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn test_big_merge_sends_garbage() {
+        // 玩家1两个32合成64，攻击1块垃圾
+        let (events, [_rx1, mut rx2]) = TestMatch::new()
+            .mode(MatchMode::Garbage)
+            .position(0, "4 5500/0000/0000/0000")
+            .configure(|actor| actor.set_garbage_delay(Duration::from_millis(100)))
+            .spawn();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+
+        let before = loop {
+            let state = receive_state(&mut rx2).await;
+            if state.incoming_garbage == vec![0, 1] {
+                break state;
+            }
+        };
+        // 垃圾落下后队列清空，玩家2的棋盘多出一块
+        let after = receive_state(&mut rx2).await;
+        assert_eq!(after.incoming_garbage, vec![0, 0]);
+        let count = |state: &GameState| state.boards[1].iter().flatten().filter(|&&tile| tile != 0).count();
+        assert_eq!(count(&after), count(&before) + 1);
+    }
+
+    #[tokio::test]
+    async fn test_coop_alternate_ignores_out_of_turn() {
+        let (events, mut receivers) = TestMatch::new()
+            .mode(MatchMode::Coop(CoopControl::Alternate))
+            .position(0, "4 1100/0000/0000/0000")
+            .spawn::<2>();
+        // 开局轮到玩家1，玩家2抢先的一步被忽略
+        for player in [1, 0] {
+            let action = Message::PlayerAction(PlayerAction { direction: Direction::Right });
+            events.send(ConnectionEvent::Message(player, action)).await.unwrap();
+        }
+        let loaded = vec![vec![2, 2, 0, 0], vec![0; 4], vec![0; 4], vec![0; 4]];
+        let state = receive_state_after(&mut receivers[0], &loaded).await;
+        assert_eq!(state.boards.len(), 1);
+        assert_eq!(state.boards[0][0][3], 4);
+        assert_eq!(state.coop.unwrap().turn, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_coop_vote_majority_moves() {
+        let (events, mut receivers) =
+            TestMatch::new().mode(MatchMode::Coop(CoopControl::Vote)).position(0, "4 1100/0000/0000/0000").spawn::<3>();
+        for (player, direction) in [(0, Direction::Right), (1, Direction::Left), (2, Direction::Left)] {
+            let action = Message::PlayerAction(PlayerAction { direction });
+            events.send(ConnectionEvent::Message(player, action)).await.unwrap();
+        }
+        // 先看到第一票，三人都投完后立即按多数向左移动
+        let loaded = vec![vec![2, 2, 0, 0], vec![0; 4], vec![0; 4], vec![0; 4]];
+        let state = receive_state_after(&mut receivers[2], &loaded).await;
+        assert_eq!(state.coop.unwrap().votes, vec![Some(Direction::Right), None, None]);
+        let state = loop {
+            let state = receive_state(&mut receivers[2]).await;
+            if state.boards[0] != loaded {
+                break state;
+            }
+        };
+        assert_eq!(state.boards[0][0][0], 4);
+        assert!(state.coop.unwrap().votes.iter().all(Option::is_none));
+    }
+
+    #[tokio::test]
+    async fn test_coop_locked_board_ends_together() {
+        let (events, mut receivers) = TestMatch::new()
+            .mode(MatchMode::Coop(CoopControl::SplitAxis))
+            .position(0, "4 1212/2121/1212/2121")
+            .spawn::<2>();
+        // 无路可走的一步之后判定
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+        for rx in receivers.iter_mut() {
+            match drain(rx).await.last() {
+                Some(Message::MatchOver(result)) => {
+                    assert_eq!(result.winner, None);
+                    assert_eq!(result.reason, MatchEndReason::NoMoves);
+                    assert_eq!(result.scores, vec![48]);
+                }
+                other => panic!("应收到 MatchOver，实际 {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_turn_based_clock_runs_out() {
+        let (events, [mut rx1, mut rx2]) = TestMatch::new()
+            .mode(MatchMode::TurnBased { clock_secs: 1, increment_secs: 5 })
+            .position(0, "4 3000/0000/0000/0000")
+            .spawn();
+        // 还没轮到玩家2，这一步被忽略；玩家1走完后加时，轮到玩家2
+        for player in [1, 0] {
+            let action = Message::PlayerAction(PlayerAction { direction: Direction::Right });
+            events.send(ConnectionEvent::Message(player, action)).await.unwrap();
+        }
+        let loaded = vec![vec![8, 0, 0, 0], vec![0; 4], vec![0; 4], vec![0; 4]];
+        let state = receive_state_after(&mut rx1, &loaded).await;
+        assert_eq!(state.boards[0][0][3], 8);
+        let clocks = state.clocks.unwrap();
+        assert_eq!(clocks.turn, 2);
+        assert!(clocks.remaining_ms[0] > 5000);
+        assert!(clocks.remaining_ms[1] <= 1000);
+
+        // 玩家2一直不走，棋钟走完判负
+        match drain(&mut rx2).await.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.winner, Some(1));
+                assert_eq!(result.reason, MatchEndReason::OutOfTime);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawner_swaps_roles_and_compares_scores() {
+        // 双方的棋盘向右滑动后都只剩左下角一个空格，放上2就无路可走
+        let (events, [mut rx1, mut rx2]) = TestMatch::new()
+            .mode(MatchMode::Spawner { spawn_secs: 1 })
+            .position(0, "4 1212/2121/3454/2120")
+            .position(1, "4 1212/2121/5656/4340")
+            .spawn();
+        let send = |player: usize, message: Message| {
+            let events = events.clone();
+            async move { events.send(ConnectionEvent::Message(player, message)).await.unwrap() }
+        };
+        let slide = || Message::PlayerAction(PlayerAction { direction: Direction::Right });
+        let spawn = |x, y, value| Message::SpawnTile(TilePlacement { x, y, value });
+        receive_state(&mut rx1).await;
+
+        // 还没滑动时不能出块，等待出块时滑动方不能再移动
+        send(1, spawn(0, 3, 2)).await;
+        send(0, slide()).await;
+        send(0, slide()).await;
+        let state = receive_state(&mut rx1).await;
+        assert_eq!(state.boards[0][3], vec![0, 4, 2, 4]);
+        let roles = state.spawner.unwrap();
+        assert_eq!((roles.slider, roles.spawner), (1, 2));
+        assert!(roles.spawn_time_left_ms.is_some());
+
+        // 不合法的数值被拒绝，合法的出块让玩家1无路可走，交换角色
+        send(1, spawn(0, 3, 8)).await;
+        send(1, spawn(0, 3, 2)).await;
+        let state = receive_state(&mut rx1).await;
+        assert_eq!(state.boards[0][3], vec![2, 4, 2, 4]);
+        let state = receive_state(&mut rx1).await;
+        assert_eq!(state.spawner, Some(SpawnerStatus { slider: 2, spawner: 1, spawn_time_left_ms: None }));
+
+        // 玩家1不出块，时限到后服务器随机出块，玩家2也无路可走，两轮结束比较分数
+        send(1, slide()).await;
+        match drain(&mut rx2).await.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.reason, MatchEndReason::RolesSwapped);
+                assert_eq!(result.winner, Some(2));
+                assert_ne!(result.boards[1][3][0], 0);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge_schedule_is_broadcast() {
+        // 每行一座关闭的桥梁，由时间表打开，第一段都留在创建时的行
+        let closed = (0..4).map(|row| Bridge::new(false, Direction::Right, false, row, row, 0)).collect();
+        let phases: Vec<(BridgePhase, BridgePhase)> = (0..4)
+            .map(|line| {
+                let mut schedule = BridgeSchedule::new(42 + line as u64, line, 4, false);
+                (schedule.next_phase(), schedule.next_phase())
+            })
+            .collect();
+        // 第一行的桥梁只有顺着它的方向（双向桥梁时任一方向）才能把瓷砖送过去
+        let current = phases[0].0;
+        let (player, direction) = match current.direction {
+            Direction::Right => (0, Direction::Right),
+            _ => (1, Direction::Left),
+        };
+        // 双方只在各自的一行留一个瓷砖，其他行的桥梁没有瓷砖可送
+        let (events, [mut rx1, _rx2]) = TestMatch::new()
+            .bridges(closed)
+            .position(player, "4 1000/0000/0000/0000")
+            .position(1 - player, "4 0000/1000/0000/0000")
+            .configure(|actor| actor.enable_bridge_schedule(42))
+            .spawn();
+        let state = receive_state(&mut rx1).await;
+        let expected: Vec<BridgeStatus> = phases
+            .iter()
+            .map(|(current, _)| BridgeStatus {
+                open: true,
+                line: current.line,
+                direction: current.direction,
+                two_way: current.two_way,
+                remaining: current.sending_limit,
+            })
+            .collect();
+        assert_eq!(state.bridges, expected);
+        assert_eq!(state.bridge_forecasts.len(), 4);
+        for (forecast, (current, next)) in state.bridge_forecasts.iter().zip(&phases) {
+            assert_eq!(forecast.next, *next);
+            assert!(forecast.next_in_ms <= current.duration_ms);
+        }
+
+        events.send(ConnectionEvent::Message(player, Message::PlayerAction(PlayerAction { direction }))).await.unwrap();
+        let state = loop {
+            let state = receive_state(&mut rx1).await;
+            if !state.animated.is_empty() {
+                break state;
+            }
+        };
+        assert_eq!(state.animated, vec![(0, vec![2])]);
+        assert_eq!(state.bridges[0].remaining, current.sending_limit - 1);
+        assert_eq!(state.bridges[1].remaining, phases[1].0.sending_limit);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_moves_bridges_between_rows() {
+        // 两座桥梁起初在第二、四行，时间表开启时把它们换到别的行，但同一行不会有两座
+        let link = BridgeLink { left: 0, right: 1 };
+        let bridges = vec![
+            (link, Bridge::new(false, Direction::Right, false, 1, 1, 0)),
+            (link, Bridge::new(false, Direction::Right, false, 3, 3, 0)),
+        ];
+        let (events_tx, events_rx) = mpsc::channel(8);
+        let (senders, _receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| mpsc::channel(8)).unzip();
+        let mut actor = MatchActor::new(vec![GameBoard::new(), GameBoard::new()], bridges, senders, events_tx, events_rx);
+        actor.enable_bridge_schedule(42);
+        let mut lines = vec![1, 3];
+        let mut moved = false;
+        for _ in 0..20 {
+            // 不等计时，直接让每座桥梁换到下一段
+            for (_, scheduled) in actor.schedules.iter_mut() {
+                scheduled.next.0 = Instant::now();
+            }
+            let planned: Vec<usize> = actor.schedules.iter().map(|(_, scheduled)| scheduled.next.1.line as usize).collect();
+            actor.change_bridges();
+            let changed: Vec<usize> = actor.bridges.iter().map(|(_, bridge)| bridge.line()).collect();
+            assert_ne!(changed[0], changed[1]);
+            for index in 0..2 {
+                assert!(changed[index] == planned[index] || changed[index] == lines[index]);
+            }
+            moved |= changed != lines;
+            lines = changed;
+        }
+        assert!(moved);
+    }
+
+    #[tokio::test]
+    async fn test_each_bridge_has_own_schedule() {
+        // 三人围成一环，三座桥梁都在第三行，各自按自己的时间表变化
+        let (events, mut receivers) = TestMatch::new()
+            .mode(MatchMode::BattleRoyale)
+            .configure(|actor| actor.enable_bridge_schedule(42))
+            .spawn::<3>();
+        let phases: Vec<(BridgePhase, BridgePhase)> = (0..3)
+            .map(|index| {
+                let mut schedule = BridgeSchedule::new(42 + index, 2, 4, false);
+                (schedule.next_phase(), schedule.next_phase())
+            })
+            .collect();
+        let state = receive_state(&mut receivers[0]).await;
+        assert_eq!(state.bridge_forecasts.len(), 3);
+        for ((bridge, forecast), (current, next)) in state.bridges.iter().zip(&state.bridge_forecasts).zip(&phases) {
+            assert_eq!((bridge.direction, bridge.two_way, bridge.remaining), (current.direction, current.two_way, current.sending_limit));
+            assert_eq!(forecast.next, *next);
+        }
+
+        // 玩家2出局后，时间表跟着剩下的两座桥梁改连
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        let state = loop {
+            let state = receive_state(&mut receivers[0]).await;
+            if state.eliminated[1] {
+                break state;
+            }
+        };
+        let forecasts: Vec<BridgePhase> = state.bridge_forecasts.iter().map(|forecast| forecast.next).collect();
+        assert_eq!(forecasts, vec![phases[0].1, phases[2].1]);
+    }
+
+    #[tokio::test]
+    async fn test_move_sends_through_every_open_row() {
+        // 第一行双向常开，第二行关闭，第三行只能向左，第四行只剩一块额度
+        let bridges = vec![
+            Bridge::new(true, Direction::Right, true, 0, 0, 999999),
+            Bridge::new(true, Direction::Right, false, 1, 1, 999999),
+            Bridge::new(false, Direction::Left, true, 2, 2, 999999),
+            Bridge::new(true, Direction::Right, true, 3, 3, 1),
+        ];
+        let (events, [mut rx1, _rx2]) = TestMatch::new()
+            .bridges(bridges)
+            .position(0, "4 1000/1000/1000/1100")
+            .position(1, "4 0000/0001/0000/0000")
+            .spawn();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Right });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+        let state = loop {
+            let state = receive_state(&mut rx1).await;
+            if !state.animated.is_empty() {
+                break state;
+            }
+        };
+        assert_eq!(state.animated, vec![(0, vec![2]), (3, vec![2])]);
+        assert_eq!(state.boards[1][0], vec![0, 0, 0, 2]);
+        assert_eq!(state.boards[1][1], vec![0, 0, 0, 2]);
+        assert_eq!(state.boards[1][2], vec![0; 4]);
+        assert_eq!(state.boards[1][3], vec![0, 0, 0, 2]);
+        assert_eq!(state.bridges[3].remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_vertical_bridge_sends_up() {
+        // 玩家 0 的棋盘在上，玩家 1 的在下，第二列架一座双向常开的竖直桥梁
+        let bridges = vec![Bridge::new(true, Direction::Down, true, 1, 1, 999999)];
+        let (events, [mut rx1, _rx2]) = TestMatch::new()
+            .bridges(bridges)
+            .position(0, "4 0000/0000/0000/1000")
+            .position(1, "4 0000/0000/0000/0100")
+            .spawn();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Up });
+        events.send(ConnectionEvent::Message(1, action)).await.unwrap();
+        let state = loop {
+            let state = receive_state(&mut rx1).await;
+            if !state.animated.is_empty() {
+                break state;
+            }
+        };
+        assert_eq!(state.animated, vec![(0, vec![2])]);
+        assert_eq!(state.boards[0][0][1], 2);
+        assert!(state.bridges[0].two_way);
+    }
+
+    #[tokio::test]
+    async fn test_vertical_bridges_keep_column_schedule() {
+        // 上下叠放时每列一座竖直桥梁，时间表只在上下之间选方向
+        let stacked = (0..4).map(|column| Bridge::new(false, Direction::Down, false, column, column, 0)).collect();
+        let (_events, [mut rx1, _rx2]) = TestMatch::new()
+            .bridges(stacked)
+            .configure(|actor| actor.enable_bridge_schedule(42))
+            .spawn();
+        let state = receive_state(&mut rx1).await;
+        for (column, (bridge, forecast)) in state.bridges.iter().zip(&state.bridge_forecasts).enumerate() {
+            let mut schedule = BridgeSchedule::new(42 + column as u64, column as u8, 4, true);
+            assert_eq!(bridge.direction, schedule.next_phase().direction);
+            assert!(matches!(bridge.direction, Direction::Up | Direction::Down));
+            assert!(matches!(forecast.next.direction, Direction::Up | Direction::Down));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pong_reports_latency() {
+        let (events, [mut rx1, mut rx2]) =
+            TestMatch::new().configure(|actor| actor.set_timeouts(Duration::from_millis(50), Duration::from_secs(60))).spawn();
+        let sequence = loop {
+            if let Some(Message::Ping(sequence)) = rx1.recv().await {
+                break sequence;
+            }
+        };
+        events.send(ConnectionEvent::Message(0, Message::Pong(sequence))).await.unwrap();
+
+        // 双方都收到玩家1的延迟，玩家2还没回复心跳
+        loop {
+            if let Some(Message::Latency(latency)) = rx2.recv().await {
+                assert!(latency.players_ms[0].is_some());
+                assert_eq!(latency.players_ms[1], None);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_player_loses() {
+        let (events, [_rx1, mut rx2]) =
+            TestMatch::new().configure(|actor| actor.set_timeouts(Duration::from_secs(60), Duration::from_millis(400))).spawn();
+        // 玩家2一直在移动，玩家1从不操作
+        for _ in 0..3 {
+            let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+            events.send(ConnectionEvent::Message(1, action)).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        match drain(&mut rx2).await.last() {
+            Some(Message::MatchOver(result)) => {
+                assert_eq!(result.winner, Some(2));
+                assert_eq!(result.reason, MatchEndReason::Idle);
+            }
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spectator_receives_updates() {
+        let live = Arc::new(LiveMatches::new());
+        let (events, _players) = TestMatch::new().configure(|actor| actor.enable_spectating(live.clone(), "测试")).spawn::<2>();
+        let info = live.list().pop().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let pending = PendingClient {
+            socket,
+            reader: FrameReader::new(),
+            codec: Codec::Json,
+            resume_token: None,
+            permit: None,
+            rated_name: None,
+        };
+        let request = crate::protocol::SpectateRequest { match_id: info.id, delay_secs: 0 };
+        assert!(live.watch(request, pending).is_ok());
+
+        let mut reader = FrameReader::new();
+        let spectating = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(spectating, Message::Spectating(ref watched) if *watched == info));
+        let state = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(state, Message::GameState(_)));
+
+        // 观众发来的消息不影响对局，玩家移动后观众收到新棋盘，对局结束后从列表中移除
+        crate::protocol::write_message(&mut client, Codec::Json, &Message::Forfeit).await.unwrap();
+        let action = Message::PlayerAction(PlayerAction { direction: Direction::Left });
+        events.send(ConnectionEvent::Message(0, action)).await.unwrap();
+        let state = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(state, Message::GameState(_)));
+        events.send(ConnectionEvent::Message(1, Message::Forfeit)).await.unwrap();
+        let over = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(over, Message::MatchOver(ref result) if result.winner == Some(1)));
+        assert!(live.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_grace_period_expires() {
+        let sessions = Arc::new(SessionRegistry::new());
+        let (events, [mut rx1, mut rx2]) = resumable(sessions.clone());
+        let token = receive_token(&mut rx1).await;
+        events.send(ConnectionEvent::Closed(0, "test".to_string())).await.unwrap();
+
+        // 先通知对手掉线，宽限期过后判负，令牌随之作废
+        let messages = drain(&mut rx2).await;
+        assert!(messages.iter().any(|message| matches!(message, Message::OpponentDisconnected(1))));
+        match messages.last() {
+            Some(Message::MatchOver(result)) => assert_eq!(result.reason, MatchEndReason::OpponentLeft),
+            other => panic!("应收到 MatchOver，实际 {:?}", other),
+        }
+        assert!(sessions.find(&token).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resume_with_token() {
+        let sessions = Arc::new(SessionRegistry::new());
+        let (events, [mut rx1, mut rx2]) = resumable(sessions.clone());
+        let token = receive_token(&mut rx1).await;
+        events.send(ConnectionEvent::Closed(0, "test".to_string())).await.unwrap();
+
+        // 用本地 TCP 连接模拟重连的客户端
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let pending = PendingClient {
+            socket,
+            reader: FrameReader::new(),
+            codec: Codec::Json,
+            resume_token: Some(token.clone()),
+            permit: None,
+            rated_name: None,
+        };
+        let (player, reconnect) = sessions.find(&token).unwrap();
+        reconnect.send((player, pending)).await.unwrap();
+
+        let mut reader = FrameReader::new();
+        let identity = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(identity, Message::PlayerIdentity(PlayerIdentity { player_number: 1 })));
+        let resumed = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(resumed, Message::SessionToken(ref t) if *t == token));
+        let state = reader.read_message(&mut client, Codec::Json).await.unwrap();
+        assert!(matches!(state, Message::GameState(_)));
+
+        // 对手收到重连通知，宽限期过后对局仍在继续
+        loop {
+            if let Some(Message::OpponentReconnected(1)) = rx2.recv().await {
+                break;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(rx2.try_recv().is_err(), "重连后不应再判负");
+    }
+}
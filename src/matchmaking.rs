@@ -0,0 +1,391 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::{interval, Duration, Instant};
+
+use crate::config;
+use crate::connection::PendingClient;
+use crate::protocol::{CoopControl, CoopScore, MatchResult};
+
+// 新玩家的初始等级分和 Elo 的 K 值
+const INITIAL_RATING: f64 = 1500.0;
+const ELO_K: f64 = 32.0;
+
+/// 一名玩家的等级分
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerRating {
+    pub rating: f64,
+    pub games: u32,
+}
+
+/// 服务器数据文件的内容，按玩家名记录等级分，另有合作玩法排行榜
+// 第一次以某名字排位时服务器发放一把密钥，之后以该名字排位都须出示，别人不能冒用名字替他赢输等级分
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ServerData {
+    #[serde(default)]
+    pub ratings: BTreeMap<String, PlayerRating>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, String>, // 玩家名 → 服务器发放的密钥
+    #[serde(default)]
+    pub coop_scores: Vec<CoopScore>, // 分数从高到低，最多 COOP_LEADERBOARD_SIZE 条
+}
+
+impl ServerData {
+    // 数据文件不存在时从空数据开始；读不出或解析失败时返回错误，不能让下一次保存覆盖掉原来的等级分
+    pub fn load(path: &str) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path, e)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, text).map_err(|e| e.to_string())
+    }
+
+    pub fn rating(&self, name: &str) -> f64 {
+        self.ratings.get(name).map_or(INITIAL_RATING, |player| player.rating)
+    }
+
+    // 认领玩家名：已有密钥的名字须出示同一把密钥；还没有时发放一把新的，返回给客户端保存
+    // 有密钥之前就有等级分的名字，由第一个以它排位的人认领
+    pub fn claim(&mut self, name: &str, key: Option<&str>) -> Result<Option<String>, String> {
+        match self.keys.get(name) {
+            Some(issued) if key == Some(issued.as_str()) => Ok(None),
+            Some(_) => Err(format!("玩家名 {} 已被别人使用", name)),
+            None => {
+                let key = format!("{:032x}", rand::random::<u128>());
+                self.keys.insert(name.to_string(), key.clone());
+                Ok(Some(key))
+            }
+        }
+    }
+
+    // 记录一局排位赛，score 为玩家1的得分：胜1、平0.5、负0
+    pub fn record(&mut self, names: &[String; 2], score: f64) {
+        let ratings = [self.rating(&names[0]), self.rating(&names[1])];
+        let change = ELO_K * (score - expected_score(ratings[0], ratings[1]));
+        for (player, delta) in [(0, change), (1, -change)] {
+            let entry = self.ratings.entry(names[player].clone()).or_insert(PlayerRating {
+                rating: INITIAL_RATING,
+                games: 0,
+            });
+            entry.rating = ratings[player] + delta;
+            entry.games += 1;
+        }
+    }
+
+    // 记录一局合作对局，分数相同时先打出的排在前面
+    pub fn record_coop(&mut self, score: CoopScore) {
+        let place = self.coop_scores.iter().position(|entry| entry.score < score.score).unwrap_or(self.coop_scores.len());
+        self.coop_scores.insert(place, score);
+        self.coop_scores.truncate(config::COOP_LEADERBOARD_SIZE);
+    }
+}
+
+// 按 Elo 公式，等级分为 rating 的一方对 opponent 的期望得分
+pub fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+// 排队 waited 之后愿意接受的最大分差，等得越久范围越大
+pub fn allowed_gap(waited: Duration) -> f64 {
+    (config::RATING_GAP_BASE + config::RATING_GAP_PER_SEC * waited.as_secs_f64()).min(config::RATING_GAP_MAX)
+}
+
+/// 从排队的玩家中找出可以配对的两两组合，entries 为 (等级分, 已等待时间)，应按等待时间从长到短排列
+// 等得最久的玩家优先，与分差最小的对手配对，分差须在双方各自允许的范围内
+pub fn find_pairs(entries: &[(f64, Duration)]) -> Vec<(usize, usize)> {
+    let mut paired = vec![false; entries.len()];
+    let mut pairs = vec![];
+    for i in 0..entries.len() {
+        if paired[i] {
+            continue;
+        }
+        let (rating, waited) = entries[i];
+        let opponent = (i + 1..entries.len())
+            .filter(|&j| !paired[j])
+            .map(|j| (j, (entries[j].0 - rating).abs()))
+            .filter(|&(j, gap)| gap <= allowed_gap(waited).min(allowed_gap(entries[j].1)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((j, _)) = opponent {
+            paired[i] = true;
+            paired[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs
+}
+
+/// 排到对手后发给双方大厅任务的通知
+pub enum Pairing {
+    Host(oneshot::Receiver<PendingClient>), // 等待对手交来连接，由自己一方建立对局，自己是玩家1
+    Guest(oneshot::Sender<PendingClient>),  // 把自己的连接交给对手
+}
+
+// 队列中的一名玩家
+struct Ticket {
+    id: u64,
+    name: String,
+    rating: f64,
+    joined: Instant,
+    notify: oneshot::Sender<Pairing>,
+}
+
+// 排队中的玩家和下一个排队编号
+struct Queue {
+    tickets: Vec<Ticket>,
+    next_id: u64,
+}
+
+/// 排位赛匹配服务：按等级分配对排队的玩家，对局结束后更新等级分并写入数据文件
+// 锁只在查表时短暂持有，不会跨越 await
+pub struct Matchmaker {
+    queue: Mutex<Queue>,
+    data: Mutex<ServerData>,
+    data_file: Option<String>, // 为 None 时只保存在内存中
+}
+
+impl Matchmaker {
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(Queue { tickets: vec![], next_id: 0 }),
+            data: Mutex::new(ServerData::default()),
+            data_file: None,
+        }
+    }
+
+    // 从数据文件读取等级分，之后每局排位赛结束都写回该文件
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            data: Mutex::new(ServerData::load(path)?),
+            data_file: Some(path.to_string()),
+            ..Self::new()
+        })
+    }
+
+    pub fn rating(&self, name: &str) -> f64 {
+        self.data.lock().unwrap().rating(name)
+    }
+
+    // 排位前认领玩家名，发放了新密钥时立即写入数据文件
+    pub fn claim(&self, name: &str, key: Option<&str>) -> Result<Option<String>, String> {
+        let mut data = self.data.lock().unwrap();
+        let issued = data.claim(name, key)?;
+        if issued.is_some() {
+            self.save(&data);
+        }
+        Ok(issued)
+    }
+
+    // 排队，返回排队编号和接收配对通知的一端，同名玩家不能同时排队
+    // joined 为最初排队的时刻，对手临时离开后重新排队时沿用，已经放宽的分差范围不会重置
+    pub fn enqueue(&self, name: &str, joined: Instant) -> Result<(u64, oneshot::Receiver<Pairing>), String> {
+        let rating = self.rating(name);
+        let mut queue = self.queue.lock().unwrap();
+        if queue.tickets.iter().any(|ticket| ticket.name == name) {
+            return Err(format!("玩家 {} 已经在排队", name));
+        }
+        let id = queue.next_id;
+        queue.next_id += 1;
+        let (notify, receiver) = oneshot::channel();
+        queue.tickets.push(Ticket { id, name: name.to_string(), rating, joined, notify });
+        Ok((id, receiver))
+    }
+
+    // 退出队列，返回 false 表示已经配对，配对通知已在管道中
+    pub fn dequeue(&self, id: u64) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        let before = queue.tickets.len();
+        queue.tickets.retain(|ticket| ticket.id != id);
+        queue.tickets.len() < before
+    }
+
+    // 配对一轮，通知在同一次加锁内发出
+    // 房主已离开时对手放回队列；对手已离开时房主会收到错误并自行重新排队
+    fn pair(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.tickets.sort_by_key(|ticket| ticket.joined);
+        let now = Instant::now();
+        let entries: Vec<(f64, Duration)> =
+            queue.tickets.iter().map(|ticket| (ticket.rating, now - ticket.joined)).collect();
+        let pairs = find_pairs(&entries);
+        if pairs.is_empty() {
+            return;
+        }
+
+        let mut tickets: Vec<Option<Ticket>> = queue.tickets.drain(..).map(Some).collect();
+        for (i, j) in pairs {
+            let (host, guest) = (tickets[i].take().unwrap(), tickets[j].take().unwrap());
+            println!("Matched {} ({:.0}) with {} ({:.0})", host.name, host.rating, guest.name, guest.rating);
+            let (sender, receiver) = oneshot::channel();
+            if host.notify.send(Pairing::Host(receiver)).is_err() {
+                tickets[j] = Some(guest);
+                continue;
+            }
+            let _ = guest.notify.send(Pairing::Guest(sender));
+        }
+        queue.tickets = tickets.into_iter().flatten().collect();
+    }
+
+    // 定时配对，排队的玩家等得越久，可接受的分差越大
+    pub async fn run(self: Arc<Self>) {
+        let mut ticker = interval(Duration::from_millis(config::MATCHMAKING_INTERVAL_MS));
+        loop {
+            ticker.tick().await;
+            self.pair();
+        }
+    }
+
+    // 合作玩法排行榜
+    pub fn coop_scores(&self) -> Vec<CoopScore> {
+        self.data.lock().unwrap().coop_scores.clone()
+    }
+
+    // 合作对局结束，把共同得分记入排行榜并写入数据文件
+    pub fn record_coop(&self, team: &str, players: u8, control: CoopControl, result: &MatchResult) {
+        let score = CoopScore { team: team.to_string(), players, control, score: result.scores.first().copied().unwrap_or(0) };
+        println!("Co-op score recorded: {:?}", score);
+        let mut data = self.data.lock().unwrap();
+        data.record_coop(score);
+        self.save(&data);
+    }
+
+    // 写入数据文件，失败时只打印错误
+    fn save(&self, data: &ServerData) {
+        if let Some(ref path) = self.data_file {
+            if let Err(e) = data.save(path) {
+                eprintln!("Failed to save server data: {}", e);
+            }
+        }
+    }
+
+    // 排位赛结束，更新双方等级分并写入数据文件
+    pub fn record(&self, names: &[String; 2], result: &MatchResult) {
+        let score = match result.winner {
+            Some(1) => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+        let mut data = self.data.lock().unwrap();
+        data.record(names, score);
+        println!(
+            "Ratings updated: {} {:.0}, {} {:.0}",
+            names[0],
+            data.rating(&names[0]),
+            names[1],
+            data.rating(&names[1])
+        );
+        self.save(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests_matchmaking {
+    use super::*;
+
+    fn names() -> [String; 2] {
+        ["alice".to_string(), "bob".to_string()]
+    }
+
+    #[test]
+    fn test_elo_update() {
+        let mut data = ServerData::default();
+        data.record(&names(), 1.0);
+        // 同分时胜者得 K/2，双方总分不变
+        assert_eq!(data.rating("alice"), INITIAL_RATING + ELO_K / 2.0);
+        assert_eq!(data.rating("bob"), INITIAL_RATING - ELO_K / 2.0);
+        assert_eq!(data.ratings["bob"].games, 1);
+
+        // 高分者战平低分者会丢分
+        data.record(&names(), 0.5);
+        assert!(data.rating("alice") < INITIAL_RATING + ELO_K / 2.0);
+        assert!((data.rating("alice") + data.rating("bob") - INITIAL_RATING * 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_gap_widens_while_waiting() {
+        let just_joined = Duration::ZERO;
+        let long_wait = Duration::from_secs(600);
+        // 分差小的立即配对，且优先选分差最小的对手
+        assert_eq!(find_pairs(&[(1500.0, just_joined), (1900.0, just_joined), (1520.0, just_joined)]), vec![(0, 2)]);
+        // 分差大的要等双方都等够了才配对
+        assert!(find_pairs(&[(1500.0, long_wait), (1800.0, just_joined)]).is_empty());
+        assert_eq!(find_pairs(&[(1500.0, long_wait), (1800.0, long_wait)]), vec![(0, 1)]);
+        assert!(allowed_gap(long_wait) <= config::RATING_GAP_MAX);
+    }
+
+    #[test]
+    fn test_server_data_round_trip() {
+        let path = std::env::temp_dir().join(format!("rust2048_server_data_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let matchmaker = Matchmaker::load(path).unwrap();
+        let result = MatchResult {
+            winner: Some(2),
+            reason: crate::protocol::MatchEndReason::Forfeit,
+            boards: vec![],
+            scores: vec![],
+            standings: vec![],
+        };
+        matchmaker.record(&names(), &result);
+        let reloaded = ServerData::load(path).unwrap();
+        let _ = fs::remove_file(path);
+        assert!(reloaded.rating("bob") > INITIAL_RATING);
+        assert_eq!(reloaded.rating("bob"), matchmaker.rating("bob"));
+    }
+
+    #[test]
+    fn test_corrupt_server_data_is_refused() {
+        let path = std::env::temp_dir().join(format!("rust2048_corrupt_data_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        // 文件不存在时从空数据开始
+        assert!(Matchmaker::load(path).unwrap().data.lock().unwrap().ratings.is_empty());
+        // 解析失败时不加载，文件原样保留
+        fs::write(path, "{\"ratings\": {\"alice\": ").unwrap();
+        assert!(Matchmaker::load(path).is_err());
+        let text = fs::read_to_string(path).unwrap();
+        let _ = fs::remove_file(path);
+        assert_eq!(text, "{\"ratings\": {\"alice\": ");
+    }
+
+    #[test]
+    fn test_claimed_name_needs_key() {
+        let mut data = ServerData::default();
+        let key = data.claim("alice", None).unwrap().unwrap();
+        assert_eq!(data.claim("alice", Some(&key)), Ok(None));
+        assert!(data.claim("alice", None).is_err());
+        assert!(data.claim("alice", Some("猜的")).is_err());
+        // 别的名字各有各的密钥
+        assert_ne!(data.claim("bob", Some(&key)).unwrap().unwrap(), key);
+    }
+
+    #[test]
+    fn test_coop_leaderboard_keeps_best() {
+        let mut data = ServerData::default();
+        let entry = |team: &str, score| CoopScore { team: team.to_string(), players: 2, control: CoopControl::Vote, score };
+        for score in 0..config::COOP_LEADERBOARD_SIZE as u32 + 2 {
+            data.record_coop(entry("队伍", score * 10));
+        }
+        data.record_coop(entry("后到", 50));
+        assert_eq!(data.coop_scores.len(), config::COOP_LEADERBOARD_SIZE);
+        assert_eq!(data.coop_scores[0].score, (config::COOP_LEADERBOARD_SIZE as u32 + 1) * 10);
+        // 同分的后到者排在先到者之后
+        let tied = data.coop_scores.iter().position(|entry| entry.score == 50).unwrap();
+        assert_eq!(data.coop_scores[tied + 1].team, "后到");
+        assert!(data.coop_scores.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_same_name_twice() {
+        let matchmaker = Matchmaker::new();
+        let (id, _receiver) = matchmaker.enqueue("alice", Instant::now()).unwrap();
+        assert!(matchmaker.enqueue("alice", Instant::now()).is_err());
+        assert!(matchmaker.dequeue(id));
+        assert!(!matchmaker.dequeue(id));
+    }
+}
@@ -14,6 +14,24 @@ use tui::{
     Terminal,
 };
 
+mod game_board;
+mod notation;
+
+pub use crate::game_board::GameBoard;
+
+// 拼接启动子程序的参数，菜单收到的 --position 原样转交给游戏
+fn game_args(bin: &str, flags: &[&str], position: &Option<String>) -> Vec<String> {
+    let mut args = vec!["run".to_string(), "--bin".to_string(), bin.to_string(), "--".to_string()];
+    for flag in flags {
+        args.push(flag.to_string());
+    }
+    if let Some(position) = position {
+        args.push("--position".to_string());
+        args.push(position.clone());
+    }
+    args
+}
+
 fn draw_ui(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     menu_items: &[ListItem],
@@ -52,6 +70,12 @@ fn draw_ui(
 }
 
 pub fn run_ui() -> Result<(), Box<dyn std::error::Error>> {
+    // 进入界面前先检查 --position 是否合法
+    let position = notation::position_from_args();
+    if let Some(ref text) = position {
+        GameBoard::from_notation(text)?;
+    }
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -62,6 +86,8 @@ pub fn run_ui() -> Result<(), Box<dyn std::error::Error>> {
     let menu_items = vec![
         ListItem::new("单人游戏").style(Style::default().add_modifier(Modifier::BOLD)),
         ListItem::new("双人游戏").style(Style::default().add_modifier(Modifier::BOLD)),
+        ListItem::new("谜题模式").style(Style::default().add_modifier(Modifier::BOLD)),
+        ListItem::new("棋盘编辑器").style(Style::default().add_modifier(Modifier::BOLD)),
         ListItem::new("退出").style(Style::default().add_modifier(Modifier::BOLD)),
     ];
     let mut list_state = ListState::default();
@@ -158,7 +184,7 @@ pub fn run_ui() -> Result<(), Box<dyn std::error::Error>> {
                             0 => {
                                 // println!("启动单人游戏...");
                                 Command::new("cargo")
-                                    .args(&["run", "--bin", "sc"])
+                                    .args(game_args("sc", &[], &position))
                                     .spawn()?
                                     .wait()?;
                                 terminal.clear()?;
@@ -172,7 +198,7 @@ pub fn run_ui() -> Result<(), Box<dyn std::error::Error>> {
                             1 => {
                                 // println!("启动双人游戏...");
                                 Command::new("cargo")
-                                    .args(&["run", "--bin", "client"])
+                                    .args(game_args("client", &[], &position))
                                     .spawn()?
                                     .wait()?;
                                 terminal.clear()?;
@@ -184,6 +210,34 @@ pub fn run_ui() -> Result<(), Box<dyn std::error::Error>> {
                                 )?;
                             }
                             2 => {
+                                // 谜题模式从下一道未完成的谜题开始
+                                Command::new("cargo")
+                                    .args(game_args("sc", &["--puzzle"], &None))
+                                    .spawn()?
+                                    .wait()?;
+                                terminal.clear()?;
+                                draw_ui(
+                                    &mut terminal,
+                                    &menu_items,
+                                    &mut list_state,
+                                    &instructions,
+                                )?;
+                            }
+                            3 => {
+                                // 编辑器结束后可直接在 sc 中游玩编辑好的局面
+                                Command::new("cargo")
+                                    .args(game_args("sc", &["--editor"], &position))
+                                    .spawn()?
+                                    .wait()?;
+                                terminal.clear()?;
+                                draw_ui(
+                                    &mut terminal,
+                                    &menu_items,
+                                    &mut list_state,
+                                    &instructions,
+                                )?;
+                            }
+                            4 => {
                                 // println!("操作: 退出");
                                 // sleep(Duration::from_secs(3));
                                 game_running = false;
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use crate::GameBoard;
+
+// 局面记谱，类似国际象棋的FEN，一行文本即可完整描述一个局面
+// 格式： <边长> <各行瓷砖，用'/'分隔> [分数] [轮到谁]
+// 例如： 4 0000/0120/0003/0000 14 2
+// 瓷砖使用指数表示，0为空，1为2，2为4 …… a为1024，b为2048，最大到v(2^31)
+// 分数和轮到谁都可以省略，'-'表示不记录；轮到谁是多人对局中轮到的玩家编号，从1开始
+// 棋盘本身不保存这两项，GameBoard 导入时只取瓷砖，需要它们的调用方用 Notation::parse 读取
+
+// 指数最大值，u32 最多能表示 2^31
+const MAX_EXPONENT: u32 = 31;
+
+/// 解析后的局面记谱，瓷砖都是2的幂
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notation {
+    pub tiles: Vec<Vec<u32>>,
+    pub score: Option<u32>, // None 表示不记录分数
+    pub turn: Option<u8>,   // None 表示单人局面或不记录轮次
+}
+
+impl Notation {
+    // 瓷砖中有非2的幂的数值时无法记谱
+    pub fn new(tiles: Vec<Vec<u32>>) -> Result<Self, String> {
+        for row in &tiles {
+            for &tile in row {
+                if tile != 0 && !tile.is_power_of_two() {
+                    return Err(format!("Tile {} is not a power of two", tile));
+                }
+            }
+        }
+        Ok(Self { tiles, score: None, turn: None })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() < 2 || fields.len() > 4 {
+            return Err(format!("Position should have 2 to 4 fields (size, rows, score, turn), got {}", fields.len()));
+        }
+
+        // 边长
+        let size: usize = fields[0]
+            .parse()
+            .map_err(|_| format!("Invalid board size: {}", fields[0]))?;
+        if size < 2 {
+            return Err(format!("Board size must be at least 2, got {}", size));
+        }
+
+        // 各行瓷砖
+        let rows: Vec<&str> = fields[1].split('/').collect();
+        if rows.len() != size {
+            return Err(format!("Expected {} rows, got {}", size, rows.len()));
+        }
+        let mut tiles = vec![];
+        for (i, row) in rows.iter().enumerate() {
+            let mut line = vec![];
+            for ch in row.chars() {
+                line.push(exponent_to_tile(ch)?);
+            }
+            if line.len() != size {
+                return Err(format!(
+                    "Row {} should have {} tiles, got {}",
+                    i + 1,
+                    size,
+                    line.len()
+                ));
+            }
+            tiles.push(line);
+        }
+
+        // 分数
+        let score = match fields.get(2) {
+            None | Some(&"-") => None,
+            Some(field) => Some(field.parse().map_err(|_| format!("Invalid score: {}", field))?),
+        };
+
+        // 轮到谁
+        let turn = match fields.get(3) {
+            None | Some(&"-") => None,
+            Some(field) => Some(
+                field
+                    .parse()
+                    .ok()
+                    .filter(|&player: &u8| player >= 1)
+                    .ok_or_else(|| format!("Invalid turn: {}", field))?,
+            ),
+        };
+
+        Ok(Self { tiles, score, turn })
+    }
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<String> = self
+            .tiles
+            .iter()
+            .map(|row| row.iter().map(|&tile| tile_to_exponent(tile)).collect())
+            .collect();
+        write!(f, "{} {}", self.tiles.len(), rows.join("/"))?;
+        // 只记录了轮次时分数写成'-'，两项都没有时省略
+        let score = self.score.map_or("-".to_string(), |score| score.to_string());
+        match self.turn {
+            Some(turn) => write!(f, " {} {}", score, turn),
+            None if self.score.is_some() => write!(f, " {}", score),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GameBoard {
+    // 从记谱导入棋盘，分数和轮次由调用方通过 Notation::parse 自行获取
+    pub fn from_notation(text: &str) -> Result<GameBoard, String> {
+        let notation = Notation::parse(text)?;
+        Ok(GameBoard::from_tiles(notation.tiles))
+    }
+
+    // 导出当前棋盘的记谱，有非2的幂的瓷砖时报错
+    pub fn to_notation(&self) -> Result<String, String> {
+        Ok(Notation::new(self.get_tiles().clone())?.to_string())
+    }
+}
+
+// 单个字符转为瓷砖数值
+fn exponent_to_tile(ch: char) -> Result<u32, String> {
+    match ch.to_digit(32) {
+        Some(0) => Ok(0),
+        Some(exponent) if exponent <= MAX_EXPONENT => Ok(1 << exponent),
+        _ => Err(format!("Invalid tile character: {}", ch)),
+    }
+}
+
+// 瓷砖数值转为单个字符，Notation::new 已保证数值是2的幂
+fn tile_to_exponent(tile: u32) -> char {
+    if tile == 0 {
+        return '0';
+    }
+    std::char::from_digit(tile.trailing_zeros(), 32).unwrap()
+}
+
+/// 从命令行参数读取 --position，支持 "--position <记谱>" 与 "--position=<记谱>" 两种写法
+pub fn position_from_args() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--position" {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix("--position=") {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests_notation {
+    use super::*;
+    use crate::game_board::Direction;
+
+    #[test]
+    fn test_parse_and_print() {
+        let text = "4 0000/0120/0003/000b";
+        let notation = Notation::parse(text).unwrap();
+        assert_eq!(
+            notation.tiles,
+            vec![
+                vec![0, 0, 0, 0],
+                vec![0, 2, 4, 0],
+                vec![0, 0, 0, 8],
+                vec![0, 0, 0, 2048],
+            ]
+        );
+        assert_eq!(notation.to_string(), text, "记谱往返不一致");
+    }
+
+    #[test]
+    fn test_score_and_turn_round_trip() {
+        for text in ["2 11/20", "2 11/20 100", "2 11/20 100 2", "2 11/20 - 2"] {
+            assert_eq!(Notation::parse(text).unwrap().to_string(), text, "记谱往返不一致");
+        }
+        let notation = Notation::parse("2 11/20 100 2").unwrap();
+        assert_eq!((notation.score, notation.turn), (Some(100), Some(2)));
+        let notation = Notation::parse("2 11/20 - -").unwrap();
+        assert_eq!((notation.score, notation.turn), (None, None));
+        assert_eq!(notation.to_string(), "2 11/20");
+        assert!(Notation::parse("2 11/20 abc").is_err(), "无效的分数应报错");
+        assert!(Notation::parse("2 11/20 100 0").is_err(), "玩家编号从1开始");
+        assert!(Notation::parse("2 11/20 100 2 x").is_err(), "多余的字段应报错");
+    }
+
+    #[test]
+    fn test_non_power_of_two_rejected() {
+        assert_eq!(Notation::new(vec![vec![12, 0], vec![0, 0]]), Err("Tile 12 is not a power of two".to_string()));
+        let board = GameBoard::from_tiles(vec![vec![2, 0], vec![0, 3]]);
+        assert!(board.to_notation().is_err());
+    }
+
+    #[test]
+    fn test_invalid_positions() {
+        assert!(Notation::parse("").is_err());
+        assert!(Notation::parse("4 0000/0000/0000").is_err(), "行数不足应报错");
+        assert!(Notation::parse("3 000/00/000").is_err(), "列数不足应报错");
+        assert!(Notation::parse("2 0z/00").is_err(), "超出范围的指数应报错");
+    }
+
+    #[test]
+    fn test_game_board_round_trip() {
+        let mut board = GameBoard::from_notation("3 110/000/002").unwrap();
+        assert_eq!(board.size(), 3);
+        board.move_tiles(Direction::Left);
+        assert_eq!(board.to_notation().unwrap(), "3 200/000/200");
+    }
+}
@@ -1,33 +1,540 @@
+use byteorder::{BigEndian, ByteOrder};
 use game_board::Direction;
 use serde::{Deserialize, Serialize};
 use std::str;
-use tokio::io::{self, AsyncReadExt};
-use tokio::net::TcpStream;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
+use crate::config;
 use crate::game_board;
 
-// 定义游戏棋盘的状态
-#[derive(Serialize, Deserialize, Debug)]
+// 定义游戏棋盘的状态，boards 按玩家编号排列，第 i 块是玩家 i+1 的棋盘
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GameState {
-    pub board1: Vec<Vec<u32>>,
-    pub board2: Vec<Vec<u32>>,
-    pub board1_reach_2048: bool,
-    pub board2_reach_2048: bool,
-    pub animated_vector: Option<Vec<u32>>,
+    pub boards: Vec<Vec<Vec<u32>>>,
+    pub reach_2048: Vec<bool>,
+    pub links: Vec<BridgeLink>,        // 每座桥梁连接的两块棋盘，相邻的两块棋盘之间可有多座桥梁，每行至多一座
+    pub animated: Vec<(usize, Vec<u32>)>, // 这一步送过桥的瓷砖：桥梁在 links 中的下标和经过它的瓷砖
+    pub eliminated: Vec<bool>,         // 淘汰赛中各玩家是否已出局
+    pub race: Option<RaceStatus>,      // 竞速玩法的目标和剩余时间，其他玩法为 None
+    pub incoming_garbage: Vec<u32>,    // 垃圾攻击玩法中各玩家即将落下的垃圾块数
+    pub coop: Option<CoopStatus>,      // 合作玩法中的移动权，其他玩法为 None
+    pub clocks: Option<ClockStatus>,   // 回合制玩法的棋钟，其他玩法为 None
+    pub spawner: Option<SpawnerStatus>, // 反派出块玩法中的角色和出块时限，其他玩法为 None
+    pub bridges: Vec<BridgeStatus>,     // 各桥梁当前的状态，与 links 一一对应
+    pub bridge_forecasts: Vec<BridgeForecast>, // 各桥梁的下一次变化，与 bridges 一一对应，服务器没有为桥梁排时间表时为空
+}
+
+/// 一座桥梁当前的状态
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BridgeStatus {
+    pub open: bool,
+    pub line: u8,             // 桥梁所在的行，竖直桥梁为列，从0开始
+    pub direction: Direction, // 瓷砖过桥的方向，向右、向下时 left 一侧送给 right 一侧，向左、向上时反之；上下表示竖直桥梁
+    pub two_way: bool,        // 双向桥梁两侧都能沿 direction 所在的轴发送
+    pub remaining: u32,       // 本段还能送出的瓷砖数
+}
+
+/// 桥梁时间表中的一段，由服务器按每局的种子生成
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BridgePhase {
+    pub open: bool,
+    pub line: u8, // 这一段桥梁所在的行，竖直桥梁为列，开启时可能换到别的行
+    pub direction: Direction,
+    pub two_way: bool,
+    pub sending_limit: u32, // 这一段最多能送出的瓷砖数
+    pub duration_ms: u64,
+}
+
+/// 某座桥梁的下一次变化，客户端收到后自行倒计时
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BridgeForecast {
+    pub next: BridgePhase,
+    pub next_in_ms: u64, // 距离切换到 next 还剩的毫秒数
+}
+
+/// 反派出块玩法的角色：滑动方移动自己的棋盘，出块方决定这块棋盘上下一个瓷砖的位置和数值
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnerStatus {
+    pub slider: u8,                      // 本轮滑动的玩家编号
+    pub spawner: u8,                     // 本轮出块的玩家编号
+    pub spawn_time_left_ms: Option<u64>, // 等待出块时距离时限还剩的毫秒数，等待滑动时为 None
+}
+
+/// 出块方指定的新瓷砖，x为列、y为行，value 只能是2或4
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TilePlacement {
+    pub x: u8,
+    pub y: u8,
+    pub value: u32,
+}
+
+/// 回合制玩法的棋钟，客户端收到后自行为轮到的玩家倒计时
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClockStatus {
+    pub turn: u8,               // 轮到的玩家编号
+    pub remaining_ms: Vec<u64>, // 各玩家棋钟剩余的毫秒数，按玩家编号排列
+}
+
+/// 合作玩法中移动权的状态，所有玩家共用 boards 中唯一的一块棋盘
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CoopStatus {
+    pub control: CoopControl,
+    pub turn: Option<u8>,              // 轮流移动时轮到的玩家编号
+    pub votes: Vec<Option<Direction>>, // 投票移动时各玩家本轮投的方向，按玩家编号排列
+}
+
+/// 竞速玩法的进度，客户端收到后自行倒计时
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaceStatus {
+    pub target: u32,       // 率先合成这个瓷砖的玩家获胜
+    pub time_left_ms: u64, // 距离时间到还剩的毫秒数，时间到时分数最高者获胜
+}
+
+/// 一座桥梁连接的两块棋盘，left 一侧的玩家向右、right 一侧的玩家向左把瓷砖送给对方；
+/// 竖直桥梁中 left 为上方的棋盘，向下送出，right 为下方的棋盘，向上送出
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeLink {
+    pub left: usize,
+    pub right: usize,
+}
+
+impl BridgeLink {
+    // players 块棋盘首尾相连成环，每名玩家右边是下一名、左边是上一名；两人时只有一座桥
+    pub fn ring(players: usize) -> Vec<BridgeLink> {
+        match players {
+            0 | 1 => vec![],
+            2 => vec![BridgeLink { left: 0, right: 1 }],
+            _ => (0..players).map(|left| BridgeLink { left, right: (left + 1) % players }).collect(),
+        }
+    }
 }
 
 // 玩家的操作，将内部Direction封装
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlayerAction {
     pub direction: Direction,
 }
 
 // 消息枚举，用于区分不同类型的消息
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    GameState(GameState),
+    GameState(Box<GameState>), // 棋盘状态比其他消息大得多，装箱后不拖大整个枚举
     PlayerAction(PlayerAction),
     PlayerIdentity(PlayerIdentity),
+    Hello(Hello),         // 握手：客户端连接后发送的第一条消息
+    Welcome(Welcome),     // 握手：服务器接受连接
+    Rejected(String),     // 握手：服务器拒绝连接，内容为原因，随后断开
+    Forfeit,              // 客户端认输，对局立即结束
+    OpponentLeft(u8),     // 该玩家断开连接，随后服务器发送 MatchOver
+    MatchOver(MatchResult), // 对局结束，服务器发送后关闭连接
+    SessionToken(String),   // 对局开始时服务器发给玩家的会话令牌，断线后凭它重连
+    OpponentDisconnected(u8), // 该玩家掉线，服务器在宽限期内为其保留对局
+    OpponentReconnected(u8),  // 掉线的该玩家已重新连接
+    Ping(u64),              // 服务器在大厅和对局中定时发送的心跳，内容为序号
+    Pong(u64),              // 客户端收到心跳后原样回复序号
+    Latency(Latency),       // 服务器测得的各玩家往返延迟
+    CreateRoom(RoomSettings), // 大厅：创建房间并在其中等待对手
+    ListRooms,                // 大厅：请求公开房间列表
+    JoinRoom(String),         // 大厅：凭房间码加入房间，人满后对局立即开始
+    LeaveRoom,                // 大厅：关闭自己创建的房间，或离开已加入的房间
+    RoomCreated(RoomInfo),    // 大厅：房间已创建，附带房间码
+    RoomUpdate(RoomInfo),     // 大厅：有人加入了自己所在的房间，发给房间中的所有人
+    RoomList(Vec<RoomInfo>),  // 大厅：公开房间列表
+    LobbyError(String),       // 大厅：请求失败，内容为原因
+    JoinQueue(QueueRequest),  // 大厅：以指定玩家名加入排位队列，名字已被认领时须带上服务器发放的密钥
+    PlayerKey(String),        // 大厅：第一次以某名字排位时服务器发放的密钥，客户端保存，之后以该名字排位都要带上
+    LeaveQueue,               // 大厅：退出排位队列
+    Queued(u32),              // 大厅：已进入排位队列，内容为自己的等级分
+    ListMatches,              // 观战：请求进行中的对局列表
+    MatchList(Vec<MatchInfo>), // 观战：进行中的对局列表
+    Spectate(SpectateRequest), // 观战：只读地观看一局对局
+    Spectating(MatchInfo),    // 观战：开始观看，随后收到与玩家相同的 GameState，直到 MatchOver
+    Eliminated(Elimination),  // 淘汰赛：有玩家出局，出局的玩家留在对局中观战
+    ListCoopScores,           // 大厅：请求合作玩法排行榜
+    CoopScores(Vec<CoopScore>), // 大厅：合作玩法排行榜，分数从高到低
+    SpawnTile(TilePlacement), // 反派出块：出块方在滑动方的棋盘上放置新瓷砖
+}
+
+/// 对局的玩法
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    #[default]
+    Classic,      // 任何一人合成2048、无路可走或离开时对局结束
+    BattleRoyale, // 淘汰赛：无路可走、认输或离开的玩家出局，桥梁改连到下一名幸存的邻居，最后留下的玩家获胜
+    Race { target: u32, time_limit_secs: u32 }, // 竞速：率先合成 target 者胜，时间到时分数最高者胜
+    Garbage,      // 垃圾攻击：大合并和连锁合并向下一名玩家发送垃圾，其余规则同经典玩法
+    Coop(CoopControl), // 合作：所有玩家共用一块棋盘，无路可走时结束，共同得分计入合作排行榜
+    TurnBased { clock_secs: u32, increment_secs: u32 }, // 回合制：轮流移动，每人一个棋钟，每走一步加 increment_secs，用完判负
+    Spawner { spawn_secs: u32 }, // 反派出块：两人一人滑动、一人在 spawn_secs 内出块，滑动方无路可走时交换角色，两轮后滑动得分高者胜
+}
+
+/// 合作玩法中谁可以移动
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoopControl {
+    Alternate, // 轮流移动，每人一步
+    Vote,      // 在投票窗口内投票，得票最多的方向生效，平票时先投的优先
+    SplitAxis, // 分轴：编号为奇数的玩家只能左右移动，偶数的只能上下移动
+}
+
+impl CoopControl {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            CoopControl::Alternate => "轮流",
+            CoopControl::Vote => "投票",
+            CoopControl::SplitAxis => "分轴",
+        }
+    }
+
+    // 分轴时该玩家（从0开始的下标）能否向 direction 移动，其他方式下总是可以
+    pub fn allows(&self, player: usize, direction: Direction) -> bool {
+        match (self, player % 2) {
+            (CoopControl::SplitAxis, 0) => matches!(direction, Direction::Left | Direction::Right),
+            (CoopControl::SplitAxis, _) => matches!(direction, Direction::Up | Direction::Down),
+            _ => true,
+        }
+    }
+}
+
+/// 合作玩法排行榜上的一条记录
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CoopScore {
+    pub team: String, // 房间名
+    pub players: u8,
+    pub control: CoopControl,
+    pub score: u32,
+}
+
+impl MatchMode {
+    pub const ALL: [MatchMode; 9] = [
+        MatchMode::Classic,
+        MatchMode::BattleRoyale,
+        MatchMode::Race { target: config::RACE_DEFAULT_TARGET, time_limit_secs: config::RACE_DEFAULT_TIME_LIMIT_SECS },
+        MatchMode::Garbage,
+        MatchMode::Coop(CoopControl::Alternate),
+        MatchMode::Coop(CoopControl::Vote),
+        MatchMode::Coop(CoopControl::SplitAxis),
+        MatchMode::TurnBased { clock_secs: config::TURN_CLOCK_DEFAULT_SECS, increment_secs: config::TURN_INCREMENT_DEFAULT_SECS },
+        MatchMode::Spawner { spawn_secs: config::SPAWNER_DEFAULT_SPAWN_SECS },
+    ];
+
+    pub fn describe(&self) -> String {
+        match self {
+            MatchMode::Classic => "经典".to_string(),
+            MatchMode::BattleRoyale => "淘汰赛".to_string(),
+            MatchMode::Race { target, time_limit_secs } => format!("竞速 {} / {}秒", target, time_limit_secs),
+            MatchMode::Garbage => "垃圾攻击".to_string(),
+            MatchMode::Coop(control) => format!("合作·{}", control.describe()),
+            MatchMode::TurnBased { clock_secs, increment_secs } => format!("回合制 {}秒+{}秒", clock_secs, increment_secs),
+            MatchMode::Spawner { spawn_secs } => format!("反派出块 {}秒", spawn_secs),
+        }
+    }
+
+    // 该玩法的功能标志，同一玩法的不同设置共用一个
+    pub fn feature(&self) -> &'static str {
+        match self {
+            MatchMode::Classic => "mode-classic",
+            MatchMode::BattleRoyale => "mode-battle-royale",
+            MatchMode::Race { .. } => "mode-race",
+            MatchMode::Garbage => "mode-garbage",
+            MatchMode::Coop(_) => "mode-coop",
+            MatchMode::TurnBased { .. } => "mode-turn-based",
+            MatchMode::Spawner { .. } => "mode-spawner",
+        }
+    }
+
+    // 大厅中依次切换玩法，竞速、回合制和反派出块玩法切换到时取默认的设置
+    pub fn next(&self) -> Self {
+        let kind = std::mem::discriminant(self);
+        let index = Self::ALL
+            .iter()
+            .position(|mode| mode == self)
+            .or_else(|| Self::ALL.iter().position(|mode| std::mem::discriminant(mode) == kind))
+            .unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    // 服务器只接受合理的设置：竞速目标是 2 的幂且不小于 8，竞速时限、棋钟和出块时限在范围之内，反派出块恰好两人
+    pub fn validate(&self, players: u8) -> Result<(), String> {
+        match *self {
+            MatchMode::Race { target, .. } if target < 8 || !target.is_power_of_two() => {
+                Err(format!("竞速目标 {} 必须是不小于 8 的 2 的幂", target))
+            }
+            MatchMode::Race { time_limit_secs, .. }
+                if !(config::RACE_MIN_TIME_LIMIT_SECS..=config::RACE_MAX_TIME_LIMIT_SECS).contains(&time_limit_secs) =>
+            {
+                Err(format!(
+                    "竞速时限须在 {} 到 {} 秒之间",
+                    config::RACE_MIN_TIME_LIMIT_SECS,
+                    config::RACE_MAX_TIME_LIMIT_SECS
+                ))
+            }
+            MatchMode::TurnBased { clock_secs, increment_secs }
+                if !(config::TURN_CLOCK_MIN_SECS..=config::TURN_CLOCK_MAX_SECS).contains(&clock_secs)
+                    || increment_secs > config::TURN_INCREMENT_MAX_SECS =>
+            {
+                Err(format!(
+                    "棋钟须在 {} 到 {} 秒之间，每步加时不超过 {} 秒",
+                    config::TURN_CLOCK_MIN_SECS,
+                    config::TURN_CLOCK_MAX_SECS,
+                    config::TURN_INCREMENT_MAX_SECS
+                ))
+            }
+            MatchMode::Spawner { .. } if players != 2 => Err("反派出块玩法只能两人对局".to_string()),
+            MatchMode::Spawner { spawn_secs }
+                if !(config::SPAWNER_MIN_SPAWN_SECS..=config::SPAWNER_MAX_SPAWN_SECS).contains(&spawn_secs) =>
+            {
+                Err(format!(
+                    "出块时限须在 {} 到 {} 秒之间",
+                    config::SPAWNER_MIN_SPAWN_SECS,
+                    config::SPAWNER_MAX_SPAWN_SECS
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 淘汰赛中一名玩家出局
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Elimination {
+    pub player: u8,             // 出局的玩家编号
+    pub reason: MatchEndReason,
+    pub place: u8,              // 出局者的最终名次
+    pub standings: Vec<u8>,     // 当前排名，从第一名开始的玩家编号：幸存者按分数排在前面，出局者按出局先后倒序排在后面
+}
+
+/// 进行中的一局对局
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MatchInfo {
+    pub id: u64,
+    pub title: String, // 房间名，排位赛为双方玩家名
+}
+
+/// 排位请求，key 为之前以该名字排位时服务器发放的密钥，第一次排位时为 None
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueueRequest {
+    pub name: String,
+    pub key: Option<String>,
+}
+
+/// 观战请求，delay_secs 不为零时画面比实际对局晚这么多秒
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpectateRequest {
+    pub match_id: u64,
+    pub delay_secs: u32,
+}
+
+/// 创建房间时的设置
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomSettings {
+    pub name: String,
+    pub private: bool, // 私密房间不出现在列表中，只能凭房间码加入
+    pub players: u8,   // 对局人数，含房主
+    pub mode: MatchMode,
+    pub position: Option<String>, // 所有人的开局局面记谱，边长须与服务器的棋盘一致，为空时随机开局
+    pub stacked: bool, // 两人对局时棋盘上下叠放，桥梁架在列上
+}
+
+/// 大厅中的一个房间
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub code: String, // 加入房间用的短码
+    pub name: String,
+    pub private: bool,
+    pub players: u8,  // 对局人数
+    pub joined: u8,   // 已在房间中的人数，含房主
+    pub mode: MatchMode,
+    pub position: Option<String>, // 开局局面记谱，为空时随机开局
+    pub stacked: bool, // 棋盘上下叠放，桥梁架在列上
+}
+
+/// 各玩家的往返延迟，单位毫秒，按玩家编号排列，尚未测得时为 None
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub players_ms: Vec<Option<u32>>,
+}
+
+/// 对局结束的原因
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchEndReason {
+    Reached2048,  // 胜者合成了2048
+    NoMoves,      // 败者的棋盘无路可走
+    Forfeit,      // 败者认输
+    OpponentLeft, // 败者断开连接
+    Idle,         // 败者长时间没有操作
+    ReachedTarget, // 竞速：胜者率先合成了目标瓷砖
+    TimeUp,        // 竞速：时间到，分数最高者胜
+    OutOfTime,     // 回合制：败者的棋钟走完了
+    RolesSwapped,  // 反派出块：两轮都已结束，滑动得分高者胜
+}
+
+/// 对局结果，附带各玩家最终棋盘和分数，均按玩家编号排列；合作玩法只有一块棋盘和一个共同得分
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MatchResult {
+    pub winner: Option<u8>, // 胜者的玩家编号（从1开始），None 表示平局或合作玩法
+    pub reason: MatchEndReason,
+    pub boards: Vec<Vec<Vec<u32>>>,
+    pub scores: Vec<u32>,
+    pub standings: Vec<u8>, // 最终排名，从第一名开始的玩家编号
+}
+
+impl MatchEndReason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            MatchEndReason::Reached2048 => "合成2048",
+            MatchEndReason::NoMoves => "无路可走",
+            MatchEndReason::Forfeit => "认输",
+            MatchEndReason::OpponentLeft => "离开对局",
+            MatchEndReason::Idle => "长时间未操作",
+            MatchEndReason::ReachedTarget => "率先合成目标",
+            MatchEndReason::TimeUp => "时间到",
+            MatchEndReason::OutOfTime => "超时",
+            MatchEndReason::RolesSwapped => "两轮结束",
+        }
+    }
+}
+
+/// 协议版本，消息格式或流程有不兼容的改动时加一
+/// - 2：握手后先进入大厅，不再自动配对
+/// - 3：对局支持多名玩家，棋盘改为列表
+/// - 4：房间可选玩法，加入淘汰赛
+/// - 5：加入竞速玩法
+/// - 6：加入垃圾攻击玩法
+/// - 7：加入合作玩法
+/// - 8：加入回合制玩法
+/// - 9：加入反派出块玩法
+/// - 10：桥梁由服务器按时间表变化
+/// - 11：桥梁可以双向、竖直
+/// - 12：相邻棋盘之间每行一座桥梁，各行分别变化
+/// - 13：开局局面改为房间设置，去掉对局中的 LoadPosition
+/// - 14：每座桥梁各有一份时间表，bridge_forecasts 与 bridges 一一对应
+/// - 15：房间可选上下叠放，两人对局的桥梁改为架在列上
+/// - 16：相邻棋盘之间的桥梁少于行数，开启时会换行
+/// - 17：大厅中也发送心跳，客户端须回复 Pong，房间中的加入者可以发送 LeaveRoom 离开
+/// - 18：排位的玩家名由服务器发放的密钥认领，JoinQueue 带上密钥
+pub const PROTOCOL_VERSION: u32 = 18;
+
+// 功能标志，未知的标志直接忽略，新功能可以在不升级版本的情况下加入
+// 服务器用 --disable 关掉的功能不出现在 Welcome 中，客户端据此隐藏相应的选项
+pub const FEATURE_BRIDGE: &str = "bridge";               // 服务器在双方棋盘之间架设桥梁
+pub const FEATURE_BRIDGE_SCHEDULE: &str = "bridge-schedule"; // 桥梁按时间表开关、换行，GameState 带有预告；没有时桥梁一直双向开启
+pub const FEATURE_STACKED: &str = "stacked";             // 两人房间可以上下叠放
+// 每种玩法另有一个标志，见 MatchMode::feature，经典玩法总是开启
+
+/// 服务器支持的所有功能
+pub fn all_features() -> Vec<&'static str> {
+    let mut features = vec![FEATURE_BRIDGE, FEATURE_BRIDGE_SCHEDULE, FEATURE_STACKED];
+    for mode in MatchMode::ALL {
+        if !features.contains(&mode.feature()) {
+            features.push(mode.feature());
+        }
+    }
+    features
+}
+
+// 从命令行参数读取 --disable=<功能,...>，返回其余开启的功能
+pub fn features_from_args() -> Result<Vec<&'static str>, String> {
+    match std::env::args().skip(1).find_map(|arg| arg.strip_prefix("--disable=").map(str::to_string)) {
+        Some(disabled) => features_without(&disabled),
+        None => Ok(all_features()),
+    }
+}
+
+// 关掉以逗号分隔的功能，返回其余的功能，名称未知或要关掉经典玩法时返回错误
+pub fn features_without(disabled: &str) -> Result<Vec<&'static str>, String> {
+    let mut features = all_features();
+    for name in disabled.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        if name == MatchMode::Classic.feature() {
+            return Err("经典玩法不能关闭".to_string());
+        }
+        if !all_features().contains(&name) {
+            return Err(format!("未知的功能 {}，可选：{}", name, all_features().join(", ")));
+        }
+        features.retain(|&feature| feature != name);
+    }
+    Ok(features)
+}
+
+/// 握手请求，握手消息总是使用 JSON 编码，之后双方改用协商出的编码
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+    pub client_name: String,
+    pub codecs: Vec<Codec>,     // 客户端支持的编码，越靠前越优先
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub resume_token: Option<String>, // 断线重连时带上之前收到的会话令牌
+}
+
+/// 握手应答
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Welcome {
+    pub version: u32,
+    pub server_name: String,
+    pub codec: Codec,           // 之后双方都使用的编码
+    pub features: Vec<String>,  // 服务器启用的功能
+    pub board_size: usize,      // 本服务器对局使用的棋盘边长
+}
+
+impl Hello {
+    pub fn new(client_name: &str, preferred: Codec) -> Self {
+        let mut codecs = vec![preferred];
+        codecs.extend(Codec::ALL.iter().filter(|&&codec| codec != preferred));
+        Self {
+            version: PROTOCOL_VERSION,
+            client_name: client_name.to_string(),
+            codecs,
+            features: vec![],
+            resume_token: None,
+        }
+    }
+
+    // 用会话令牌恢复之前的对局
+    pub fn resume(client_name: &str, preferred: Codec, token: &str) -> Self {
+        Self {
+            resume_token: Some(token.to_string()),
+            ..Self::new(client_name, preferred)
+        }
+    }
+}
+
+impl Welcome {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// 服务器根据 Hello 决定是否接受连接，拒绝时返回展示给玩家的原因
+// 编码优先使用服务器指定的，客户端不支持时按客户端的顺序选第一个
+pub fn negotiate(
+    hello: &Hello,
+    server_name: &str,
+    preferred: Codec,
+    features: &[&str],
+    board_size: usize,
+) -> Result<Welcome, String> {
+    if hello.version != PROTOCOL_VERSION {
+        return Err(format!(
+            "协议版本不兼容：客户端为 {}，服务器为 {}，请更新客户端",
+            hello.version, PROTOCOL_VERSION
+        ));
+    }
+    let codec = if hello.codecs.contains(&preferred) {
+        preferred
+    } else {
+        *hello
+            .codecs
+            .iter()
+            .find(|codec| Codec::ALL.contains(codec))
+            .ok_or_else(|| "没有双方都支持的消息编码".to_string())?
+    };
+    Ok(Welcome {
+        version: PROTOCOL_VERSION,
+        server_name: server_name.to_string(),
+        codec,
+        features: features.iter().map(|f| f.to_string()).collect(),
+        board_size,
+    })
 }
 
 // 序列化消息
@@ -40,151 +547,308 @@ pub fn deserialize_message(json_data: &str) -> Result<Message, serde_json::Error
     serde_json::from_str(json_data)
 }
 
-// 接受协议报函数，无重复buffer设计
-pub async fn receive_message(stream: &mut TcpStream) -> Result<Message, io::Error> {
-    let mut buffer = Vec::new(); // 使用动态数组来处理可能的多个数据块
-    let mut temp_buf = [0; 1024]; // 临时缓冲区
+/// 消息体的编码方式，客户端和服务器读写共用
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    Bincode,
+}
 
-    // 持续从流中读取数据，直到找到消息终止符
-    loop {
-        let n = stream.read(&mut temp_buf).await?;
-        if n == 0 {
-            if buffer.is_empty() {
-                eprintln!("No data read; stream might be closed.");
-                return Err(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "Connection was closed by the server",
-                ));
-            } else {
-                break; // 如果已经读到了数据但没有更多数据了，则尝试解析它
-            }
-        }
-        buffer.extend_from_slice(&temp_buf[..n]);
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Json, Codec::Bincode];
 
-        if buffer.ends_with(b"\n") {
-            // 检查是否达到消息的结尾
-            break; // 完成消息读取
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "json" => Ok(Codec::Json),
+            "bincode" => Ok(Codec::Bincode),
+            _ => Err(format!("Unknown codec '{}', expected json or bincode", name)),
         }
     }
 
-    // 从buffer中移除终止符，并尝试解析消息
-    let message_str =
-        str::from_utf8(&buffer[..buffer.len() - 1]) // 去除末尾的'\n'
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    // 从命令行参数读取 --codec=<json|bincode>，作为握手时的首选编码，未指定时使用 JSON
+    pub fn from_args() -> Result<Self, String> {
+        match std::env::args().skip(1).find_map(|arg| arg.strip_prefix("--codec=").map(str::to_string)) {
+            Some(name) => Codec::parse(&name),
+            None => Ok(Codec::default()),
+        }
+    }
 
-    match deserialize_message(message_str) {
-        Ok(message) => {
-            // eprintln!("Successfully deserialized message.");
-            // println!("correct message: {:?}", message);
-            Ok(message)
+    pub fn encode(&self, message: &Message) -> Result<Vec<u8>, String> {
+        match self {
+            Codec::Json => serialize_message(message).map(String::into_bytes).map_err(|e| e.to_string()),
+            Codec::Bincode => bincode::serialize(message).map_err(|e| e.to_string()),
         }
-        Err(e) => {
-            println!("{:?}", temp_buf);
-            eprintln!("Failed to deserialize message: {}", e);
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to deserialize message: {}", e),
-            ))
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<Message, String> {
+        match self {
+            Codec::Json => {
+                let json_data = str::from_utf8(data).map_err(|e| e.to_string())?;
+                deserialize_message(json_data).map_err(|e| e.to_string())
+            }
+            Codec::Bincode => bincode::deserialize(data).map_err(|e| e.to_string()),
         }
     }
 }
 
-pub fn prevent_sticky_message(ori: String) -> String {
-    ori + "\n"
+// 帧格式：4字节大端长度 + 消息体，长度不含头部本身
+const FRAME_HEADER_SIZE: usize = 4;
+
+// 把消息编码成一帧，超过 MAX_FRAME_SIZE 的消息直接拒绝
+pub fn encode_frame(codec: Codec, message: &Message) -> Result<Vec<u8>, io::Error> {
+    let payload = codec
+        .encode(message)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if payload.len() > config::MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Frame of {} bytes exceeds limit of {}", payload.len(), config::MAX_FRAME_SIZE),
+        ));
+    }
+    let mut frame = vec![0; FRAME_HEADER_SIZE];
+    BigEndian::write_u32(&mut frame, payload.len() as u32);
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+// 发送一条消息，整帧一次写出
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    codec: Codec,
+    message: &Message,
+) -> Result<(), io::Error> {
+    let frame = encode_frame(codec, message)?;
+    writer.write_all(&frame).await
+}
+
+/// 按长度前缀拆帧的读取器，未读完的数据留在内部缓冲区
+// 每次 read 的结果都先追加到缓冲区再解析，在 select! 中被取消也不会丢数据，
+// 一次 read 读到半帧或多帧都能正确处理
+pub struct FrameReader {
+    buffer: Vec<u8>,
 }
 
-// 接受协议报函数，有大量数据的设计
-/// 接收消息并处理缓冲区，返回第一个完整的消息
-pub async fn receive_message_with_buffer(
-    stream: &mut TcpStream,
-    buffer: &mut Vec<u8>,
-) -> Result<Message, io::Error> {
-    // 首先检查缓冲区是否已包含至少一个完整的消息
-    if let Some(pos) = buffer.iter().position(|&x| x == b'\n') {
-        let message = process_message(&buffer[..pos])?; // 处理第一个完整消息，不包括 '\n'
-        buffer.drain(..=pos); // 清空包括 '\n' 在内的已处理部分
-        return Ok(message);
+impl FrameReader {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
     }
 
-    // 从流中读取数据，直到找到一个完整的消息
-    let mut temp_buf = [0; 1024]; // 临时缓冲区
+    // 读取下一帧的消息体，连接关闭或帧长度超限时返回错误，此后这条连接不能再用
+    pub async fn read_frame<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<Vec<u8>, io::Error> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+            let mut temp_buf = [0; 1024];
+            let n = reader.read(&mut temp_buf).await?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    if self.buffer.is_empty() {
+                        "Connection was closed by the peer"
+                    } else {
+                        "Connection was closed in the middle of a frame"
+                    },
+                ));
+            }
+            self.buffer.extend_from_slice(&temp_buf[..n]);
+        }
+    }
 
-    let n = stream.read(&mut temp_buf).await?;
-    if n == 0 {
-        // 如果没有更多数据可读，检查缓冲区是否空，如果是则报错
-        if buffer.is_empty() {
-            eprintln!("No data read; stream might be closed.");
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "Connection was closed by the server",
-            ));
-        } else {
+    // 读取并解码下一条消息，解码失败也视为错误
+    pub async fn read_message<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+        codec: Codec,
+    ) -> Result<Message, io::Error> {
+        let frame = self.read_frame(reader).await?;
+        codec.decode(&frame).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Failed to deserialize message: {}", e))
+        })
+    }
+
+    // 缓冲区中已有完整的一帧时取出
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, io::Error> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+        let length = BigEndian::read_u32(&self.buffer[..FRAME_HEADER_SIZE]) as usize;
+        if length > config::MAX_FRAME_SIZE {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "Data does not end with a newline and no more data to read",
+                format!("Frame of {} bytes exceeds limit of {}", length, config::MAX_FRAME_SIZE),
             ));
         }
+        if self.buffer.len() < FRAME_HEADER_SIZE + length {
+            return Ok(None);
+        }
+        let frame = self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length].to_vec();
+        self.buffer.drain(..FRAME_HEADER_SIZE + length);
+        Ok(Some(frame))
     }
-    buffer.extend_from_slice(&temp_buf[..n]); // 将新读取的数据追加到缓冲区后面
+}
+
+// 告诉玩家是player1还是2
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerIdentity {
+    pub player_number: u8, // 从1开始的玩家编号，与 GameState 中棋盘的顺序对应
+}
+
+#[cfg(test)]
+mod tests_protocol {
+    use super::*;
 
-    // 再次检查是否存在完整的消息
-    if let Some(pos) = buffer.iter().position(|&x| x == b'\n') {
-        let message = process_message(&buffer[..pos])?; // 处理第一个完整消息，不包括 '\n'
-        buffer.drain(..=pos); // 清空包括 '\n' 在内的已处理部分
-        return Ok(message);
+    fn sample_message() -> Message {
+        Message::GameState(Box::new(GameState {
+            boards: vec![vec![vec![2, 0, 0, 4]; 4], vec![vec![0, 8, 16, 0]; 4], vec![vec![0; 4]; 4]],
+            reach_2048: vec![false, true, false],
+            links: [BridgeLink::ring(3), BridgeLink::ring(3)].concat(),
+            animated: vec![(2, vec![1, 2, 3]), (5, vec![4])],
+            eliminated: vec![false, false, true],
+            race: Some(RaceStatus { target: 1024, time_left_ms: 12345 }),
+            incoming_garbage: vec![0, 3, 0],
+            coop: Some(CoopStatus { control: CoopControl::Vote, turn: None, votes: vec![Some(Direction::Up), None, None] }),
+            clocks: Some(ClockStatus { turn: 2, remaining_ms: vec![1000, 2000, 3000] }),
+            spawner: Some(SpawnerStatus { slider: 1, spawner: 2, spawn_time_left_ms: Some(500) }),
+            bridges: vec![BridgeStatus { open: true, line: 2, direction: Direction::Left, two_way: false, remaining: 3 }; 6],
+            bridge_forecasts: vec![BridgeForecast {
+                next: BridgePhase { open: false, line: 1, direction: Direction::Up, two_way: true, sending_limit: 5, duration_ms: 4000 },
+                next_in_ms: 1500,
+            }],
+        }))
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::InvalidData,
-        "Data does not end with a newline",
-    ))
-}
+    #[tokio::test]
+    async fn test_round_trip_both_codecs() {
+        for codec in [Codec::Json, Codec::Bincode] {
+            let mut data = Vec::new();
+            write_message(&mut data, codec, &sample_message()).await.unwrap();
+            write_message(&mut data, codec, &Message::PlayerAction(PlayerAction { direction: Direction::Left }))
+                .await
+                .unwrap();
 
-/// 解析消息并返回
-fn process_message(data: &[u8]) -> Result<Message, io::Error> {
-    let message_str = String::from_utf8_lossy(data);
-    // println!("{:?}", message_str);
-    match deserialize_message(&message_str) {
-        Ok(message) => {
-            // eprintln!("Successfully deserialized message.");
-            Ok(message)
+            // 两帧粘在一起也能依次读出
+            let mut reader = FrameReader::new();
+            let mut stream = &data[..];
+            let first = reader.read_message(&mut stream, codec).await.unwrap();
+            assert_eq!(format!("{:?}", first), format!("{:?}", sample_message()));
+            let second = reader.read_message(&mut stream, codec).await.unwrap();
+            assert!(matches!(second, Message::PlayerAction(PlayerAction { direction: Direction::Left })));
+            assert_eq!(
+                reader.read_message(&mut stream, codec).await.unwrap_err().kind(),
+                io::ErrorKind::UnexpectedEof
+            );
         }
-        Err(e) => {
-            eprintln!("Failed to deserialize message: {}", e);
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to deserialize message: {}", e),
-            ))
+    }
+
+    #[tokio::test]
+    async fn test_split_reads() {
+        // 管道容量只有3字节，每次 read 都只能读到半帧
+        let (mut client, mut server) = io::duplex(3);
+        let writer = tokio::spawn(async move {
+            for _ in 0..3 {
+                write_message(&mut client, Codec::Bincode, &sample_message()).await.unwrap();
+            }
+        });
+        let mut reader = FrameReader::new();
+        for _ in 0..3 {
+            let message = reader.read_message(&mut server, Codec::Bincode).await.unwrap();
+            assert!(matches!(message, Message::GameState(_)));
         }
+        writer.await.unwrap();
+    }
+
+    #[test]
+    fn test_disable_features() {
+        let features = features_without("stacked, mode-race").unwrap();
+        assert!(!features.contains(&FEATURE_STACKED));
+        assert!(!features.contains(&MatchMode::ALL[2].feature()));
+        assert!(features.contains(&FEATURE_BRIDGE));
+        // 合作玩法的三种方式共用一个标志
+        assert_eq!(all_features().iter().filter(|&&feature| feature == "mode-coop").count(), 1);
+        assert!(features_without("mode-classic").is_err());
+        assert!(features_without("teleport").is_err());
+        assert_eq!(features_without("").unwrap(), all_features());
     }
-}
 
-// 写入，序列化方法
-// let game_state = GameState {
-//     board1,
-//     board2,
-//     board1_reach_2048: bool,
-//     board2_reach_2048: bool,
-// };
-// let message = Message::GameState(game_state);
-// let serialized = serialize_message(&message).unwrap();
-// socket.write_all(serialized.as_bytes()).await.unwrap();
+    #[test]
+    fn test_negotiate() {
+        let hello = Hello::new("test", Codec::Bincode);
+        assert_eq!(hello.codecs, vec![Codec::Bincode, Codec::Json]);
 
-// 接受，反序列化
-// let received_json = String::from_utf8(buffer[..n].to_vec()).unwrap();
+        // 服务器首选的编码客户端也支持时使用服务器的
+        let welcome = negotiate(&hello, "server", Codec::Json, &[FEATURE_BRIDGE], 4).unwrap();
+        assert_eq!(welcome.codec, Codec::Json);
+        assert!(welcome.has_feature(FEATURE_BRIDGE));
+        assert_eq!(welcome.features, vec![FEATURE_BRIDGE.to_string()]);
 
-//     解析接收到的消息
-//     match deserialize_message(&received_json) {
-//         Ok(Message::PlayerAction(action)) => {
-//             println!("Received player action: {:?}", action.direction);
-//             // 处理玩家操作...
-//         },
-//         Ok(_) => println!("Unexpected message type"),
-//         Err(e) => println!("Failed to deserialize message: {:?}", e),
-//     }
+        // 客户端只支持一种时按客户端的来
+        let only_json = Hello { codecs: vec![Codec::Json], ..hello.clone() };
+        assert_eq!(negotiate(&only_json, "server", Codec::Bincode, &[], 4).unwrap().codec, Codec::Json);
 
-// 告诉玩家是player1还是2
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PlayerIdentity {
-    pub player_number: u8, // 用数字1或2表示玩家1或玩家2
+        let none = Hello { codecs: vec![], ..hello.clone() };
+        assert!(negotiate(&none, "server", Codec::Json, &[], 4).is_err());
+
+        let old = Hello { version: PROTOCOL_VERSION + 1, ..hello };
+        assert!(negotiate(&old, "server", Codec::Json, &[], 4).unwrap_err().contains("协议版本"));
+    }
+
+    #[test]
+    fn test_ring_links() {
+        assert!(BridgeLink::ring(1).is_empty());
+        assert_eq!(BridgeLink::ring(2), vec![BridgeLink { left: 0, right: 1 }]);
+        // 每名玩家恰好在一座桥的左侧、一座桥的右侧，最后一名与第一名相连
+        let links = BridgeLink::ring(5);
+        assert_eq!(links.len(), 5);
+        for player in 0..5 {
+            assert_eq!(links.iter().filter(|link| link.left == player).count(), 1);
+            assert_eq!(links.iter().filter(|link| link.right == player).count(), 1);
+        }
+        assert_eq!(links[4], BridgeLink { left: 4, right: 0 });
+    }
+
+    #[test]
+    fn test_mode_settings() {
+        // 自定义设置的竞速、回合制玩法也能切换到下一种玩法
+        let race = MatchMode::Race { target: 256, time_limit_secs: 60 };
+        assert_eq!(race.next(), MatchMode::Garbage);
+        assert_eq!(MatchMode::Garbage.next(), MatchMode::Coop(CoopControl::Alternate));
+        assert_eq!(MatchMode::Coop(CoopControl::Alternate).next(), MatchMode::Coop(CoopControl::Vote));
+        assert_eq!(MatchMode::Coop(CoopControl::SplitAxis).next(), MatchMode::ALL[7]);
+        assert_eq!(MatchMode::TurnBased { clock_secs: 60, increment_secs: 0 }.next(), MatchMode::ALL[8]);
+        assert_eq!(MatchMode::Spawner { spawn_secs: 9 }.next(), MatchMode::Classic);
+        assert!(MatchMode::TurnBased { clock_secs: 60, increment_secs: 0 }.validate(2).is_ok());
+        assert!(MatchMode::TurnBased { clock_secs: 0, increment_secs: 0 }.validate(2).is_err());
+        assert!(MatchMode::TurnBased { clock_secs: 60, increment_secs: 3600 }.validate(2).is_err());
+        assert_eq!(MatchMode::BattleRoyale.next(), MatchMode::ALL[2]);
+        assert!(race.validate(2).is_ok());
+        assert!(MatchMode::Race { target: 100, time_limit_secs: 60 }.validate(2).is_err());
+        assert!(MatchMode::Race { target: 4, time_limit_secs: 60 }.validate(2).is_err());
+        assert!(MatchMode::Race { target: 256, time_limit_secs: 0 }.validate(2).is_err());
+        assert!(MatchMode::Spawner { spawn_secs: 5 }.validate(2).is_ok());
+        assert!(MatchMode::Spawner { spawn_secs: 5 }.validate(3).is_err());
+        assert!(MatchMode::Spawner { spawn_secs: 0 }.validate(2).is_err());
+    }
+
+    #[test]
+    fn test_split_axis() {
+        assert!(CoopControl::SplitAxis.allows(0, Direction::Left));
+        assert!(!CoopControl::SplitAxis.allows(0, Direction::Up));
+        assert!(CoopControl::SplitAxis.allows(1, Direction::Down));
+        assert!(!CoopControl::SplitAxis.allows(1, Direction::Right));
+        assert!(CoopControl::Vote.allows(1, Direction::Right));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_rejected() {
+        let mut data = vec![0; FRAME_HEADER_SIZE];
+        BigEndian::write_u32(&mut data, config::MAX_FRAME_SIZE as u32 + 1);
+        let mut stream = &data[..];
+        let error = FrameReader::new().read_frame(&mut stream).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let huge = Message::JoinRoom("0".repeat(config::MAX_FRAME_SIZE + 1));
+        assert!(encode_frame(Codec::Json, &huge).is_err());
+    }
 }
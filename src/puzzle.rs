@@ -0,0 +1,331 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::config;
+use crate::game_board::Direction;
+use crate::GameBoard;
+
+// 随游戏附带的谜题文件
+const BUNDLED_PUZZLES: &str = include_str!("../puzzles/puzzles.json");
+
+pub const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+/// 谜题目标
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Goal {
+    ReachTile { tile: u32, moves: usize },      // 在 moves 步内合成 tile
+    ClearDownTo { tiles: usize, moves: usize }, // 在 moves 步内把棋盘上的瓷砖减少到 tiles 个及以下
+}
+
+impl Goal {
+    pub fn move_limit(&self) -> usize {
+        match self {
+            Goal::ReachTile { moves, .. } => *moves,
+            Goal::ClearDownTo { moves, .. } => *moves,
+        }
+    }
+
+    // 检查棋盘是否已达成目标
+    pub fn is_reached(&self, board: &GameBoard) -> bool {
+        match self {
+            Goal::ReachTile { tile, .. } => board.return_score().1 >= *tile,
+            Goal::ClearDownTo { tiles, .. } => board.count_tiles() <= *tiles,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Goal::ReachTile { tile, moves } => format!("{} 步内合成 {}", moves, tile),
+            Goal::ClearDownTo { tiles, moves } => format!("{} 步内把棋盘清理到 {} 块以内", moves, tiles),
+        }
+    }
+}
+
+/// 固定生成序列中的一步，x为列、y为行
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SpawnStep {
+    pub x: usize,
+    pub y: usize,
+    pub value: u32,
+}
+
+/// 每一步移动后新瓷砖的生成方式，保证同一谜题每次游玩都相同
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SpawnSequence {
+    Fixed(Vec<SpawnStep>), // 按顺序生成，序列用完后不再生成
+    Seeded(u64),           // 第 k 步使用 seed + k 作为随机种子
+}
+
+impl SpawnSequence {
+    // 第 step 步（从0开始）移动后生成新瓷砖
+    pub fn spawn(&self, board: &mut GameBoard, step: usize) {
+        match self {
+            SpawnSequence::Fixed(steps) => {
+                if let Some(spawn) = steps.get(step) {
+                    // 指定位置已被占用时，放到按行优先的第一个空位
+                    if !board.place_tile(spawn.x, spawn.y, spawn.value) {
+                        let size = board.size();
+                        let empty = (0..size * size).find(|i| board.get_tiles()[i / size][i % size] == 0);
+                        if let Some(i) = empty {
+                            board.place_tile(i % size, i / size, spawn.value);
+                        }
+                    }
+                }
+            }
+            SpawnSequence::Seeded(seed) => {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(step as u64));
+                board.spawn_tile_with_rng(&mut rng);
+            }
+        }
+    }
+}
+
+/// 谜题难度，由生成器根据搜索结果评定，手工谜题可以不填
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "简单",
+            Difficulty::Medium => "中等",
+            Difficulty::Hard => "困难",
+        }
+    }
+}
+
+/// 一道谜题，起始局面使用局面记谱
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Puzzle {
+    pub id: String,
+    pub name: String,
+    pub position: String,
+    pub spawns: SpawnSequence,
+    pub goal: Goal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub difficulty: Option<Difficulty>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PuzzleStatus {
+    InProgress,
+    Solved,
+    Failed,
+}
+
+/// 正在进行的谜题
+#[derive(Clone)]
+pub struct PuzzleRun {
+    puzzle: Puzzle,
+    board: GameBoard,
+    moves_made: usize,
+    status: PuzzleStatus,
+}
+
+impl PuzzleRun {
+    pub fn new(puzzle: Puzzle) -> Result<Self, String> {
+        let board = GameBoard::from_notation(&puzzle.position)?;
+        Ok(Self {
+            puzzle,
+            board,
+            moves_made: 0,
+            status: PuzzleStatus::InProgress,
+        })
+    }
+
+    pub fn get_puzzle(&self) -> &Puzzle {
+        &self.puzzle
+    }
+
+    pub fn get_board(&self) -> &GameBoard {
+        &self.board
+    }
+
+    pub fn moves_made(&self) -> usize {
+        self.moves_made
+    }
+
+    pub fn moves_left(&self) -> usize {
+        self.puzzle.goal.move_limit().saturating_sub(self.moves_made)
+    }
+
+    pub fn status(&self) -> PuzzleStatus {
+        self.status
+    }
+
+    // 执行一步移动，移动后立即检查目标，未完成才生成新瓷砖
+    // 不改变棋盘的移动不计步数
+    pub fn apply_move(&mut self, direction: Direction) -> PuzzleStatus {
+        if self.status != PuzzleStatus::InProgress {
+            return self.status;
+        }
+        if !self.board.can_move(direction) {
+            return self.status;
+        }
+        self.board.move_tiles(direction);
+
+        self.moves_made += 1;
+        if self.puzzle.goal.is_reached(&self.board) {
+            self.status = PuzzleStatus::Solved;
+            return self.status;
+        }
+
+        self.puzzle.spawns.spawn(&mut self.board, self.moves_made - 1);
+        let locked = !DIRECTIONS.iter().any(|&direction| self.board.can_move(direction));
+        if self.moves_made >= self.puzzle.goal.move_limit() || locked {
+            self.status = PuzzleStatus::Failed;
+        }
+        self.status
+    }
+}
+
+/// 读取随游戏附带的谜题
+pub fn load_bundled_puzzles() -> Result<Vec<Puzzle>, String> {
+    serde_json::from_str(BUNDLED_PUZZLES).map_err(|e| format!("Failed to parse bundled puzzles: {}", e))
+}
+
+/// 玩家的谜题进度，记录已完成谜题的最少步数
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PuzzleProgress {
+    pub solved: BTreeMap<String, usize>,
+}
+
+impl PuzzleProgress {
+    // 进度文件不存在或损坏时视为没有进度
+    pub fn load() -> Self {
+        fs::read_to_string(config::PUZZLE_PROGRESS_FILE)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(config::PUZZLE_PROGRESS_FILE, text).map_err(|e| e.to_string())
+    }
+
+    pub fn is_solved(&self, id: &str) -> bool {
+        self.solved.contains_key(id)
+    }
+
+    // 记录一次完成，只保留最少步数
+    pub fn record(&mut self, id: &str, moves: usize) {
+        let best = self.solved.entry(id.to_string()).or_insert(moves);
+        *best = (*best).min(moves);
+    }
+
+    // 第一道未完成的谜题，全部完成时返回第一道
+    pub fn next_unsolved(&self, puzzles: &[Puzzle]) -> usize {
+        puzzles.iter().position(|puzzle| !self.is_solved(&puzzle.id)).unwrap_or(0)
+    }
+}
+
+/// 从命令行参数读取 --puzzle，"--puzzle=<id>" 指定谜题，单独的 "--puzzle" 表示从下一道未完成的谜题开始
+pub fn puzzle_from_args() -> Option<Option<String>> {
+    for arg in std::env::args().skip(1) {
+        if arg == "--puzzle" {
+            return Some(None);
+        }
+        if let Some(id) = arg.strip_prefix("--puzzle=") {
+            return Some(Some(id.to_string()));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests_puzzle {
+    use super::*;
+
+    // 穷举搜索谜题是否能在步数限制内完成
+    fn solvable(run: &PuzzleRun) -> bool {
+        DIRECTIONS.iter().any(|&direction| {
+            let mut next = run.clone();
+            let before = next.moves_made;
+            match next.apply_move(direction) {
+                PuzzleStatus::Solved => true,
+                PuzzleStatus::InProgress => next.moves_made > before && solvable(&next),
+                PuzzleStatus::Failed => false,
+            }
+        })
+    }
+
+    fn puzzle(position: &str, spawns: SpawnSequence, goal: Goal) -> Puzzle {
+        Puzzle {
+            id: "test".to_string(),
+            name: "test".to_string(),
+            position: position.to_string(),
+            spawns,
+            goal,
+            difficulty: None,
+        }
+    }
+
+    #[test]
+    fn test_bundled_puzzles_are_solvable() {
+        let puzzles = load_bundled_puzzles().unwrap();
+        assert!(!puzzles.is_empty());
+        for puzzle in puzzles {
+            let run = PuzzleRun::new(puzzle.clone()).unwrap();
+            assert!(solvable(&run), "谜题 {} 无法在限制步数内完成", puzzle.id);
+        }
+    }
+
+    #[test]
+    fn test_reach_tile() {
+        let mut run = PuzzleRun::new(puzzle(
+            "4 1100/0000/0000/0000",
+            SpawnSequence::Fixed(vec![]),
+            Goal::ReachTile { tile: 4, moves: 1 },
+        ))
+        .unwrap();
+        // 不改变棋盘的移动不计步
+        assert_eq!(run.apply_move(Direction::Up), PuzzleStatus::InProgress);
+        assert_eq!(run.moves_made(), 0);
+        assert_eq!(run.apply_move(Direction::Left), PuzzleStatus::Solved);
+    }
+
+    #[test]
+    fn test_move_limit_and_fixed_spawns() {
+        let mut run = PuzzleRun::new(puzzle(
+            "4 1000/0000/0000/0000",
+            SpawnSequence::Fixed(vec![SpawnStep { x: 3, y: 0, value: 2 }, SpawnStep { x: 0, y: 0, value: 4 }]),
+            Goal::ReachTile { tile: 64, moves: 2 },
+        ))
+        .unwrap();
+        assert_eq!(run.apply_move(Direction::Down), PuzzleStatus::InProgress);
+        assert_eq!(run.get_board().get_tiles()[0][3], 2, "固定序列应在指定位置生成");
+        // 第二步后步数用完，失败
+        assert_eq!(run.apply_move(Direction::Up), PuzzleStatus::Failed);
+        assert_eq!(run.moves_left(), 0);
+    }
+
+    #[test]
+    fn test_seeded_spawns_are_repeatable() {
+        let spawns = SpawnSequence::Seeded(42);
+        let mut board1 = GameBoard::new();
+        let mut board2 = GameBoard::new();
+        for step in 0..5 {
+            spawns.spawn(&mut board1, step);
+            spawns.spawn(&mut board2, step);
+        }
+        assert_eq!(board1.get_tiles(), board2.get_tiles());
+        assert_eq!(board1.count_tiles(), 5);
+    }
+
+    #[test]
+    fn test_progress_keeps_best() {
+        let mut progress = PuzzleProgress::default();
+        progress.record("p1", 5);
+        progress.record("p1", 7);
+        progress.record("p1", 3);
+        assert_eq!(progress.solved["p1"], 3);
+    }
+}
@@ -0,0 +1,231 @@
+use rand::Rng;
+use std::collections::HashMap;
+
+use crate::puzzle::{Difficulty, Goal, Puzzle, PuzzleRun, PuzzleStatus, SpawnSequence, DIRECTIONS};
+use crate::GameBoard;
+
+/// 穷举搜索的结果
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchStats {
+    pub min_moves: Option<usize>, // 最少需要几步，None 表示限制步数内无解
+    pub solutions: usize,         // 最短解（移动序列）的数量
+    pub branching: f64,           // 搜索过的局面平均有几个合法移动
+}
+
+/// 在谜题引擎上逐层穷举所有移动序列，找出最短解
+// 生成序列只与步数有关，同一层中相同的棋盘后续完全一样，合并后只记录到达该棋盘的路径数
+pub fn search(puzzle: &Puzzle) -> Result<SearchStats, String> {
+    let start = PuzzleRun::new(puzzle.clone())?;
+    let mut frontier: Vec<(PuzzleRun, usize)> = vec![(start, 1)];
+    let mut nodes = 0;
+    let mut legal_moves = 0;
+
+    for depth in 1..=puzzle.goal.move_limit() {
+        let mut next_frontier: HashMap<Vec<Vec<u32>>, (PuzzleRun, usize)> = HashMap::new();
+        let mut solutions = 0;
+        for (run, paths) in &frontier {
+            nodes += 1;
+            for &direction in DIRECTIONS.iter() {
+                if !run.get_board().can_move(direction) {
+                    continue;
+                }
+                legal_moves += 1;
+                let mut next = run.clone();
+                match next.apply_move(direction) {
+                    PuzzleStatus::Solved => solutions += paths,
+                    PuzzleStatus::InProgress => {
+                        let key = next.get_board().get_tiles().clone();
+                        next_frontier.entry(key).or_insert((next, 0)).1 += paths;
+                    }
+                    PuzzleStatus::Failed => {}
+                }
+            }
+        }
+        if solutions > 0 {
+            return Ok(SearchStats {
+                min_moves: Some(depth),
+                solutions,
+                branching: legal_moves as f64 / nodes as f64,
+            });
+        }
+        frontier = next_frontier.into_values().collect();
+        if frontier.is_empty() {
+            break;
+        }
+    }
+
+    Ok(SearchStats {
+        min_moves: None,
+        solutions: 0,
+        branching: if nodes == 0 { 0.0 } else { legal_moves as f64 / nodes as f64 },
+    })
+}
+
+/// 根据分支数和解的唯一性评定难度
+// 用 log2(分支数^步数 / 最短解数量) 估计玩家需要排除多少种走法，解越唯一越难
+pub fn rate(moves: usize, stats: &SearchStats) -> Difficulty {
+    let bits = moves as f64 * stats.branching.max(1.0).log2() - (stats.solutions.max(1) as f64).log2();
+    if bits < 4.0 {
+        Difficulty::Easy
+    } else if bits < 8.0 {
+        Difficulty::Medium
+    } else {
+        Difficulty::Hard
+    }
+}
+
+/// 生成器参数
+pub struct GeneratorConfig {
+    pub size: usize,         // 棋盘边长
+    pub moves: usize,        // 谜题恰好需要的步数
+    pub min_tiles: usize,    // 起始局面瓷砖数量范围
+    pub max_tiles: usize,
+    pub max_exponent: u32,   // 起始瓷砖最大为 2^max_exponent
+    pub attempts: usize,     // 放弃前最多尝试多少个随机局面
+    pub position: Option<String>, // 给定起始局面的记谱时只随机目标和生成序列，边长以局面为准
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            moves: 4,
+            min_tiles: 4,
+            max_tiles: 8,
+            max_exponent: 5,
+            attempts: 2000,
+            position: None,
+        }
+    }
+}
+
+/// 随机搜索起始局面和生成序列，只保留恰好需要 config.moves 步、不能更少的谜题
+pub fn generate<R: Rng>(config: &GeneratorConfig, rng: &mut R, id: &str) -> Option<Puzzle> {
+    for _ in 0..config.attempts {
+        let puzzle = random_candidate(config, rng, id);
+        let stats = match search(&puzzle) {
+            Ok(stats) => stats,
+            Err(_) => continue,
+        };
+        if stats.min_moves == Some(config.moves) {
+            return Some(Puzzle {
+                difficulty: Some(rate(config.moves, &stats)),
+                ..puzzle
+            });
+        }
+    }
+    None
+}
+
+// 随机生成一个候选谜题，尚未验证
+fn random_candidate<R: Rng>(config: &GeneratorConfig, rng: &mut R, id: &str) -> Puzzle {
+    let board = match config.position {
+        Some(ref position) => GameBoard::from_notation(position).unwrap(), // 调用方已检查过
+        None => {
+            let size = config.size;
+            let mut board = GameBoard::with_size(size);
+            let tiles = rng.gen_range(config.min_tiles..=config.max_tiles.min(size * size));
+            while board.count_tiles() < tiles {
+                let exponent = rng.gen_range(1..=config.max_exponent);
+                board.place_tile(rng.gen_range(0..size), rng.gen_range(0..size), 1 << exponent);
+            }
+            board
+        }
+    };
+    let tiles = board.count_tiles();
+
+    // 目标为比当前最大瓷砖大一到两级，或者清理掉一到三块
+    let goal = if rng.gen_bool(0.5) {
+        let max = board.return_score().1;
+        Goal::ReachTile {
+            tile: max << rng.gen_range(1..=2),
+            moves: config.moves,
+        }
+    } else {
+        Goal::ClearDownTo {
+            tiles: tiles.saturating_sub(rng.gen_range(1..=3)).max(1),
+            moves: config.moves,
+        }
+    };
+
+    Puzzle {
+        id: id.to_string(),
+        name: format!("生成谜题 {}", id),
+        position: board.to_notation().unwrap(), // 生成和记谱导入的瓷砖都是2的幂
+        spawns: SpawnSequence::Seeded(rng.gen()),
+        goal,
+        difficulty: None,
+    }
+}
+
+#[cfg(test)]
+mod tests_puzzle_generator {
+    use super::*;
+    use crate::puzzle::SpawnStep;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_search_finds_shortest() {
+        // 2 2 4 8 向左连续合并，最少三步合成16
+        let puzzle = Puzzle {
+            id: "t".to_string(),
+            name: "t".to_string(),
+            position: "4 1123/0000/0000/0000".to_string(),
+            spawns: SpawnSequence::Fixed(vec![SpawnStep { x: 0, y: 3, value: 2 }; 3]),
+            goal: Goal::ReachTile { tile: 16, moves: 5 },
+            difficulty: None,
+        };
+        let stats = search(&puzzle).unwrap();
+        assert_eq!(stats.min_moves, Some(3));
+        assert!(stats.solutions >= 1);
+        assert!(stats.branching > 1.0);
+    }
+
+    #[test]
+    fn test_search_reports_unsolvable() {
+        let puzzle = Puzzle {
+            id: "t".to_string(),
+            name: "t".to_string(),
+            position: "4 1000/0000/0000/0000".to_string(),
+            spawns: SpawnSequence::Fixed(vec![]),
+            goal: Goal::ReachTile { tile: 64, moves: 3 },
+            difficulty: None,
+        };
+        assert_eq!(search(&puzzle).unwrap().min_moves, None);
+    }
+
+    #[test]
+    fn test_generated_puzzles_need_exact_moves() {
+        let mut rng = StdRng::seed_from_u64(2048);
+        let config = GeneratorConfig {
+            moves: 3,
+            ..GeneratorConfig::default()
+        };
+        let puzzle = generate(&config, &mut rng, "g1").expect("应能生成谜题");
+        let stats = search(&puzzle).unwrap();
+        assert_eq!(stats.min_moves, Some(3), "生成的谜题应恰好需要3步");
+        assert!(puzzle.difficulty.is_some());
+    }
+
+    #[test]
+    fn test_generated_puzzle_keeps_given_position() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let config = GeneratorConfig {
+            moves: 3,
+            position: Some("4 1123/0000/0000/0000".to_string()),
+            ..GeneratorConfig::default()
+        };
+        let puzzle = generate(&config, &mut rng, "g1").expect("应能生成谜题");
+        assert_eq!(puzzle.position, "4 1123/0000/0000/0000");
+        assert_eq!(search(&puzzle).unwrap().min_moves, Some(3));
+    }
+
+    #[test]
+    fn test_rate() {
+        let unique = SearchStats { min_moves: Some(6), solutions: 1, branching: 3.0 };
+        let many = SearchStats { min_moves: Some(2), solutions: 8, branching: 3.0 };
+        assert_eq!(rate(6, &unique), Difficulty::Hard);
+        assert_eq!(rate(2, &many), Difficulty::Easy);
+    }
+}
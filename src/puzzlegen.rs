@@ -0,0 +1,77 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+mod config;
+mod game_board;
+mod notation;
+mod puzzle;
+mod puzzle_generator;
+
+pub use crate::game_board::GameBoard;
+use puzzle_generator::{generate, GeneratorConfig};
+
+// 谜题生成器，结果以JSON数组输出到标准输出，可直接追加到 puzzles/puzzles.json
+// 用法: cargo run --bin puzzlegen -- [--count N] [--moves N] [--size N] [--seed N] [--position <记谱>]
+// 给定 --position 时所有谜题都从这个局面开始，只随机目标和生成序列
+fn main() {
+    let mut count = 5;
+    let mut seed: u64 = rand::random();
+    let mut config = GeneratorConfig {
+        position: notation::position_from_args(),
+        ..GeneratorConfig::default()
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut i = 0;
+    while i < args.len() {
+        // --position 已经由 position_from_args 读出
+        let skip = match args[i].as_str() {
+            "--position" => 2,
+            arg if arg.starts_with("--position=") => 1,
+            _ => 0,
+        };
+        if skip > 0 {
+            i += skip;
+            continue;
+        }
+        let value = args.get(i + 1).and_then(|value| value.parse::<u64>().ok());
+        match (args[i].as_str(), value) {
+            ("--count", Some(value)) => count = value as usize,
+            ("--moves", Some(value)) => config.moves = value as usize,
+            ("--size", Some(value)) => config.size = value as usize,
+            ("--seed", Some(value)) => seed = value,
+            _ => {
+                eprintln!("Usage: puzzlegen [--count N] [--moves N] [--size N] [--seed N] [--position <notation>]");
+                std::process::exit(1);
+            }
+        }
+        i += 2;
+    }
+    if let Some(ref position) = config.position {
+        match GameBoard::from_notation(position) {
+            Ok(board) => config.size = board.size(),
+            Err(e) => {
+                eprintln!("Invalid --position: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if config.size < 2 {
+        eprintln!("Board size must be at least 2");
+        std::process::exit(1);
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut puzzles = vec![];
+    for n in 1..=count {
+        let id = format!("g{}-{}", seed, n);
+        match generate(&config, &mut rng, &id) {
+            Some(puzzle) => {
+                eprintln!("Generated {} ({:?})", puzzle.id, puzzle.difficulty);
+                puzzles.push(puzzle);
+            }
+            None => eprintln!("Failed to generate puzzle {} after {} attempts", id, config.attempts),
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&puzzles).unwrap());
+}
@@ -1,5 +1,6 @@
 use crossterm::{
-    terminal::{enable_raw_mode, EnterAlternateScreen},
+    event::{self, Event, KeyCode, KeyEventKind},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 
@@ -8,21 +9,29 @@ use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Rect},
     style::{Color, Modifier, Style},
+    text::{Span, Spans},
     widgets::{Block, Borders, Paragraph},
     Frame, Terminal,
 };
 
 mod bridge;
+mod config;
+mod dc;
+mod editor;
 mod game;
 mod game_board;
 mod game_controller;
 mod io_manager;
+mod notation;
+mod puzzle;
 
 pub use crate::bridge::Bridge;
 pub use crate::game_board::GameBoard;
 pub use crate::game_controller::GameController;
 pub use crate::io_manager::IOManager;
+use editor::{run_editor, EditorOutcome};
 use game_board::Direction;
+use puzzle::{PuzzleProgress, PuzzleRun, PuzzleStatus};
 
 fn draw_board<B: Backend>(frame: &mut Frame<B>, board: &Vec<Vec<u32>>) {
     let size = frame.size();
@@ -115,12 +124,109 @@ fn get_bg_color(n: u32) -> Color {
     }
 }
 
+/// 谜题界面，在棋盘上方显示目标和剩余步数
+fn draw_puzzle<B: Backend>(frame: &mut Frame<B>, run: &PuzzleRun, progress: &PuzzleProgress, index: usize, total: usize) {
+    draw_board(frame, run.get_board().get_tiles());
+
+    let puzzle = run.get_puzzle();
+    let (status, color) = match run.status() {
+        PuzzleStatus::InProgress => (format!("剩余步数: {}", run.moves_left()), Color::White),
+        PuzzleStatus::Solved => (
+            format!("成功！用了 {} 步    N - 下一题  R - 重试  Q - 退出", run.moves_made()),
+            Color::Green,
+        ),
+        PuzzleStatus::Failed => ("失败    R - 重试  N - 下一题  Q - 退出".to_string(), Color::Red),
+    };
+    let solved = if progress.is_solved(&puzzle.id) { " (已完成)" } else { "" };
+    let difficulty = match puzzle.difficulty {
+        Some(difficulty) => format!(" [{}]", difficulty.describe()),
+        None => String::new(),
+    };
+    let text = vec![
+        Spans::from(Span::styled(
+            format!("谜题 {}/{}: {}{}{}    目标: {}", index + 1, total, puzzle.name, difficulty, solved, puzzle.goal.describe()),
+            Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+        )),
+        Spans::from(Span::styled(status, Style::default().fg(color))),
+    ];
+    let size = frame.size();
+    let area = Rect::new(size.x + 2, size.y + 1, size.width.saturating_sub(4), 2);
+    frame.render_widget(Paragraph::new(text).alignment(Alignment::Center), area);
+}
+
+/// 谜题模式，每步移动后检查目标，结束后可以重试、进入下一题或退出
+fn play_puzzles<B: Backend>(
+    terminal: &mut Terminal<B>,
+    io_manager: &mut IOManager,
+    puzzle_id: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let puzzles = puzzle::load_bundled_puzzles()?;
+    let mut progress = PuzzleProgress::load();
+    let mut index = match puzzle_id {
+        Some(id) => puzzles
+            .iter()
+            .position(|puzzle| puzzle.id == id)
+            .ok_or(format!("Unknown puzzle: {}", id))?,
+        None => progress.next_unsolved(&puzzles),
+    };
+
+    loop {
+        let mut run = PuzzleRun::new(puzzles[index].clone())?;
+        terminal.draw(|f| draw_puzzle(f, &run, &progress, index, puzzles.len()))?;
+
+        while run.status() == PuzzleStatus::InProgress {
+            if let Some(action) = io_manager.read_input(1) {
+                match action {
+                    Direction::None => continue,
+                    Direction::Quit => return Ok(()),
+                    _ => {
+                        if run.apply_move(action) == PuzzleStatus::Solved {
+                            progress.record(&run.get_puzzle().id, run.moves_made());
+                            progress.save()?;
+                        }
+                        terminal.draw(|f| draw_puzzle(f, &run, &progress, index, puzzles.len()))?;
+                    }
+                }
+            }
+        }
+
+        // 谜题结束，等待玩家选择
+        loop {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('r') => break,
+                    KeyCode::Char('n') => {
+                        index = (index + 1) % puzzles.len();
+                        break;
+                    }
+                    KeyCode::Char('q') => return Ok(()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 期盼逻辑
     // 允许 10ms 后续这种参数放config
     let mut io_manager = IOManager::new(10);
-    let mut game_board = GameBoard::new();
-    game_board.spawn_tile();
+    // 带 --editor 启动时先进入棋盘编辑器
+    let editor_mode = std::env::args().any(|arg| arg == "--editor");
+    // 若指定了 --position 则从该局面开始，否则随机开局，编辑器从空棋盘开始
+    let mut game_board = match notation::position_from_args() {
+        Some(position) => GameBoard::from_notation(&position)?,
+        None => {
+            let mut board = GameBoard::new();
+            if !editor_mode {
+                board.spawn_tile();
+            }
+            board
+        }
+    };
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -129,6 +235,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
+    // 带 --puzzle 启动时进入谜题模式
+    if let Some(puzzle_id) = puzzle::puzzle_from_args() {
+        return play_puzzles(&mut terminal, &mut io_manager, puzzle_id);
+    }
+
+    if editor_mode {
+        match run_editor(&mut terminal, game_board)? {
+            EditorOutcome::Play(board) => game_board = board,
+            EditorOutcome::Quit(exported) => {
+                disable_raw_mode()?;
+                terminal.backend_mut().execute(LeaveAlternateScreen)?;
+                // 退出后打印导出的记谱，方便复制
+                if let Some(notation) = exported {
+                    println!("{}", notation);
+                }
+                return Ok(());
+            }
+        }
+    }
+
     // 等待用户按任意键退出
     loop {
         if let Some(action) = io_manager.read_input(1) {
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+use crate::connection::PendingClient;
+
+/// 凭会话令牌重连的客户端，交给对局时附带玩家下标
+pub type Reconnect = (usize, PendingClient);
+
+/// 进行中对局的会话令牌，重连时据此找到原来的对局
+// 锁只在查表时短暂持有，不会跨越 await
+pub struct SessionRegistry {
+    sessions: Mutex<HashMap<String, (usize, mpsc::Sender<Reconnect>)>>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 为对局中的一名玩家生成令牌，重连的客户端会发到 reconnect
+    pub fn register(&self, player: usize, reconnect: mpsc::Sender<Reconnect>) -> String {
+        let token = format!("{:032x}", rand::random::<u128>());
+        self.sessions.lock().unwrap().insert(token.clone(), (player, reconnect));
+        token
+    }
+
+    // 令牌对应的玩家下标和对局
+    pub fn find(&self, token: &str) -> Option<(usize, mpsc::Sender<Reconnect>)> {
+        self.sessions.lock().unwrap().get(token).cloned()
+    }
+
+    // 对局结束后令牌作废
+    pub fn remove(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Instant;
+
+mod game_board;
+mod notation;
+mod solver;
+
+pub use crate::game_board::GameBoard;
+use solver::Solver;
+
+// 小棋盘精确求解，局面使用记谱给出
+// 用法: cargo run --release --bin solve -- --position "3 100/000/010" [--depth N]
+// 不限深度时从3x3开局算到底约有五千万个局面，需要几分钟和近2GB内存
+fn main() {
+    let position = match notation::position_from_args() {
+        Some(position) => position,
+        None => {
+            eprintln!("Usage: solve --position <notation> [--depth N]");
+            std::process::exit(1);
+        }
+    };
+    let board = match GameBoard::from_notation(&position) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("Invalid --position: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let args: Vec<String> = std::env::args().collect();
+    let depth = args
+        .iter()
+        .position(|arg| arg == "--depth")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|depth| depth.parse().ok());
+    let mut solver = match depth {
+        Some(depth) => Solver::with_depth_limit(depth),
+        None => Solver::new(),
+    };
+
+    let start = Instant::now();
+    match solver.solve(board.get_tiles()) {
+        Ok(solution) => {
+            board.print_state();
+            println!("Expected score:   {:.3} (sum of merged tiles)", solution.expected_score);
+            println!("Certain max tile: {}", solution.certain_max_tile);
+            println!("Best move:        {:?}", solution.best_move);
+            println!("Positions:        {}", solver.table_size());
+            println!("Time:             {:?}", start.elapsed());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
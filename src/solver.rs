@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use crate::game_board::Direction;
+
+// 与 GameBoard::spawn_tile 保持一致：gen_range(0..9) 为0时生成4，其余生成2
+const SPAWN_FOUR_PROBABILITY: f64 = 1.0 / 9.0;
+const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+// 打包棋盘时每格占4位，存瓷砖的指数，空格为0
+const BITS_PER_TILE: usize = 4;
+const TILE_MASK: u64 = (1 << BITS_PER_TILE) - 1;
+// 合并后的指数也要放得下，瓷砖最大只能到 2^14
+const MAX_EXPONENT: u32 = TILE_MASK as u32 - 1;
+// 置换表的键：低40位是棋盘，往上8位是边长，最高16位是深度
+const SIZE_SHIFT: usize = 40;
+const DEPTH_SHIFT: usize = 48;
+// 不限深度时使用的深度值；3x3棋盘上的一局远走不到这么多步，更深的限制也按不限深度处理
+const UNLIMITED: usize = u16::MAX as usize;
+
+/// 求解结果
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Solution {
+    pub expected_score: f64,     // 最优策略下之后所有合并得分的期望，与 GameBoard::return_score 的瓷砖总和不同，只计合并出的瓷砖
+    pub certain_max_tile: u32,   // 无论新瓷砖如何生成都一定能达到的最大瓷砖
+    pub best_move: Option<Direction>, // 期望得分最高的走法，无路可走时为 None
+}
+
+/// 小棋盘精确求解器，expectimax 加置换表
+// 棋盘打包成 u64，第 i 行第 j 列在第 i*size+j 个4位上，移动和对称变换都直接在整数上做
+// 期望得分和保证能达到的最大瓷砖在同一次搜索中求出，置换表以边长、规范化后的棋盘和深度为键，
+// 八种对称（旋转、翻转）的棋盘共用同一条记录
+pub struct Solver {
+    depth_limit: Option<usize>, // 只向后看若干步，None 表示一直算到游戏结束
+    size: usize,
+    rows: Vec<(u64, u32)>, // 以一行打包后的值为下标，向左移动后的行和合并得分，按边长预先算好
+    table: HashMap<u64, (f64, u32), BuildHasherDefault<PackedHasher>>, // 期望得分和保证能达到的最大瓷砖的指数
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self {
+            depth_limit: None,
+            size: 0,
+            rows: vec![],
+            table: HashMap::default(),
+        }
+    }
+
+    // 限制搜索深度，只想知道接下来几步时使用
+    pub fn with_depth_limit(depth: usize) -> Self {
+        Self {
+            depth_limit: Some(depth),
+            ..Self::new()
+        }
+    }
+
+    // 置换表中的局面数
+    pub fn table_size(&self) -> usize {
+        self.table.len()
+    }
+
+    // 求解轮到玩家移动的局面（新瓷砖已经生成）
+    pub fn solve(&mut self, tiles: &[Vec<u32>]) -> Result<Solution, String> {
+        let size = tiles.len();
+        if size != 2 && size != 3 {
+            return Err(format!("Solver only supports 2x2 and 3x3 boards, got {}x{}", size, size));
+        }
+        if tiles.iter().flatten().any(|&tile| tile != 0 && exponent(tile) > MAX_EXPONENT) {
+            return Err("Tile too large for solver".to_string());
+        }
+        if size != self.size {
+            self.size = size;
+            self.rows = row_table(size);
+        }
+
+        let board = pack(tiles);
+        let depth = self.depth_limit.unwrap_or(UNLIMITED).min(UNLIMITED);
+        let mut best_move = None;
+        let mut expected_score = 0.0;
+        let mut certain = max_exponent(board, size);
+        if depth > 0 {
+            for &direction in DIRECTIONS.iter() {
+                if let Some((next, gain)) = self.slide(board, direction) {
+                    let (value, worst) = self.chance_value(next, next_depth(depth));
+                    if best_move.is_none() || gain + value > expected_score {
+                        best_move = Some(direction);
+                        expected_score = gain + value;
+                    }
+                    certain = certain.max(worst);
+                }
+            }
+        }
+        Ok(Solution {
+            expected_score,
+            certain_max_tile: if certain == 0 { 0 } else { 1 << certain },
+            best_move,
+        })
+    }
+
+    // 玩家回合：期望得分取最好的走法，保证能达到的最大瓷砖也取最好的走法
+    fn max_value(&mut self, board: u64, depth: usize) -> (f64, u32) {
+        let current = max_exponent(board, self.size);
+        if depth == 0 {
+            return (0.0, current);
+        }
+        let key = canonical(board, self.size) | (self.size as u64) << SIZE_SHIFT | (depth as u64) << DEPTH_SHIFT;
+        if let Some(&value) = self.table.get(&key) {
+            return value;
+        }
+        let mut best = (0.0, current);
+        for &direction in DIRECTIONS.iter() {
+            if let Some((next, gain)) = self.slide(board, direction) {
+                let (value, worst) = self.chance_value(next, next_depth(depth));
+                best = (f64::max(best.0, gain + value), best.1.max(worst));
+            }
+        }
+        self.table.insert(key, best);
+        best
+    }
+
+    // 生成回合：期望得分对所有空位和2/4两种数值求期望，保证能达到的最大瓷砖取最坏的情况
+    // 移动后的棋盘总有空位：没有合并时空位数不变，有合并时空出格子
+    fn chance_value(&mut self, board: u64, depth: usize) -> (f64, u32) {
+        let empty = empty_cells(board, self.size);
+        let mut total = 0.0;
+        let mut worst = u32::MAX;
+        for &cell in &empty {
+            for (exponent, probability) in [(1, 1.0 - SPAWN_FOUR_PROBABILITY), (2, SPAWN_FOUR_PROBABILITY)] {
+                let (value, certain) = self.max_value(set_cell(board, cell, exponent), depth);
+                total += probability * value;
+                worst = worst.min(certain);
+            }
+        }
+        (total / empty.len() as f64, worst)
+    }
+
+    // 执行一次移动，棋盘未改变时返回 None，否则返回新棋盘和合并得分
+    // 上下移动先转置，按左右移动处理后再转置回来；向右移动时每行先反转
+    fn slide(&self, board: u64, direction: Direction) -> Option<(u64, f64)> {
+        let size = self.size;
+        let vertical = matches!(direction, Direction::Up | Direction::Down);
+        let reversed = matches!(direction, Direction::Right | Direction::Down);
+        let mut rows = if vertical { transpose(board, size) } else { board };
+        if reversed {
+            rows = mirror(rows, size);
+        }
+        let row_bits = BITS_PER_TILE * size;
+        let mut moved = 0;
+        let mut gain = 0;
+        for i in 0..size {
+            let (row, score) = self.rows[((rows >> (row_bits * i)) & ((1 << row_bits) - 1)) as usize];
+            moved |= row << (row_bits * i);
+            gain += score;
+        }
+        if reversed {
+            moved = mirror(moved, size);
+        }
+        if vertical {
+            moved = transpose(moved, size);
+        }
+        (moved != board).then_some((moved, gain as f64))
+    }
+}
+
+// 置换表的键已经是打包好的整数，乘一个奇数常数打散即可，比默认的 SipHash 快得多
+#[derive(Default)]
+struct PackedHasher(u64);
+
+impl Hasher for PackedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(self.0 ^ byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        let mixed = n.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        self.0 = mixed ^ (mixed >> 32);
+    }
+}
+
+// 不限深度时深度保持不变，置换表的键才能在不同层之间共用
+fn next_depth(depth: usize) -> usize {
+    if depth == UNLIMITED {
+        UNLIMITED
+    } else {
+        depth.saturating_sub(1)
+    }
+}
+
+// 边长为 size 的所有行向左移动的结果：非空瓷砖靠左，相邻相同的两块合并一次，得分为合并出的瓷砖
+fn row_table(size: usize) -> Vec<(u64, u32)> {
+    (0..1u64 << (BITS_PER_TILE * size))
+        .map(|row| {
+            let exponents: Vec<u64> = (0..size).map(|j| (row >> (BITS_PER_TILE * j)) & TILE_MASK).filter(|&e| e != 0).collect();
+            let mut merged = vec![];
+            let mut score = 0;
+            let mut k = 0;
+            while k < exponents.len() {
+                if k + 1 < exponents.len() && exponents[k] == exponents[k + 1] {
+                    merged.push(exponents[k] + 1);
+                    score += 1 << (exponents[k] + 1);
+                    k += 2;
+                } else {
+                    merged.push(exponents[k]);
+                    k += 1;
+                }
+            }
+            let packed = merged.iter().enumerate().fold(0, |packed, (j, &e)| packed | (e << (BITS_PER_TILE * j)));
+            (packed, score)
+        })
+        .collect()
+}
+
+fn cell(board: u64, index: usize) -> u64 {
+    (board >> (BITS_PER_TILE * index)) & TILE_MASK
+}
+
+fn set_cell(board: u64, index: usize, exponent: u64) -> u64 {
+    board & !(TILE_MASK << (BITS_PER_TILE * index)) | (exponent << (BITS_PER_TILE * index))
+}
+
+// 沿主对角线翻转
+fn transpose(board: u64, size: usize) -> u64 {
+    let mut result = 0;
+    for i in 0..size {
+        for j in 0..size {
+            result = set_cell(result, j * size + i, cell(board, i * size + j));
+        }
+    }
+    result
+}
+
+// 每行左右反转
+fn mirror(board: u64, size: usize) -> u64 {
+    let mut result = 0;
+    for i in 0..size {
+        for j in 0..size {
+            result = set_cell(result, i * size + size - 1 - j, cell(board, i * size + j));
+        }
+    }
+    result
+}
+
+// 对称的棋盘之后的走势也对称，取八种变换中最小的整数作为置换表的键；
+// 不同边长的棋盘可能打包成同一个整数，键里还要带上边长
+fn canonical(board: u64, size: usize) -> u64 {
+    let mut rotated = board;
+    let mut best = u64::MAX;
+    for _ in 0..4 {
+        rotated = mirror(transpose(rotated, size), size); // 顺时针旋转90度
+        best = best.min(rotated).min(transpose(rotated, size));
+    }
+    best
+}
+
+fn exponent(tile: u32) -> u32 {
+    tile.trailing_zeros()
+}
+
+fn max_exponent(board: u64, size: usize) -> u32 {
+    (0..size * size).map(|index| cell(board, index) as u32).max().unwrap_or(0)
+}
+
+fn empty_cells(board: u64, size: usize) -> Vec<usize> {
+    (0..size * size).filter(|&index| cell(board, index) == 0).collect()
+}
+
+// 棋盘打包成整数，每格4位存指数
+fn pack(tiles: &[Vec<u32>]) -> u64 {
+    tiles.iter().flatten().enumerate().fold(0, |board, (index, &tile)| {
+        let exponent = if tile == 0 { 0 } else { exponent(tile) as u64 };
+        set_cell(board, index, exponent)
+    })
+}
+
+#[cfg(test)]
+mod tests_solver {
+    use super::*;
+    use crate::GameBoard;
+
+    #[test]
+    fn test_locked_board() {
+        let mut solver = Solver::new();
+        let solution = solver.solve(&[vec![2, 4], vec![8, 16]]).unwrap();
+        assert_eq!(solution.expected_score, 0.0);
+        assert_eq!(solution.certain_max_tile, 16);
+        assert_eq!(solution.best_move, None);
+    }
+
+    #[test]
+    fn test_one_move_lookahead() {
+        // 只看一步时，向左或向右合并两个2得4分
+        let mut solver = Solver::with_depth_limit(1);
+        let solution = solver.solve(&[vec![2, 2], vec![0, 0]]).unwrap();
+        assert_eq!(solution.expected_score, 4.0);
+        assert_eq!(solution.certain_max_tile, 4);
+        assert!(matches!(solution.best_move, Some(Direction::Left) | Some(Direction::Right)));
+    }
+
+    #[test]
+    fn test_symmetric_boards_share_value() {
+        let mut solver = Solver::new();
+        let a = solver.solve(&[vec![2, 0], vec![0, 4]]).unwrap();
+        let b = solver.solve(&[vec![0, 2], vec![4, 0]]).unwrap();
+        assert!((a.expected_score - b.expected_score).abs() < 1e-9);
+        assert_eq!(a.certain_max_tile, b.certain_max_tile);
+        assert!(a.expected_score > 0.0);
+        assert!(a.certain_max_tile >= 8, "2x2 至少能保证合成8");
+        assert_eq!(
+            canonical(pack(&[vec![2, 0], vec![0, 4]]), 2),
+            canonical(pack(&[vec![4, 0], vec![0, 2]]), 2)
+        );
+    }
+
+    #[test]
+    fn test_small_3x3_lookahead() {
+        let mut solver = Solver::with_depth_limit(3);
+        let solution = solver.solve(&[vec![2, 2, 0], vec![0, 0, 0], vec![0, 0, 4]]).unwrap();
+        assert!(solution.expected_score >= 4.0);
+        assert!(solution.best_move.is_some());
+    }
+
+    #[test]
+    fn test_sizes_do_not_share_table() {
+        // 2x2 和 3x3 的棋盘打包后可能是同一个整数，同一个求解器先后求解两种边长也互不影响
+        let small = [vec![0, 0], vec![2, 4]];
+        let large = [vec![0, 0, 0], vec![0, 0, 0], vec![0, 2, 4]];
+        let mut solver = Solver::with_depth_limit(4);
+        solver.solve(&small).unwrap();
+        let shared = solver.solve(&large).unwrap();
+        assert_eq!(shared, Solver::with_depth_limit(4).solve(&large).unwrap());
+    }
+
+    #[test]
+    fn test_packed_moves_match_engine() {
+        // 打包棋盘上的移动与 GameBoard 的结果一致，得分为合并出的瓷砖之和
+        let mut solver = Solver::new();
+        solver.solve(&vec![vec![0; 3]; 3]).unwrap();
+        let boards = [
+            vec![vec![2, 2, 4], vec![0, 4, 4], vec![8, 0, 8]],
+            vec![vec![2, 2, 2], vec![4, 0, 2], vec![2, 4, 8]],
+            vec![vec![0, 0, 2], vec![16, 16, 16], vec![2, 0, 2]],
+        ];
+        for tiles in boards {
+            for &direction in DIRECTIONS.iter() {
+                let mut board = GameBoard::from_tiles(tiles.clone());
+                board.move_tiles(direction);
+                let expected = (board.get_tiles() != &tiles).then(|| pack(board.get_tiles()));
+                assert_eq!(solver.slide(pack(&tiles), direction).map(|(next, _)| next), expected, "{:?} {:?}", tiles, direction);
+            }
+        }
+        assert_eq!(solver.slide(pack(&[vec![2, 2, 4], vec![0; 3], vec![0; 3]]), Direction::Left).map(|(_, gain)| gain), Some(4.0));
+        assert_eq!(solver.slide(pack(&[vec![4, 4, 4], vec![0; 3], vec![2, 0, 2]]), Direction::Right).map(|(_, gain)| gain), Some(12.0));
+    }
+
+    #[test]
+    fn test_unlimited_3x3_solve() {
+        // 不限深度一直算到游戏结束：大瓷砖互相合并不了，小瓷砖只能在右下角的四个空格里周转，很快就会走投无路
+        let locked = GameBoard::from_notation("3 dcb/980/000").unwrap();
+        let mut solver = Solver::new();
+        let solution = solver.solve(locked.get_tiles()).unwrap();
+        assert!(solution.best_move.is_some());
+        assert!(solution.expected_score > 0.0);
+        assert_eq!(solution.certain_max_tile, 8192);
+        // 这里任何一局都走不到一千步，限制深度得到同样的结果
+        let limited = Solver::with_depth_limit(1000).solve(locked.get_tiles()).unwrap();
+        assert!((limited.expected_score - solution.expected_score).abs() < 1e-9);
+        assert_eq!(limited.certain_max_tile, solution.certain_max_tile);
+    }
+
+    #[test]
+    fn test_unsupported_size() {
+        assert!(Solver::new().solve(&vec![vec![0; 4]; 4]).is_err());
+    }
+}
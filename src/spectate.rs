@@ -0,0 +1,170 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::config;
+use crate::connection::PendingClient;
+use crate::protocol::{MatchInfo, Message, SpectateRequest};
+
+/// 观众的连接和观战延迟，经管道交给对局
+pub type Spectator = (PendingClient, Duration);
+
+// 一局进行中的对局，新观众经 spectators 交给对局任务
+struct LiveMatch {
+    info: MatchInfo,
+    spectators: mpsc::Sender<Spectator>,
+}
+
+/// 所有进行中的对局，观众据此找到要观看的对局
+// 锁只在查表时短暂持有，不会跨越 await
+pub struct LiveMatches {
+    matches: Mutex<(HashMap<u64, LiveMatch>, u64)>, // 对局和下一个对局编号
+}
+
+impl LiveMatches {
+    pub fn new() -> Self {
+        Self {
+            matches: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    // 登记一局对局，返回对局信息
+    pub fn register(&self, title: &str, spectators: mpsc::Sender<Spectator>) -> MatchInfo {
+        let mut matches = self.matches.lock().unwrap();
+        let id = matches.1;
+        matches.1 += 1;
+        let info = MatchInfo { id, title: title.to_string() };
+        matches.0.insert(id, LiveMatch { info: info.clone(), spectators });
+        info
+    }
+
+    // 对局结束后不再接受观众
+    pub fn remove(&self, id: u64) {
+        self.matches.lock().unwrap().0.remove(&id);
+    }
+
+    // 进行中的对局，先开始的在前
+    pub fn list(&self) -> Vec<MatchInfo> {
+        let mut matches: Vec<MatchInfo> = self.matches.lock().unwrap().0.values().map(|live| live.info.clone()).collect();
+        matches.sort_by_key(|info| info.id);
+        matches
+    }
+
+    // 把观众交给对局，失败时把客户端连同原因交还
+    pub fn watch(&self, request: SpectateRequest, client: PendingClient) -> Result<(), (PendingClient, String)> {
+        let matches = self.matches.lock().unwrap();
+        let live = match matches.0.get(&request.match_id) {
+            Some(live) => live,
+            None => return Err((client, format!("对局 {} 不存在或已结束", request.match_id))),
+        };
+        let delay = Duration::from_secs(request.delay_secs.min(config::SPECTATOR_MAX_DELAY_SECS) as u64);
+        live.spectators.try_send((client, delay)).map_err(|e| match e {
+            TrySendError::Full((client, _)) => (client, "观战人数过多，请稍后再试".to_string()),
+            TrySendError::Closed((client, _)) => (client, "对局已结束".to_string()),
+        })
+    }
+}
+
+/// 发给一名观众的一端
+pub enum SpectatorSender {
+    Live(mpsc::Sender<Message>),             // 没有延迟，直接交给连接
+    Delayed(mpsc::UnboundedSender<Message>), // 经 delayed 延迟转发
+}
+
+impl SpectatorSender {
+    // 观众已断开时返回 false；直接转发时连接的队列满了只丢掉这一条，和发给玩家一样
+    pub fn send(&self, message: Message) -> bool {
+        match self {
+            SpectatorSender::Live(sender) => !matches!(sender.try_send(message), Err(TrySendError::Closed(_))),
+            SpectatorSender::Delayed(sender) => sender.send(message).is_ok(),
+        }
+    }
+}
+
+/// 延迟转发：发到返回的发送端的消息，过 delay 之后才依次转交给 sender
+// 延迟期间的消息都要留着，队列不设上限，最多积攒 delay 这段时间内的消息；
+// 对局结束、发送端全部丢弃后，仍会把已收到的消息按时发完
+pub fn delayed(sender: mpsc::Sender<Message>, delay: Duration) -> mpsc::UnboundedSender<Message> {
+    let (delayed_tx, mut delayed_rx) = mpsc::unbounded_channel::<Message>();
+    tokio::spawn(async move {
+        let mut pending: VecDeque<(Instant, Message)> = VecDeque::new();
+        let mut open = true;
+        while open || !pending.is_empty() {
+            let next = pending.front().map(|(at, _)| *at);
+            tokio::select! {
+                message = delayed_rx.recv(), if open => match message {
+                    Some(message) => pending.push_back((Instant::now() + delay, message)),
+                    None => open = false,
+                },
+                _ = sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let (_, message) = pending.pop_front().unwrap();
+                    if sender.send(message).await.is_err() {
+                        break; // 观众已断开
+                    }
+                }
+            }
+        }
+    });
+    delayed_tx
+}
+
+#[cfg(test)]
+mod tests_spectate {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_delayed_keeps_order_and_delay() {
+        let (tx, mut rx) = mpsc::channel(8);
+        let delay = Duration::from_millis(200);
+        let delayed_tx = delayed(tx, delay);
+        let start = Instant::now();
+        for sequence in 0..3 {
+            delayed_tx.send(Message::Ping(sequence)).unwrap();
+        }
+        drop(delayed_tx);
+
+        // 发送端丢弃后仍按顺序发完
+        for sequence in 0..3 {
+            match rx.recv().await {
+                Some(Message::Ping(received)) => assert_eq!(received, sequence),
+                other => panic!("应收到 Ping，实际 {:?}", other),
+            }
+            assert!(start.elapsed() >= delay);
+        }
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_keeps_burst() {
+        // 一口气发来的消息比连接的队列还多，延迟期间一条也不丢，最后的对局结果也能送到
+        let (tx, mut rx) = mpsc::channel(8);
+        let delayed_tx = delayed(tx, Duration::from_millis(100));
+        for sequence in 0..100 {
+            delayed_tx.send(Message::Ping(sequence)).unwrap();
+        }
+        delayed_tx.send(Message::Forfeit).unwrap();
+        drop(delayed_tx);
+
+        for sequence in 0..100 {
+            match rx.recv().await {
+                Some(Message::Ping(received)) => assert_eq!(received, sequence),
+                other => panic!("应收到 Ping，实际 {:?}", other),
+            }
+        }
+        assert!(matches!(rx.recv().await, Some(Message::Forfeit)));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_and_remove() {
+        let live = LiveMatches::new();
+        let (tx, _rx) = mpsc::channel(1);
+        let first = live.register("甲", tx.clone());
+        let second = live.register("乙", tx);
+        assert_eq!(live.list(), vec![first.clone(), second.clone()]);
+        live.remove(first.id);
+        assert_eq!(live.list(), vec![second]);
+    }
+}